@@ -4,23 +4,7 @@
 CREATE DATABASE IF NOT EXISTS timekeeper;
 USE timekeeper;
 
--- Create the whitelist table.
-CREATE TABLE IF NOT EXISTS whitelisted_addresses (
-  address VARCHAR(255),
-  avatar VARCHAR(255),
-  referral_code CHAR(32),
-  referred_from VARCHAR(32),
-  PRIMARY KEY (address),
-  UNIQUE INDEX avatar_idx(avatar),
-  UNIQUE INDEX referral_code_idx(referral_code)
-);
-
-CREATE TABLE IF NOT EXISTS referrals(
-  refkey CHAR(128) NOT NULL,
-  refvalue CHAR(32),
-  PRIMARY KEY (refkey),
-  INDEX ref_idx (refvalue)
-);
+-- The tables are created by the migrations embedded in the service, see the migrate subcommand.
 
 -- Create the user.
 -- 1. Remove '%' user
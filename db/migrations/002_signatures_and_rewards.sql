@@ -0,0 +1,49 @@
+-- The schema of 1.2.0.
+
+CREATE TABLE IF NOT EXISTS seen_signatures(
+  signature CHAR(132) NOT NULL,
+  epoch BIGINT UNSIGNED NOT NULL,
+  PRIMARY KEY (signature),
+  INDEX epoch_idx (epoch)
+);
+
+CREATE TABLE IF NOT EXISTS user_objective_nonces(
+  chain_id BIGINT UNSIGNED NOT NULL,
+  solver CHAR(42) NOT NULL,
+  next_nonce BIGINT UNSIGNED NOT NULL,
+  PRIMARY KEY (chain_id, solver)
+);
+
+-- The solver transaction in flight on each chain, JSON encoded with all its fee replacements.
+CREATE TABLE IF NOT EXISTS pending_transactions(
+  chain_id BIGINT UNSIGNED NOT NULL,
+  tx TEXT NOT NULL,
+  PRIMARY KEY (chain_id)
+);
+
+-- Rewards of every tick on every chain, amounts in wei.
+-- Backlog rewards keep the tick they were earned in, settled_in is the tick that sends them.
+CREATE TABLE IF NOT EXISTS reward_events(
+  id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+  chain_id BIGINT UNSIGNED NOT NULL,
+  mean_time BIGINT UNSIGNED NOT NULL,
+  chronicles_md5 CHAR(32) NOT NULL,
+  receiver CHAR(42) NOT NULL,
+  amount DECIMAL(65, 0) NOT NULL,
+  referral_level INT UNSIGNED NOT NULL,
+  tx_hash CHAR(66),
+  status VARCHAR(16) NOT NULL,
+  reason TEXT,
+  settled_in CHAR(32),
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (id),
+  INDEX receiver_idx (receiver, id),
+  INDEX tick_idx (chain_id, chronicles_md5, status),
+  INDEX backlog_idx (chain_id, status, receiver),
+  INDEX settled_idx (chain_id, settled_in)
+);
+
+-- When referred_from was first set, it can't be changed after the grace period.
+-- The column is added before the statements if it's missing, MySQL has no ADD COLUMN IF NOT EXISTS.
+UPDATE whitelisted_addresses SET referred_at = CURRENT_TIMESTAMP
+  WHERE referred_from != '' AND referred_at IS NULL;
@@ -0,0 +1,3 @@
+-- Whitelisted addresses are lowercase full hex, the service canonicalizes the existing rows before.
+ALTER TABLE whitelisted_addresses
+  ADD CONSTRAINT address_canonical_chk CHECK (REGEXP_LIKE(address, '^0x[0-9a-f]{40}$', 'c'));
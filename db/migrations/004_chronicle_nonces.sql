@@ -0,0 +1,6 @@
+-- The last nonce of the EIP-712 chronicles of each time keeper, a chronicle must come with a greater one.
+CREATE TABLE IF NOT EXISTS chronicle_nonces(
+  time_keeper CHAR(42) NOT NULL,
+  nonce BIGINT UNSIGNED NOT NULL,
+  PRIMARY KEY (time_keeper)
+);
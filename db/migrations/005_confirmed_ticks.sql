@@ -0,0 +1,7 @@
+-- The last confirmed tick on each chain with its chronicles JSON encoded, `settle-backlog` sends them with the backlog.
+CREATE TABLE IF NOT EXISTS confirmed_ticks(
+  chain_id BIGINT UNSIGNED NOT NULL,
+  mean_time BIGINT UNSIGNED NOT NULL,
+  chronicles MEDIUMTEXT NOT NULL,
+  PRIMARY KEY (chain_id)
+);
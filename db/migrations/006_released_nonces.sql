@@ -0,0 +1,7 @@
+-- User objective nonces released by failed transactions, they are handed out again before new ones.
+CREATE TABLE IF NOT EXISTS released_user_objective_nonces(
+  chain_id BIGINT UNSIGNED NOT NULL,
+  solver CHAR(42) NOT NULL,
+  nonce BIGINT UNSIGNED NOT NULL,
+  PRIMARY KEY (chain_id, solver, nonce)
+);
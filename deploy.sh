@@ -25,10 +25,14 @@ do
         PRIMARY_CHAIN_ID=21363
         PRIMARY_HTTP_CHAIN_URL="https://service.lestnet.org"
         PRIMARY_BLOCK_TIME_ADDRESS="0xdD1B4D9337D0a8Ef2F133a39cC93EF85261b4A80"
+        PRIMARY_CALL_BREAKER_ADDRESS="${PRIMARY_CALL_BREAKER_ADDRESS:?Set the CallBreaker address of the primary chain}"
         SECONDARY_CHAIN_ID=84532
         SECONDARY_HTTP_CHAIN_URL="https://sepolia.base.org"
         SECONDARY_BLOCK_TIME_ADDRESS="0xdD1B4D9337D0a8Ef2F133a39cC93EF85261b4A80"
+        SECONDARY_CALL_BREAKER_ADDRESS="${SECONDARY_CALL_BREAKER_ADDRESS:?Set the CallBreaker address of the secondary chain}"
         DRY_RUN="false"
+        AUTO_MIGRATE="true"
+        PREFLIGHT="strict"
         break
         ;;
     "prod")
@@ -43,10 +47,14 @@ do
         PRIMARY_CHAIN_ID=21363
         PRIMARY_HTTP_CHAIN_URL="https://service.lestnet.org"
         PRIMARY_BLOCK_TIME_ADDRESS="0xdD1B4D9337D0a8Ef2F133a39cC93EF85261b4A80"
+        PRIMARY_CALL_BREAKER_ADDRESS="${PRIMARY_CALL_BREAKER_ADDRESS:?Set the CallBreaker address of the primary chain}"
         SECONDARY_CHAIN_ID=84532
         SECONDARY_HTTP_CHAIN_URL="https://sepolia.base.org"
         SECONDARY_BLOCK_TIME_ADDRESS="0xdD1B4D9337D0a8Ef2F133a39cC93EF85261b4A80"
+        SECONDARY_CALL_BREAKER_ADDRESS="${SECONDARY_CALL_BREAKER_ADDRESS:?Set the CallBreaker address of the secondary chain}"
         DRY_RUN="false"
+        AUTO_MIGRATE="true"
+        PREFLIGHT="strict"
         break
         ;;
     "quit")
@@ -117,9 +125,13 @@ services:
       - PRIMARY_HTTP_CHAIN_URL=${PRIMARY_HTTP_CHAIN_URL}
       - SECONDARY_HTTP_CHAIN_URL=${SECONDARY_HTTP_CHAIN_URL}
       - PRIMARY_BLOCK_TIME_ADDRESS=${PRIMARY_BLOCK_TIME_ADDRESS}
+      - PRIMARY_CALL_BREAKER_ADDRESS=${PRIMARY_CALL_BREAKER_ADDRESS}
       - SECONDARY_BLOCK_TIME_ADDRESS=${SECONDARY_BLOCK_TIME_ADDRESS}
+      - SECONDARY_CALL_BREAKER_ADDRESS=${SECONDARY_CALL_BREAKER_ADDRESS}
       - TICK_PERIOD=${TICK_PERIOD}
       - DRY_RUN=${DRY_RUN}
+      - AUTO_MIGRATE=${AUTO_MIGRATE}
+      - PREFLIGHT=${PREFLIGHT}
     ports:
       - 8000:8000
     logging:
@@ -8,9 +8,11 @@ TIME_WINDOW=3s
 PRIMARY_CHAIN_ID=21363
 PRIMARY_HTTP_CHAIN_URL=https://service.lestnet.org
 PRIMARY_BLOCK_TIME_ADDRESS=0xdD1B4D9337D0a8Ef2F133a39cC93EF85261b4A80
+PRIMARY_CALL_BREAKER_ADDRESS=${PRIMARY_CALL_BREAKER_ADDRESS:?Set the CallBreaker address of the primary chain}
 SECONDARY_CHAIN_ID=84532
 SECONDARY_HTTP_CHAIN_URL=https://sepolia.base.org
 SECONDARY_BLOCK_TIME_ADDRESS=0xdD1B4D9337D0a8Ef2F133a39cC93EF85261b4A80
+SECONDARY_CALL_BREAKER_ADDRESS=${SECONDARY_CALL_BREAKER_ADDRESS:?Set the CallBreaker address of the secondary chain}
 TICK_PERIOD=1s
 
 PROJECT_NAME="solver-438012"
@@ -33,10 +35,8 @@ cargo run \
   --mysql-host=${MYSQL_HOST} \
   --mysql-port=${MYSQL_PORT} \
   --mysql-database=${MYSQL_DATABASE} \
-  --primary-chain-id=${PRIMARY_CHAIN_ID} \
-  --primary-http-chain-url=${PRIMARY_HTTP_CHAIN_URL} \
-  --primary-block-time-address=${PRIMARY_BLOCK_TIME_ADDRESS} \
-  --secondary-chain-id=${SECONDARY_CHAIN_ID} \
-  --secondary-http-chain-url=${SECONDARY_HTTP_CHAIN_URL} \
-  --secondary-block-time-address=${SECONDARY_BLOCK_TIME_ADDRESS} \
+  --auto-migrate=true \
+  --chain=id=${PRIMARY_CHAIN_ID},url=${PRIMARY_HTTP_CHAIN_URL},block_time=${PRIMARY_BLOCK_TIME_ADDRESS},call_breaker=${PRIMARY_CALL_BREAKER_ADDRESS} \
+  --chain=id=${SECONDARY_CHAIN_ID},url=${SECONDARY_HTTP_CHAIN_URL},block_time=${SECONDARY_BLOCK_TIME_ADDRESS},call_breaker=${SECONDARY_CALL_BREAKER_ADDRESS} \
+  --signing-chain-id=${PRIMARY_CHAIN_ID} \
   --dry-run=true
\ No newline at end of file
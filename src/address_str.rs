@@ -1,5 +1,238 @@
-use ethers::types::Address;
+use std::{collections::BTreeMap, error::Error};
 
-pub fn get_address_strings(addr: &Address) -> (String, String) {
-    (format!("{:#x}", addr), addr.to_string())
+use log::{info, warn};
+use mysql::Conn;
+
+use crate::db::{merge_address_rows, read_address_rows, read_reward_receivers};
+
+// A whitelisted address row as stored, before canonicalization.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AddressRow {
+    pub address: String,
+    pub avatar: Option<String>,
+    pub referral_code: Option<String>,
+    pub referred_from: Option<String>,
+    // Unix time.
+    pub referred_at: Option<u64>,
+}
+
+// Rows with the same canonical address replaced by one row.
+#[derive(Debug, PartialEq)]
+pub struct AddressMerge {
+    pub rows: Vec<String>,
+    pub keeper: AddressRow,
+    // Referral codes of the dropped rows, their referees move to the code of the merged row.
+    pub dropped_codes: Vec<String>,
+}
+
+// Lowercase full hex, the only form accepted by the address check of the whitelist.
+pub fn is_canonical(address: &str) -> bool {
+    address.len() == 42
+        && address.starts_with("0x")
+        && address[2..]
+            .chars()
+            .all(|el| el.is_ascii_digit() || ('a'..='f').contains(&el))
+}
+
+// Full hex addresses in any case are lowercased. Display addresses like 0x25ee…483e are resolved
+// against the known full addresses and stay unresolved if none or several of them match.
+pub fn canonical_address(address: &str, known: &[String]) -> Option<String> {
+    let lowercase = address.trim().to_lowercase();
+    if is_canonical(&lowercase) {
+        return Some(lowercase);
+    }
+    let (prefix, suffix) = lowercase.split_once('…')?;
+    if prefix.len() != 6 || suffix.len() != 4 {
+        return None;
+    }
+    let mut matches = known
+        .iter()
+        .filter(|el| el.starts_with(prefix) && el.ends_with(suffix));
+    match (matches.next(), matches.next()) {
+        (Some(address), None) => Some(address.clone()),
+        _ => None,
+    }
+}
+
+// Groups the rows by their canonical address, returns the merges to apply and the unresolved rows.
+pub fn plan_canonicalization(
+    rows: Vec<AddressRow>,
+    known: &[String],
+) -> (Vec<AddressMerge>, Vec<String>) {
+    let known: Vec<String> = known
+        .iter()
+        .map(|el| el.trim().to_lowercase())
+        .filter(|el| is_canonical(el))
+        .collect();
+    let mut groups: BTreeMap<String, Vec<AddressRow>> = BTreeMap::new();
+    let mut unresolved = Vec::new();
+    for row in rows {
+        match canonical_address(&row.address, &known) {
+            Some(address) => groups.entry(address).or_default().push(row),
+            None => unresolved.push(row.address),
+        }
+    }
+    let merges = groups
+        .into_iter()
+        .filter(|(address, rows)| rows.len() > 1 || rows[0].address != *address)
+        .map(|(address, rows)| merge_rows(address, rows))
+        .collect();
+    (merges, unresolved)
+}
+
+// The row already in the canonical form wins, the others fill its empty fields.
+fn merge_rows(address: String, mut rows: Vec<AddressRow>) -> AddressMerge {
+    rows.sort_by_key(|el| el.address != address);
+    let non_empty = |value: &Option<String>| value.clone().filter(|el| !el.is_empty());
+    let mut keeper = AddressRow {
+        address,
+        ..AddressRow::default()
+    };
+    for row in rows.iter() {
+        keeper.avatar = keeper.avatar.or_else(|| non_empty(&row.avatar));
+        keeper.referral_code = keeper
+            .referral_code
+            .or_else(|| non_empty(&row.referral_code));
+        keeper.referred_from = keeper
+            .referred_from
+            .or_else(|| non_empty(&row.referred_from));
+        keeper.referred_at = match (keeper.referred_at, row.referred_at) {
+            (Some(curr), Some(next)) => Some(curr.min(next)),
+            (curr, next) => curr.or(next),
+        };
+    }
+    let dropped_codes: Vec<String> = rows
+        .iter()
+        .filter_map(|row| non_empty(&row.referral_code))
+        .filter(|code| keeper.referral_code.as_ref() != Some(code))
+        .collect();
+    // The merged rows could have referred each other.
+    if let Some(referred_from) = &keeper.referred_from {
+        if keeper.referral_code.as_ref() == Some(referred_from)
+            || dropped_codes.contains(referred_from)
+        {
+            keeper.referred_from = None;
+            keeper.referred_at = None;
+        }
+    }
+    AddressMerge {
+        rows: rows.into_iter().map(|el| el.address).collect(),
+        keeper,
+        dropped_codes,
+    }
+}
+
+// Rewrites the whitelist to canonical addresses, the reward receivers and `known` resolve display addresses.
+// Returns the unresolved addresses, they are left as they are.
+pub fn canonicalize_addresses(
+    conn: &mut Conn,
+    known: &[String],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let rows = read_address_rows(conn)?;
+    let mut known = known.to_vec();
+    known.extend(read_reward_receivers(conn)?);
+    known.extend(rows.iter().map(|el| el.address.clone()));
+    let (merges, unresolved) = plan_canonicalization(rows, &known);
+    for merge in merges.iter() {
+        info!(
+            "Canonicalizing {} into {}",
+            merge.rows.join(", "),
+            merge.keeper.address
+        );
+        merge_address_rows(conn, merge)?;
+    }
+    for address in unresolved.iter() {
+        warn!("Can't resolve the whitelisted address {}", address);
+    }
+    Ok(unresolved)
+}
+
+// Runs before the address check is added, which would fail on the unresolved addresses.
+pub fn canonicalize_addresses_strict(conn: &mut Conn) -> Result<(), Box<dyn Error>> {
+    let unresolved = canonicalize_addresses(conn, &[])?;
+    if !unresolved.is_empty() {
+        return Err(format!(
+            "Unresolved whitelisted addresses {}, run the canonicalize-addresses subcommand with --known-addresses",
+            unresolved.join(", ")
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::Address;
+
+    use super::{canonical_address, plan_canonicalization, AddressMerge, AddressRow};
+
+    #[tokio::test]
+    async fn test_canonical_address() -> Result<(), String> {
+        let addr = Address::from_low_u64_be(0xabcdef);
+        let full = format!("{:#x}", addr);
+        let known = vec![full.clone()];
+        assert_eq!(
+            canonical_address(&addr.to_string(), &known),
+            Some(full.clone())
+        );
+        assert_eq!(canonical_address(&addr.to_string(), &[]), None);
+        assert_eq!(
+            canonical_address(&full.to_uppercase().replacen("0X", "0x", 1), &[]),
+            Some(full.clone())
+        );
+        // Ambiguous display address.
+        let other = format!("0x0000{}abcdef", "1".repeat(30));
+        assert_eq!(canonical_address(&addr.to_string(), &[full, other]), None);
+        assert_eq!(canonical_address("0x123", &[]), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_plan_canonicalization() -> Result<(), String> {
+        let addr = Address::from_low_u64_be(0xabcdef);
+        let full = format!("{:#x}", addr);
+        let row = |address: &str,
+                   avatar: Option<&str>,
+                   code: Option<&str>,
+                   referred_from: Option<&str>| AddressRow {
+            address: address.to_string(),
+            avatar: avatar.map(str::to_string),
+            referral_code: code.map(str::to_string),
+            referred_from: referred_from.map(str::to_string),
+            referred_at: referred_from.map(|_| 100),
+        };
+        let rows = vec![
+            row(&addr.to_string(), Some("cat"), Some("short"), Some("third")),
+            row(&full, None, Some("full"), None),
+            row(&full.replace("abcdef", "ABCDEF"), None, None, None),
+            row("0x1234…5678", None, None, None),
+            row(
+                "0x1111111111111111111111111111111111111111",
+                Some("dog"),
+                None,
+                None,
+            ),
+        ];
+        let (merges, unresolved) = plan_canonicalization(rows, std::slice::from_ref(&full));
+        assert_eq!(unresolved, ["0x1234…5678"]);
+        assert_eq!(
+            merges,
+            [AddressMerge {
+                rows: vec![
+                    full.clone(),
+                    addr.to_string(),
+                    full.replace("abcdef", "ABCDEF")
+                ],
+                keeper: AddressRow {
+                    address: full,
+                    avatar: Some("cat".to_string()),
+                    referral_code: Some("full".to_string()),
+                    referred_from: Some("third".to_string()),
+                    referred_at: Some(100),
+                },
+                dropped_codes: vec!["short".to_string()],
+            }]
+        );
+        Ok(())
+    }
 }
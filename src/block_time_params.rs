@@ -0,0 +1,164 @@
+use std::{sync::Arc, time::Duration};
+
+use ethers::{
+    contract::ContractError,
+    providers::{Middleware, StreamExt},
+    types::U256,
+};
+use log::{error, info};
+use tokio::{sync::Mutex, time::sleep};
+
+use crate::{
+    call_breaker::CallBreakerData,
+    time_signature::{BlockTime, Chronicle, MaxBlockWidthSetFilter},
+};
+
+const WATCH_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// BlockTime parameters a tick has to satisfy, otherwise `moveTime` reverts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockTimeParams {
+    pub min_number_of_chronicles: U256,
+    pub max_block_width: U256,
+    // Precision of the mean time, the contract takes the time to be up to this much after it.
+    pub time_block_width: U256,
+}
+
+pub type BlockTimeParamsCache = Arc<Mutex<Option<BlockTimeParams>>>;
+
+#[derive(Debug, PartialEq)]
+pub enum TickCheck {
+    Pass,
+    // Not enough chronicles yet, keep them for the next tick.
+    Hold(String),
+    // The tick can't be accepted by the contract.
+    Skip(String),
+}
+
+impl BlockTimeParams {
+    pub async fn fetch<M: Middleware>(
+        contract: &BlockTime<M>,
+    ) -> Result<BlockTimeParams, ContractError<M>> {
+        Ok(BlockTimeParams {
+            min_number_of_chronicles: contract.min_number_of_chronicles().call().await?,
+            max_block_width: contract.get_max_block_width().call().await?,
+            time_block_width: contract.time_block_width().call().await?,
+        })
+    }
+
+    // Checks the number of chronicles and the width of the time block they span.
+    pub fn check(&self, chronicles: &[Chronicle]) -> TickCheck {
+        if U256::from(chronicles.len()) < self.min_number_of_chronicles {
+            return TickCheck::Hold(format!(
+                "{} chronicles, at least {} required",
+                chronicles.len(),
+                self.min_number_of_chronicles
+            ));
+        }
+        let first = chronicles.iter().map(|el| el.epoch).min();
+        let last = chronicles.iter().map(|el| el.epoch).max();
+        if let (Some(first), Some(last)) = (first, last) {
+            if !self.max_block_width.is_zero() && last - first > self.max_block_width {
+                return TickCheck::Skip(format!(
+                    "block width {} exceeds max block width {}",
+                    last - first,
+                    self.max_block_width
+                ));
+            }
+        }
+        TickCheck::Pass
+    }
+}
+
+// Returns the cached parameters, fetching them from the contract if the cache is empty.
+pub async fn get_params<M: Middleware>(
+    call_breaker_data: &CallBreakerData<M>,
+    cache: &BlockTimeParamsCache,
+) -> Option<BlockTimeParams> {
+    let mut cache = cache.lock().await;
+    if cache.is_none() {
+        match BlockTimeParams::fetch(&call_breaker_data.block_time_contract).await {
+            Ok(params) => {
+                info!(
+                    "Fetched BlockTime params of {:#x}: {:?}",
+                    call_breaker_data.block_time_contract.address(),
+                    params
+                );
+                *cache = Some(params);
+            }
+            Err(err) => error!("Error fetching BlockTime params: {}", err),
+        }
+    }
+    *cache
+}
+
+// Invalidates the cached parameters every time `MaxBlockWidthSet` is emitted.
+pub async fn watch_params<M: Middleware + 'static>(
+    call_breaker_data: Arc<CallBreakerData<M>>,
+    cache: BlockTimeParamsCache,
+) {
+    loop {
+        let event = call_breaker_data
+            .block_time_contract
+            .event::<MaxBlockWidthSetFilter>();
+        match event.stream().await {
+            Ok(mut stream) => {
+                while let Some(res) = stream.next().await {
+                    match res {
+                        Ok(ev) => {
+                            info!(
+                                "Max block width of {:#x} set to {}",
+                                call_breaker_data.block_time_contract.address(),
+                                ev.max_block_width
+                            );
+                            *cache.lock().await = None;
+                        }
+                        Err(err) => error!("Error reading MaxBlockWidthSet event: {}", err),
+                    }
+                }
+            }
+            Err(err) => error!("Error watching MaxBlockWidthSet events: {}", err),
+        }
+        sleep(WATCH_RETRY_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, time::Duration};
+
+    use ethers::types::{Address, Bytes, U256};
+
+    use crate::time_signature::Chronicle;
+
+    use super::{BlockTimeParams, TickCheck};
+
+    #[tokio::test]
+    async fn test_check() -> Result<(), String> {
+        let params = BlockTimeParams {
+            min_number_of_chronicles: U256::from(2),
+            max_block_width: Duration::from_secs(2).as_nanos().into(),
+            time_block_width: U256::zero(),
+        };
+        let chronicle = |secs: u64| {
+            Chronicle::new(
+                Duration::new(secs, 0).as_nanos().into(),
+                Address::from_str("0x25ee756f5d93e26f5011b7ed4866afb192ce483e").unwrap(),
+                Bytes::new(),
+            )
+        };
+        assert!(matches!(
+            params.check(&[chronicle(1734220767)]),
+            TickCheck::Hold(_)
+        ));
+        assert_eq!(
+            params.check(&[chronicle(1734220767), chronicle(1734220769)]),
+            TickCheck::Pass
+        );
+        assert!(matches!(
+            params.check(&[chronicle(1734220767), chronicle(1734220770)]),
+            TickCheck::Skip(_)
+        ));
+        Ok(())
+    }
+}
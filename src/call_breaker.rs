@@ -43,6 +43,7 @@ impl<M: Middleware> CallBreakerData<M> {
 }
 
 impl CallObject {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         salt: U256,
         amount: U256,
@@ -83,6 +84,7 @@ impl CallObject {
 }
 
 impl UserObjective {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         app_id: Bytes,
         nonce: U256,
@@ -111,7 +113,7 @@ impl UserObjective {
         nonce: &U256,
         sender: &Address,
         signer_private_key: &LocalWallet,
-        call_objects: &Vec<CallObject>,
+        call_objects: &[CallObject],
     ) -> Bytes {
         // generate the message hash
         let call_tokens: Vec<Token> = call_objects.iter().map(|c| c.to_token_tuple()).collect();
@@ -167,10 +169,7 @@ impl MevTimeData {
         }
     }
 
-    fn validator_signature(
-        data: &Vec<AdditionalData>,
-        validator_private_key: &LocalWallet,
-    ) -> Bytes {
+    fn validator_signature(data: &[AdditionalData], validator_private_key: &LocalWallet) -> Bytes {
         // generate the message hash
         let additional_data_token: Vec<Token> = data.iter().map(|c| c.to_token_tuple()).collect();
         let additional_data_encoded = encode(&[Token::Array(additional_data_token)]);
@@ -0,0 +1,170 @@
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use ethers::types::Address;
+use md5::Digest;
+
+// A chain the mean time is submitted to.
+// Parsed from `id=<chain id>,url=<http url>,block_time=<address>,call_breaker=<address>[,enabled=<bool>]`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainTarget {
+    pub chain_id: u64,
+    pub http_chain_url: String,
+    pub block_time_address: Address,
+    pub call_breaker_address: Address,
+    pub enabled: bool,
+}
+
+impl FromStr for ChainTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chain_id = None;
+        let mut http_chain_url = None;
+        let mut block_time_address = None;
+        let mut call_breaker_address = None;
+        let mut enabled = true;
+        for field in s.split(',') {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or(format!("Expected key=value, got \"{}\"", field))?;
+            let value = value.trim();
+            match key.trim() {
+                "id" => chain_id = Some(value.parse::<u64>().map_err(|err| err.to_string())?),
+                "url" => http_chain_url = Some(value.to_string()),
+                "block_time" => {
+                    block_time_address =
+                        Some(Address::from_str(value).map_err(|err| err.to_string())?)
+                }
+                "call_breaker" => {
+                    call_breaker_address =
+                        Some(Address::from_str(value).map_err(|err| err.to_string())?)
+                }
+                "enabled" => enabled = value.parse::<bool>().map_err(|err| err.to_string())?,
+                other => return Err(format!("Unknown chain target field \"{}\"", other)),
+            }
+        }
+        Ok(ChainTarget {
+            chain_id: chain_id.ok_or("Missing chain target id")?,
+            http_chain_url: http_chain_url.ok_or("Missing chain target url")?,
+            block_time_address: block_time_address.ok_or("Missing chain target block_time")?,
+            call_breaker_address: call_breaker_address
+                .ok_or("Missing chain target call_breaker")?,
+            enabled,
+        })
+    }
+}
+
+// Submission state of a single chain target.
+pub struct TargetState {
+    pub curr_md5: Digest,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl Default for TargetState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TargetState {
+    pub fn new() -> TargetState {
+        TargetState {
+            curr_md5: md5::compute("--dummy--"),
+            failures: 0,
+            retry_at: None,
+        }
+    }
+
+    // Whether the target is out of its retry backoff.
+    pub fn is_ready(&self, now: Instant) -> bool {
+        match self.retry_at {
+            Some(retry_at) => now >= retry_at,
+            None => true,
+        }
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    pub fn record_success(&mut self, md5: Digest) {
+        self.curr_md5 = md5;
+        self.failures = 0;
+        self.retry_at = None;
+    }
+
+    // Doubles the backoff with every consecutive failure, up to the max backoff.
+    pub fn record_failure(&mut self, now: Instant, backoff: Duration, max_backoff: Duration) {
+        let delay = backoff
+            .saturating_mul(2u32.saturating_pow(self.failures))
+            .min(max_backoff);
+        self.failures = self.failures.saturating_add(1);
+        self.retry_at = Some(now + delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        str::FromStr,
+        time::{Duration, Instant},
+    };
+
+    use ethers::types::Address;
+
+    use super::{ChainTarget, TargetState};
+
+    #[tokio::test]
+    async fn test_parse_chain_target() -> Result<(), String> {
+        let target = ChainTarget::from_str(
+            "id=84532,url=https://sepolia.base.org,block_time=0xdD1B4D9337D0a8Ef2F133a39cC93EF85261b4A80,call_breaker=0x8ab3c48c839376d2b79ab98f23f5b2406a06a022",
+        )?;
+        assert_eq!(target.chain_id, 84532);
+        assert_eq!(target.http_chain_url, "https://sepolia.base.org");
+        assert_eq!(
+            target.block_time_address,
+            Address::from_str("0xdD1B4D9337D0a8Ef2F133a39cC93EF85261b4A80").unwrap()
+        );
+        assert_eq!(
+            target.call_breaker_address,
+            Address::from_str("0x8ab3c48c839376d2b79ab98f23f5b2406a06a022").unwrap()
+        );
+        assert!(target.enabled);
+
+        let target = ChainTarget::from_str(
+            "id=1,url=http://localhost:8545,block_time=0xdD1B4D9337D0a8Ef2F133a39cC93EF85261b4A80,call_breaker=0x8ab3c48c839376d2b79ab98f23f5b2406a06a022,enabled=false",
+        )?;
+        assert!(!target.enabled);
+
+        assert!(ChainTarget::from_str("id=1,url=http://localhost:8545").is_err());
+        assert!(ChainTarget::from_str("id=1,foo=bar").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_backoff() -> Result<(), String> {
+        let mut state = TargetState::new();
+        let now = Instant::now();
+        let backoff = Duration::from_secs(1);
+        let max_backoff = Duration::from_secs(3);
+        assert!(state.is_ready(now));
+        state.record_failure(now, backoff, max_backoff);
+        assert!(!state.is_ready(now));
+        assert!(state.is_ready(now + Duration::from_secs(1)));
+        state.record_failure(now, backoff, max_backoff);
+        assert!(!state.is_ready(now + Duration::from_secs(1)));
+        assert!(state.is_ready(now + Duration::from_secs(2)));
+        state.record_failure(now, backoff, max_backoff);
+        assert!(!state.is_ready(now + Duration::from_secs(2)));
+        assert!(state.is_ready(now + Duration::from_secs(3)));
+        assert_eq!(state.failures(), 3);
+        state.record_success(md5::compute("tick"));
+        assert!(state.is_ready(now));
+        assert_eq!(state.failures(), 0);
+        Ok(())
+    }
+}
@@ -1,50 +1,51 @@
-use axum::{http::StatusCode, Json};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::types::transaction::eip712::EIP712Domain;
 use log::*;
-use mysql::PooledConn;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
 use crate::{
-    db::{is_avatar_available, update_avatar},
+    replay_guard::ReplayGuard,
+    signed_request::SignedRequest,
+    store::{KeeperStore, StorePool},
     user_data::AvatarData,
 };
 
-pub async fn handle_claim_avatar(
-    input_json: Json<AvatarData>,
-    db_conn: Arc<Mutex<PooledConn>>,
-) -> Result<(), StatusCode> {
-    let mut db_conn = db_conn.lock().await;
-    match is_avatar_available(
-        db_conn.as_mut(),
-        &input_json.time_keeper,
-        &input_json.avatar,
-    )
-    .await
-    {
-        Ok(is_avail) => {
-            if !is_avail {
-                warn!("The avatar {} is already in use", input_json.avatar);
-                return Err(StatusCode::CONFLICT);
+pub async fn handle_claim_avatar<S: StorePool>(
+    Json(input): Json<SignedRequest<AvatarData>>,
+    db: S,
+    replay_guard: Arc<Mutex<ReplayGuard>>,
+    signing_domain: Arc<EIP712Domain>,
+) -> Result<(), Response> {
+    input
+        .verify(&db, &replay_guard, &signing_domain)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let input_json = input.data;
+    let avatar = input_json.avatar.clone();
+    // A concurrent claim can take the avatar between the check and the update, the update then
+    // hits the unique index and returns false as well.
+    let is_updated = db
+        .with_store(move |store| {
+            if !store.is_avatar_available(&input_json.time_keeper, &input_json.avatar)? {
+                return Ok(false);
             }
-        }
-        Err(err) => {
-            error!("Error checking the avatar: {}", err);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
-    }
-    match update_avatar(
-        db_conn.as_mut(),
-        &input_json.time_keeper,
-        &input_json.avatar,
-    )
-    .await
-    {
-        Ok(_) => {
-            return Ok(());
+            store.update_avatar(&input_json.time_keeper, &input_json.avatar)
+        })
+        .await;
+    match is_updated {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            warn!("The avatar {} is already in use", avatar);
+            Err(StatusCode::CONFLICT.into_response())
         }
         Err(err) => {
             error!("Error updating the avatar: {}", err);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
         }
     }
 }
@@ -0,0 +1,432 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+};
+
+use clap::ValueEnum;
+use ethers::types::{Address, U256};
+
+use crate::time_signature::Chronicle;
+
+// Scale factor making the MAD a consistent estimator of the standard deviation.
+const MAD_SCALE: f64 = 1.4826;
+// Fixed point of the weights of the weighted mean.
+const WEIGHT_SCALE: u128 = 1_000_000_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ConsensusMode {
+    // Plain arithmetic mean of all chronicles in the window.
+    Mean,
+    // Median of all chronicles in the window.
+    Median,
+    // Mean after dropping the lowest and highest `trim_ratio` share of chronicles.
+    TrimmedMean,
+    // Mean after dropping chronicles further than `mad_threshold` scaled MADs from the median.
+    Mad,
+    // Mean with every chronicle weighted down by its distance from the median.
+    WeightedMean,
+}
+
+impl Display for ConsensusMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => write!(f, "{:?}", self),
+        }
+    }
+}
+
+// How several chronicles of the same time keeper within one window are reduced to one vote.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum VotePolicy {
+    // The keeper's latest chronicle is the vote.
+    Latest,
+    // The keeper's earliest chronicle is the vote.
+    Earliest,
+    // The average of the keeper's chronicles is the vote, the latest one is submitted.
+    Average,
+}
+
+impl Display for VotePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => write!(f, "{:?}", self),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ConsensusConfig {
+    pub mode: ConsensusMode,
+    pub vote_policy: VotePolicy,
+    pub trim_ratio: f64,
+    pub mad_threshold: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Consensus {
+    pub mean_time: U256,
+    pub accepted: Vec<Chronicle>,
+    pub rejected: Vec<Chronicle>,
+}
+
+// A single time keeper's vote: the epoch counted in the mean and the chronicle submitted on chain.
+#[derive(Clone, Debug)]
+struct Vote {
+    epoch: u128,
+    chronicle: Chronicle,
+}
+
+impl ConsensusConfig {
+    pub fn new(
+        mode: ConsensusMode,
+        vote_policy: VotePolicy,
+        trim_ratio: f64,
+        mad_threshold: f64,
+    ) -> ConsensusConfig {
+        ConsensusConfig {
+            mode,
+            vote_policy,
+            trim_ratio,
+            mad_threshold,
+        }
+    }
+
+    // Aggregates the chronicles of one time window into the mean time.
+    pub fn aggregate(&self, sigs: Vec<Chronicle>) -> Option<Consensus> {
+        let mut votes = self.collect_votes(sigs);
+        if votes.is_empty() {
+            return None;
+        }
+        votes.sort_by_key(|el| el.epoch);
+        let epochs: Vec<u128> = votes.iter().map(|el| el.epoch).collect();
+        match self.mode {
+            ConsensusMode::Mean => Some(Consensus {
+                mean_time: mean(&epochs).into(),
+                accepted: chronicles_of(votes),
+                rejected: Vec::new(),
+            }),
+            ConsensusMode::Median => Some(Consensus {
+                mean_time: median(&epochs).into(),
+                accepted: chronicles_of(votes),
+                rejected: Vec::new(),
+            }),
+            ConsensusMode::TrimmedMean => {
+                let len = votes.len();
+                let mut trim = (len as f64 * self.trim_ratio.clamp(0.0, 0.5)) as usize;
+                if trim * 2 >= len {
+                    // Always keep at least the middle vote.
+                    trim = (len - 1) / 2;
+                }
+                let mut rejected = votes.split_off(len - trim);
+                let accepted = votes.split_off(trim);
+                rejected.append(&mut votes);
+                Some(Consensus {
+                    mean_time: mean(&epochs[trim..len - trim]).into(),
+                    accepted: chronicles_of(accepted),
+                    rejected: chronicles_of(rejected),
+                })
+            }
+            ConsensusMode::Mad => {
+                let med = median(&epochs);
+                let mut deviations: Vec<u128> = epochs.iter().map(|el| el.abs_diff(med)).collect();
+                deviations.sort();
+                // Most chronicles on the median would reject every other one.
+                let mad = median(&deviations).max(1) as f64;
+                let limit = self.mad_threshold * MAD_SCALE * mad;
+                let (accepted, rejected): (Vec<Vote>, Vec<Vote>) = votes
+                    .into_iter()
+                    .partition(|el| el.epoch.abs_diff(med) as f64 <= limit);
+                let accepted_epochs: Vec<u128> = accepted.iter().map(|el| el.epoch).collect();
+                Some(Consensus {
+                    mean_time: mean(&accepted_epochs).into(),
+                    accepted: chronicles_of(accepted),
+                    rejected: chronicles_of(rejected),
+                })
+            }
+            ConsensusMode::WeightedMean => {
+                let med = median(&epochs);
+                let mut deviations: Vec<u128> = epochs.iter().map(|el| el.abs_diff(med)).collect();
+                deviations.sort();
+                let mad = median(&deviations).max(1);
+                // The signed offsets from the median are averaged in integers, an f64 epoch
+                // only resolves 256 ns. The weight is mad / (mad + |offset|).
+                let (weighted_sum, weight_total) =
+                    epochs.iter().fold((0i128, 0i128), |(sum, total), el| {
+                        let offset = *el as i128 - med as i128;
+                        let weight = (WEIGHT_SCALE * mad / (mad + offset.unsigned_abs())) as i128;
+                        (sum + weight * offset, total + weight)
+                    });
+                let mean_time = (med as i128 + div_round(weighted_sum, weight_total)) as u128;
+                Some(Consensus {
+                    mean_time: mean_time.into(),
+                    accepted: chronicles_of(votes),
+                    rejected: Vec::new(),
+                })
+            }
+        }
+    }
+
+    // Reduces the chronicles to one vote per time keeper according to the vote policy.
+    // Epochs past u64 nanoseconds are dropped, so that the sums of the votes can't overflow.
+    fn collect_votes(&self, sigs: Vec<Chronicle>) -> Vec<Vote> {
+        let by_keeper = sigs
+            .into_iter()
+            .filter(|el| u64::try_from(el.epoch).is_ok())
+            .fold(
+                BTreeMap::new(),
+                |mut acc: BTreeMap<Address, Vec<Chronicle>>, el| {
+                    acc.entry(el.time_keeper).or_default().push(el);
+                    acc
+                },
+            );
+        by_keeper
+            .into_values()
+            .filter_map(|mut keeper_sigs| {
+                keeper_sigs.sort_by_key(|el| el.epoch);
+                let epochs: Vec<u128> = keeper_sigs.iter().map(|el| el.epoch.low_u128()).collect();
+                let (epoch, chronicle) = match self.vote_policy {
+                    VotePolicy::Latest => (*epochs.last()?, keeper_sigs.pop()?),
+                    VotePolicy::Earliest => (*epochs.first()?, keeper_sigs.swap_remove(0)),
+                    VotePolicy::Average => (mean(&epochs), keeper_sigs.pop()?),
+                };
+                Some(Vote { epoch, chronicle })
+            })
+            .collect()
+    }
+}
+
+fn chronicles_of(votes: Vec<Vote>) -> Vec<Chronicle> {
+    votes.into_iter().map(|el| el.chronicle).collect()
+}
+
+fn mean(values: &[u128]) -> u128 {
+    values.iter().sum::<u128>() / values.len() as u128
+}
+
+// Rounds half away from zero, the divisor is positive.
+fn div_round(num: i128, den: i128) -> i128 {
+    (num + num.signum() * den / 2) / den
+}
+
+fn median(sorted: &[u128]) -> u128 {
+    let mid = sorted.len() / 2;
+    if sorted.len() % 2 == 1 {
+        sorted[mid]
+    } else {
+        (sorted[mid - 1] + sorted[mid]) / 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, time::Duration};
+
+    use ethers::types::{Address, Bytes, U256};
+
+    use crate::time_signature::Chronicle;
+
+    use super::{ConsensusConfig, ConsensusMode, VotePolicy};
+
+    // One chronicle per time keeper, the keeper address is derived from the index.
+    fn chronicles(epochs: &[u64]) -> Vec<Chronicle> {
+        epochs
+            .iter()
+            .enumerate()
+            .map(|(idx, el)| {
+                Chronicle::new(
+                    Duration::from_millis(*el).as_nanos().into(),
+                    Address::from_low_u64_be(idx as u64 + 1),
+                    Bytes::new(),
+                )
+            })
+            .collect()
+    }
+
+    fn keeper_chronicles(keeper: &str, epochs: &[u64]) -> Vec<Chronicle> {
+        epochs
+            .iter()
+            .map(|el| {
+                Chronicle::new(
+                    Duration::from_millis(*el).as_nanos().into(),
+                    Address::from_str(keeper).unwrap(),
+                    Bytes::new(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_mean() -> Result<(), String> {
+        let config = ConsensusConfig::new(ConsensusMode::Mean, VotePolicy::Latest, 0.1, 3.0);
+        let res = config
+            .aggregate(chronicles(&[1000, 1002, 1004, 9000]))
+            .unwrap();
+        assert_eq!(
+            res.mean_time,
+            Duration::from_micros(3001500).as_nanos().into()
+        );
+        assert_eq!(res.accepted.len(), 4);
+        assert!(res.rejected.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_median() -> Result<(), String> {
+        let config = ConsensusConfig::new(ConsensusMode::Median, VotePolicy::Latest, 0.1, 3.0);
+        let res = config
+            .aggregate(chronicles(&[1000, 1002, 1004, 9000]))
+            .unwrap();
+        assert_eq!(res.mean_time, Duration::from_millis(1003).as_nanos().into());
+        assert!(res.rejected.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_trimmed_mean() -> Result<(), String> {
+        let config =
+            ConsensusConfig::new(ConsensusMode::TrimmedMean, VotePolicy::Latest, 0.25, 3.0);
+        let res = config
+            .aggregate(chronicles(&[1, 1000, 1002, 1004, 9000]))
+            .unwrap();
+        assert_eq!(res.mean_time, Duration::from_millis(1002).as_nanos().into());
+        assert_eq!(res.accepted.len(), 3);
+        assert_eq!(res.rejected.len(), 2);
+
+        // Trimming never drops every chronicle.
+        let config = ConsensusConfig::new(ConsensusMode::TrimmedMean, VotePolicy::Latest, 0.5, 3.0);
+        let res = config.aggregate(chronicles(&[1000, 1002])).unwrap();
+        assert_eq!(res.mean_time, Duration::from_millis(1001).as_nanos().into());
+        assert!(res.rejected.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_mad() -> Result<(), String> {
+        let config = ConsensusConfig::new(ConsensusMode::Mad, VotePolicy::Latest, 0.1, 3.0);
+        let res = config
+            .aggregate(chronicles(&[1000, 1001, 1002, 1003, 9000]))
+            .unwrap();
+        assert_eq!(
+            res.mean_time,
+            Duration::from_micros(1001500).as_nanos().into()
+        );
+        assert_eq!(res.accepted.len(), 4);
+        assert_eq!(res.rejected.len(), 1);
+        assert_eq!(
+            res.rejected[0].epoch,
+            Duration::from_millis(9000).as_nanos().into()
+        );
+
+        // A zero MAD still accepts the chronicles next to the median.
+        let res = config
+            .aggregate(
+                [1000u64, 1000, 1000, 1002, 1009]
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, el)| {
+                        Chronicle::new(
+                            U256::from(*el),
+                            Address::from_low_u64_be(idx as u64 + 1),
+                            Bytes::new(),
+                        )
+                    })
+                    .collect(),
+            )
+            .unwrap();
+        assert_eq!(res.accepted.len(), 4);
+        assert_eq!(res.rejected[0].epoch, U256::from(1009));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_weighted_mean() -> Result<(), String> {
+        let config =
+            ConsensusConfig::new(ConsensusMode::WeightedMean, VotePolicy::Latest, 0.1, 3.0);
+        let res = config
+            .aggregate(chronicles(&[1000, 1001, 1002, 1003, 9000]))
+            .unwrap();
+        assert!(res.mean_time > Duration::from_millis(1001).as_nanos().into());
+        assert!(res.mean_time < Duration::from_millis(1100).as_nanos().into());
+        assert!(res.rejected.is_empty());
+
+        // Weights 1/2, 1 and 1/4 average the offsets -100, 0 and 300 to 14 ns, at current epochs.
+        let base = 1_734_220_767_123_456_789u64;
+        let res = config
+            .aggregate(
+                [base, base + 100, base + 400]
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, el)| {
+                        Chronicle::new(
+                            U256::from(*el),
+                            Address::from_low_u64_be(idx as u64 + 1),
+                            Bytes::new(),
+                        )
+                    })
+                    .collect(),
+            )
+            .unwrap();
+        assert_eq!(res.mean_time, U256::from(base + 114));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_empty() -> Result<(), String> {
+        let config = ConsensusConfig::new(ConsensusMode::Median, VotePolicy::Latest, 0.1, 3.0);
+        assert_eq!(config.aggregate(Vec::new()), None);
+
+        // Epochs too large to sum are dropped.
+        let mut sigs = chronicles(&[1000]);
+        sigs.push(Chronicle::new(
+            U256::MAX,
+            Address::from_low_u64_be(2),
+            Bytes::new(),
+        ));
+        let res = config.aggregate(sigs).unwrap();
+        assert_eq!(res.mean_time, Duration::from_millis(1000).as_nanos().into());
+        assert_eq!(res.accepted.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_vote_policy() -> Result<(), String> {
+        let mut sigs = keeper_chronicles(
+            "0x25ee756f5d93e26f5011b7ed4866afb192ce483e",
+            &[1000, 1004, 1002],
+        );
+        sigs.append(&mut keeper_chronicles(
+            "0x2c57d1cfc6d5f8e4182a56b4cf75421472ebaea4",
+            &[1010],
+        ));
+
+        let config = ConsensusConfig::new(ConsensusMode::Mean, VotePolicy::Latest, 0.1, 3.0);
+        let res = config.aggregate(sigs.clone()).unwrap();
+        assert_eq!(res.mean_time, Duration::from_millis(1007).as_nanos().into());
+        assert_eq!(res.accepted.len(), 2);
+
+        let config = ConsensusConfig::new(ConsensusMode::Mean, VotePolicy::Earliest, 0.1, 3.0);
+        let res = config.aggregate(sigs.clone()).unwrap();
+        assert_eq!(res.mean_time, Duration::from_millis(1005).as_nanos().into());
+        assert_eq!(res.accepted.len(), 2);
+        assert_eq!(
+            res.accepted[0].epoch,
+            Duration::from_millis(1000).as_nanos().into()
+        );
+
+        let config = ConsensusConfig::new(ConsensusMode::Mean, VotePolicy::Average, 0.1, 3.0);
+        let res = config.aggregate(sigs).unwrap();
+        assert_eq!(
+            res.mean_time,
+            Duration::from_micros(1006000).as_nanos().into()
+        );
+        assert_eq!(res.accepted.len(), 2);
+        // The averaged keeper submits its latest signed chronicle.
+        assert_eq!(
+            res.accepted[0].epoch,
+            Duration::from_millis(1004).as_nanos().into()
+        );
+        Ok(())
+    }
+}
@@ -1,33 +1,32 @@
-use std::error::Error;
+use std::{collections::HashMap, error::Error, str::FromStr, time::Duration};
 
-use ethers::types::Address;
-use mysql::{prelude::Queryable, Conn, FromRowError};
+use ethers::types::{Address, Bytes, H256, U256};
+use mysql::{prelude::Queryable, Conn, Row, TxOpts};
 
-use crate::{address_str::get_address_strings, referral::ReferralData};
+use crate::{
+    address_str::{AddressMerge, AddressRow},
+    metrics::METRICS,
+    referral::ReferralData,
+    referral_tree::{Keeper, Link},
+    reward_ledger::{RewardEvent, RewardShare, RewardStatus},
+};
 
-// Fixes an invalid address in the addresses db. Replaces a short display address with a full one.
-pub async fn fix_address(conn: &mut Conn, addr: &Address) -> Result<(), Box<dyn Error>> {
-    check_conn(conn);
-    let (full_addr, short_addr) = get_address_strings(&addr);
-    conn.exec_drop(
-        "UPDATE whitelisted_addresses SET address = ? WHERE address = ?",
-        (full_addr, short_addr),
-    )?;
-    Ok(())
-}
+// Duplicate key of a unique index.
+const ER_DUP_ENTRY: u16 = 1062;
 
-pub async fn store_user_data(
+pub fn store_user_data(
     conn: &mut Conn,
     addr: &Address,
-    avatar: &String,
+    avatar: &str,
 ) -> Result<(), Box<dyn Error>> {
     check_conn(conn);
-    let (address, trunc_address) = get_address_strings(addr);
+    let _timer = METRICS.db_timer("store_user_data");
+    let address = format!("{:#x}", addr);
     let res: Option<String> = conn.exec_first(
-        "SELECT address FROM whitelisted_addresses WHERE address = ? OR address = ?",
-        (&address, trunc_address),
+        "SELECT address FROM whitelisted_addresses WHERE address = ?",
+        (&address,),
     )?;
-    if res == None {
+    if res.is_none() {
         conn.exec_drop(
             "INSERT INTO whitelisted_addresses (address, avatar) VALUES (?, ?)",
             (address, avatar),
@@ -36,114 +35,117 @@ pub async fn store_user_data(
     Ok(())
 }
 
-pub async fn update_avatar(
+// Returns false if another time keeper took the avatar, the unique index settles concurrent claims.
+pub fn update_avatar(
     conn: &mut Conn,
     addr: &Address,
-    avatar: &String,
-) -> Result<(), Box<dyn Error>> {
+    avatar: &str,
+) -> Result<bool, Box<dyn Error>> {
     check_conn(conn);
-    let (address, trunc_address) = get_address_strings(addr);
-    conn.exec_drop(
-        "UPDATE whitelisted_addresses SET address = ?, avatar = ? WHERE address = ? OR address = ?",
-        (&address, avatar, &address, trunc_address),
-    )?;
-    Ok(())
+    let _timer = METRICS.db_timer("update_avatar");
+    let res = conn.exec_drop(
+        "UPDATE whitelisted_addresses SET avatar = ? WHERE address = ?",
+        (avatar, format!("{:#x}", addr)),
+    );
+    match res {
+        Err(mysql::Error::MySqlError(err)) if err.code == ER_DUP_ENTRY => Ok(false),
+        res => res.map(|_| true).map_err(Into::into),
+    }
 }
 
-pub async fn update_referral_code(
+// Returns false if another time keeper took the code, the unique index settles concurrent claims.
+pub fn update_referral_code(
     conn: &mut Conn,
     addr: &Address,
-    referral_code: &String,
-) -> Result<(), Box<dyn Error>> {
+    referral_code: &str,
+) -> Result<bool, Box<dyn Error>> {
     check_conn(conn);
-    let (address, trunc_address) = get_address_strings(addr);
-    let res: Option<Result<String, FromRowError>> = conn.exec_first_opt(
-        "SELECT referral_code FROM whitelisted_addresses WHERE (address = ? OR address = ?)",
-        (&address, &trunc_address),
+    let _timer = METRICS.db_timer("update_referral_code");
+    let address = format!("{:#x}", addr);
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    let existing: Option<Option<String>> = tx.exec_first(
+        "SELECT referral_code FROM whitelisted_addresses WHERE address = ? FOR UPDATE",
+        (&address,),
     )?;
-    if let Some(existing_ref_code_res) = res {
-        if let Ok(existing_ref_code) = existing_ref_code_res {
-            conn.exec_drop(
-                "UPDATE whitelisted_addresses SET referred_from = ? WHERE referred_from = ?",
-                (referral_code, existing_ref_code),
-            )?;
-        }
+    let res = tx.exec_drop(
+        "UPDATE whitelisted_addresses SET referral_code = ? WHERE address = ?",
+        (referral_code, &address),
+    );
+    match res {
+        Err(mysql::Error::MySqlError(err)) if err.code == ER_DUP_ENTRY => return Ok(false),
+        res => res?,
     }
-    conn.exec_drop(
-        "UPDATE whitelisted_addresses SET address = ?, referral_code = ? WHERE address = ? OR address = ?",
-        (&address, referral_code, &address, trunc_address),
-    )?;
-    Ok(())
+    if let Some(Some(existing_ref_code)) = existing {
+        tx.exec_drop(
+            "UPDATE whitelisted_addresses SET referred_from = ? WHERE referred_from = ?",
+            (referral_code, existing_ref_code),
+        )?;
+    }
+    tx.commit()?;
+    Ok(true)
 }
 
-pub async fn update_referred_from(
+pub fn update_referred_from(
     conn: &mut Conn,
     addr: &Address,
-    referred_from: &String,
+    referred_from: &str,
 ) -> Result<(), Box<dyn Error>> {
     check_conn(conn);
-    let (address, trunc_address) = get_address_strings(addr);
+    let _timer = METRICS.db_timer("update_referred_from");
     conn.exec_drop(
-        "UPDATE whitelisted_addresses SET address = ?, referred_from = ? WHERE address = ? OR address = ?",
-        (&address, referred_from, &address, trunc_address),
+        "UPDATE whitelisted_addresses
+            SET referred_from = ?, referred_at = COALESCE(referred_at, CURRENT_TIMESTAMP)
+            WHERE address = ?",
+        (referred_from, format!("{:#x}", addr)),
     )?;
     Ok(())
 }
 
-pub async fn is_address_whitelisted(
-    conn: &mut Conn,
-    addr: &Address,
-) -> Result<bool, Box<dyn Error>> {
+// Unix time when the referrer of the time keeper was first set.
+pub fn read_referred_at(conn: &mut Conn, addr: &Address) -> Result<Option<u64>, Box<dyn Error>> {
     check_conn(conn);
-    let (address, trunc_address) = get_address_strings(addr);
-    let res: Option<String> = conn.exec_first(
-        "SELECT address FROM whitelisted_addresses WHERE address = ? OR address = ?",
-        (address, trunc_address),
+    let _timer = METRICS.db_timer("read_referred_at");
+    let res: Option<Option<u64>> = conn.exec_first(
+        "SELECT UNIX_TIMESTAMP(referred_at) FROM whitelisted_addresses WHERE address = ?",
+        (format!("{:#x}", addr),),
     )?;
-    if let Some(_) = res {
-        return Ok(true);
-    }
-
-    Ok(false)
+    Ok(res.flatten())
 }
 
-pub async fn is_avatar_available(
-    conn: &mut Conn,
-    addr: &Address,
-    avatar: &String,
-) -> Result<bool, Box<dyn Error>> {
+pub fn is_address_whitelisted(conn: &mut Conn, addr: &Address) -> Result<bool, Box<dyn Error>> {
     check_conn(conn);
-    let (address, trunc_address) = get_address_strings(addr);
+    let _timer = METRICS.db_timer("is_address_whitelisted");
     let res: Option<String> = conn.exec_first(
-        "SELECT address FROM whitelisted_addresses WHERE (address != ? AND address != ?) AND avatar = ?",
-        (address, trunc_address, avatar),
+        "SELECT address FROM whitelisted_addresses WHERE address = ?",
+        (format!("{:#x}", addr),),
     )?;
-    if let Some(_) = res {
-        return Ok(false);
+    if res.is_some() {
+        return Ok(true);
     }
-    Ok(true)
+
+    Ok(false)
 }
 
-pub async fn is_referral_code_available(
+pub fn is_avatar_available(
     conn: &mut Conn,
     addr: &Address,
-    referral_code: &String,
+    avatar: &str,
 ) -> Result<bool, Box<dyn Error>> {
     check_conn(conn);
-
-    let (address, trunc_address) = get_address_strings(addr);
+    let _timer = METRICS.db_timer("is_avatar_available");
     let res: Option<String> = conn.exec_first(
-        "SELECT address FROM whitelisted_addresses WHERE referral_code = ? AND (address != ? AND address != ?)",
-        (referral_code, address, trunc_address),
+        "SELECT address FROM whitelisted_addresses WHERE address != ? AND avatar = ?",
+        (format!("{:#x}", addr), avatar),
     )?;
-    if let Some(_) = res {
+    if res.is_some() {
         return Ok(false);
     }
     Ok(true)
 }
 
-pub async fn get_time_keepers_count(conn: &mut Conn) -> Result<u64, Box<dyn Error>> {
+pub fn get_time_keepers_count(conn: &mut Conn) -> Result<u64, Box<dyn Error>> {
     check_conn(conn);
+    let _timer = METRICS.db_timer("get_time_keepers_count");
     let res: Option<u64> =
         conn.exec_first("SELECT count(address) FROM whitelisted_addresses", ())?;
     if let Some(tk_count) = res {
@@ -152,14 +154,21 @@ pub async fn get_time_keepers_count(conn: &mut Conn) -> Result<u64, Box<dyn Erro
     Ok(0)
 }
 
+pub fn ping(conn: &mut Conn) -> Result<(), Box<dyn Error>> {
+    let _timer = METRICS.db_timer("ping");
+    conn.query_drop("SELECT 1")?;
+    Ok(())
+}
+
 pub fn check_conn(conn: &mut Conn) {
-    if let Err(_) = conn.ping() {
+    if conn.ping().is_err() {
         let _ = conn.reset();
     }
 }
 
-pub fn read_referral(conn: &mut Conn, ref_key: &String) -> Result<String, Box<dyn Error>> {
+pub fn read_referral(conn: &mut Conn, ref_key: &str) -> Result<String, Box<dyn Error>> {
     check_conn(conn);
+    let _timer = METRICS.db_timer("read_referral");
     let res: Option<String> = conn.exec_first(
         "SELECT refvalue FROM referrals WHERE refkey = ?",
         (ref_key,),
@@ -172,6 +181,7 @@ pub fn read_referral(conn: &mut Conn, ref_key: &String) -> Result<String, Box<dy
 
 pub fn write_referral(conn: &mut Conn, ref_data: &ReferralData) -> Result<(), Box<dyn Error>> {
     check_conn(conn);
+    let _timer = METRICS.db_timer("write_referral");
     if read_referral(conn, &ref_data.refkey)?.is_empty() {
         conn.exec_drop(
             "INSERT INTO referrals (refkey, refvalue) VALUES (?, ?)",
@@ -180,3 +190,713 @@ pub fn write_referral(conn: &mut Conn, ref_data: &ReferralData) -> Result<(), Bo
     }
     Ok(())
 }
+
+// Stores a seen chronicle signature. Returns false if the signature was already stored.
+pub fn store_seen_signature(
+    conn: &mut Conn,
+    signature: &Bytes,
+    epoch: &U256,
+) -> Result<bool, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("store_seen_signature");
+    conn.exec_drop(
+        "INSERT IGNORE INTO seen_signatures (signature, epoch) VALUES (?, ?)",
+        (signature.to_string(), epoch.as_u64()),
+    )?;
+    Ok(conn.affected_rows() > 0)
+}
+
+// The update only matches a lower stored nonce, so concurrent chronicles can't both advance to the same one.
+pub fn advance_chronicle_nonce(
+    conn: &mut Conn,
+    addr: &Address,
+    nonce: u64,
+) -> Result<bool, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("advance_chronicle_nonce");
+    let address = format!("{:#x}", addr);
+    conn.exec_drop(
+        "INSERT IGNORE INTO chronicle_nonces (time_keeper, nonce) VALUES (?, ?)",
+        (&address, nonce),
+    )?;
+    if conn.affected_rows() > 0 {
+        return Ok(true);
+    }
+    conn.exec_drop(
+        "UPDATE chronicle_nonces SET nonce = ? WHERE time_keeper = ? AND nonce < ?",
+        (nonce, &address, nonce),
+    )?;
+    Ok(conn.affected_rows() > 0)
+}
+
+pub fn read_seen_signatures(conn: &mut Conn, limit: usize) -> Result<Vec<String>, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("read_seen_signatures");
+    let res: Vec<String> = conn.exec(
+        "SELECT signature FROM seen_signatures ORDER BY epoch DESC LIMIT ?",
+        (limit as u64,),
+    )?;
+    Ok(res)
+}
+
+pub fn prune_seen_signatures(conn: &mut Conn, min_epoch: u64) -> Result<(), Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("prune_seen_signatures");
+    conn.exec_drop("DELETE FROM seen_signatures WHERE epoch < ?", (min_epoch,))?;
+    Ok(())
+}
+
+pub fn read_user_objective_nonce(
+    conn: &mut Conn,
+    chain_id: u64,
+    solver: &Address,
+) -> Result<Option<u64>, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("read_user_objective_nonce");
+    let res: Option<u64> = conn.exec_first(
+        "SELECT next_nonce FROM user_objective_nonces WHERE chain_id = ? AND solver = ?",
+        (chain_id, format!("{:#x}", solver)),
+    )?;
+    Ok(res)
+}
+
+pub fn store_user_objective_nonce(
+    conn: &mut Conn,
+    chain_id: u64,
+    solver: &Address,
+    next_nonce: u64,
+) -> Result<(), Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("store_user_objective_nonce");
+    conn.exec_drop(
+        "INSERT INTO user_objective_nonces (chain_id, solver, next_nonce) VALUES (?, ?, ?)
+            ON DUPLICATE KEY UPDATE next_nonce = VALUES(next_nonce)",
+        (chain_id, format!("{:#x}", solver), next_nonce),
+    )?;
+    Ok(())
+}
+
+pub fn read_released_nonces(
+    conn: &mut Conn,
+    chain_id: u64,
+    solver: &Address,
+) -> Result<Vec<u64>, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("read_released_nonces");
+    let res: Vec<u64> = conn.exec(
+        "SELECT nonce FROM released_user_objective_nonces WHERE chain_id = ? AND solver = ?",
+        (chain_id, format!("{:#x}", solver)),
+    )?;
+    Ok(res)
+}
+
+pub fn store_released_nonce(
+    conn: &mut Conn,
+    chain_id: u64,
+    solver: &Address,
+    nonce: u64,
+) -> Result<(), Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("store_released_nonce");
+    conn.exec_drop(
+        "INSERT IGNORE INTO released_user_objective_nonces (chain_id, solver, nonce) VALUES (?, ?, ?)",
+        (chain_id, format!("{:#x}", solver), nonce),
+    )?;
+    Ok(())
+}
+
+pub fn delete_released_nonce(
+    conn: &mut Conn,
+    chain_id: u64,
+    solver: &Address,
+    nonce: u64,
+) -> Result<(), Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("delete_released_nonce");
+    conn.exec_drop(
+        "DELETE FROM released_user_objective_nonces WHERE chain_id = ? AND solver = ? AND nonce = ?",
+        (chain_id, format!("{:#x}", solver), nonce),
+    )?;
+    Ok(())
+}
+
+pub fn read_pending_tx(conn: &mut Conn, chain_id: u64) -> Result<Option<String>, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("read_pending_tx");
+    let res: Option<String> = conn.exec_first(
+        "SELECT tx FROM pending_transactions WHERE chain_id = ?",
+        (chain_id,),
+    )?;
+    Ok(res)
+}
+
+pub fn store_pending_tx(conn: &mut Conn, chain_id: u64, tx: &str) -> Result<(), Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("store_pending_tx");
+    conn.exec_drop(
+        "INSERT INTO pending_transactions (chain_id, tx) VALUES (?, ?)
+            ON DUPLICATE KEY UPDATE tx = VALUES(tx)",
+        (chain_id, tx),
+    )?;
+    Ok(())
+}
+
+pub fn delete_pending_tx(conn: &mut Conn, chain_id: u64) -> Result<(), Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("delete_pending_tx");
+    conn.exec_drop(
+        "DELETE FROM pending_transactions WHERE chain_id = ?",
+        (chain_id,),
+    )?;
+    Ok(())
+}
+
+pub fn store_confirmed_tick(
+    conn: &mut Conn,
+    chain_id: u64,
+    mean_time: &U256,
+    chronicles: &str,
+) -> Result<(), Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("store_confirmed_tick");
+    conn.exec_drop(
+        "INSERT INTO confirmed_ticks (chain_id, mean_time, chronicles) VALUES (?, ?, ?)
+            ON DUPLICATE KEY UPDATE mean_time = VALUES(mean_time), chronicles = VALUES(chronicles)",
+        (chain_id, mean_time.as_u64(), chronicles),
+    )?;
+    Ok(())
+}
+
+// Mean time and JSON encoded chronicles of the last confirmed tick on the chain.
+pub fn read_confirmed_tick(
+    conn: &mut Conn,
+    chain_id: u64,
+) -> Result<Option<(U256, String)>, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("read_confirmed_tick");
+    let res: Option<(u64, String)> = conn.exec_first(
+        "SELECT mean_time, chronicles FROM confirmed_ticks WHERE chain_id = ?",
+        (chain_id,),
+    )?;
+    Ok(res.map(|(mean_time, chronicles)| (mean_time.into(), chronicles)))
+}
+
+pub fn store_reward_events(
+    conn: &mut Conn,
+    chain_id: u64,
+    mean_time: &U256,
+    chronicles_md5: &str,
+    shares: &[RewardShare],
+    status: RewardStatus,
+    reason: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("store_reward_events");
+    conn.exec_batch(
+        "INSERT INTO reward_events
+            (chain_id, mean_time, chronicles_md5, receiver, amount, referral_level, status, reason)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        shares.iter().map(|share| {
+            (
+                chain_id,
+                mean_time.as_u64(),
+                chronicles_md5,
+                format!("{:#x}", share.receiver),
+                share.amount.to_string(),
+                share.level,
+                status.as_str(),
+                reason,
+            )
+        }),
+    )?;
+    Ok(())
+}
+
+// Settles the pending rewards of a tick on a chain.
+pub fn update_reward_events(
+    conn: &mut Conn,
+    chain_id: u64,
+    chronicles_md5: &str,
+    status: RewardStatus,
+    tx_hash: Option<H256>,
+    reason: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("update_reward_events");
+    conn.exec_drop(
+        "UPDATE reward_events SET status = ?, tx_hash = ?, reason = ?
+            WHERE chain_id = ? AND status = ?
+            AND ((chronicles_md5 = ? AND settled_in IS NULL) OR settled_in = ?)",
+        (
+            status.as_str(),
+            tx_hash.map(|tx_hash| format!("{:#x}", tx_hash)),
+            reason,
+            chain_id,
+            RewardStatus::Pending.as_str(),
+            chronicles_md5,
+            chronicles_md5,
+        ),
+    )?;
+    Ok(())
+}
+
+pub fn read_reward_events(
+    conn: &mut Conn,
+    receiver: &Address,
+    offset: u64,
+    limit: u64,
+) -> Result<Vec<RewardEvent>, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("read_reward_events");
+    let rows: Vec<Row> = conn.exec(
+        "SELECT id, chain_id, CAST(mean_time AS CHAR), chronicles_md5, receiver, CAST(amount AS CHAR),
+                referral_level, tx_hash, status, reason, UNIX_TIMESTAMP(created_at)
+            FROM reward_events
+            WHERE receiver = ?
+            ORDER BY id DESC
+            LIMIT ? OFFSET ?",
+        (format!("{:#x}", receiver), limit, offset),
+    )?;
+    let mut events = Vec::with_capacity(rows.len());
+    for row in rows {
+        let status: String = row.get(8).unwrap_or_default();
+        events.push(RewardEvent {
+            id: row.get(0).unwrap_or_default(),
+            chain_id: row.get(1).unwrap_or_default(),
+            mean_time: row.get(2).unwrap_or_default(),
+            chronicles_md5: row.get(3).unwrap_or_default(),
+            receiver: row.get(4).unwrap_or_default(),
+            amount: row.get(5).unwrap_or_default(),
+            referral_level: row.get(6).unwrap_or_default(),
+            tx_hash: row.get(7).unwrap_or_default(),
+            status: RewardStatus::from_str(&status)?,
+            reason: row.get(9).unwrap_or_default(),
+            created_at: row.get(10).unwrap_or_default(),
+        });
+    }
+    Ok(events)
+}
+
+// Sums of the rewards of a receiver by status.
+pub fn read_reward_totals(
+    conn: &mut Conn,
+    receiver: &Address,
+) -> Result<Vec<(RewardStatus, String)>, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("read_reward_totals");
+    let rows: Vec<(String, String)> = conn.exec(
+        "SELECT status, CAST(SUM(amount) AS CHAR)
+            FROM reward_events
+            WHERE receiver = ?
+            GROUP BY status",
+        (format!("{:#x}", receiver),),
+    )?;
+    let mut totals = Vec::with_capacity(rows.len());
+    for (status, sum) in rows {
+        totals.push((RewardStatus::from_str(&status)?, sum));
+    }
+    Ok(totals)
+}
+
+// Releases the accrued rewards for settlement, returns the number of released rewards.
+pub fn queue_reward_backlog(conn: &mut Conn, chain_id: Option<u64>) -> Result<u64, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("queue_reward_backlog");
+    conn.exec_drop(
+        "UPDATE reward_events SET status = ?
+            WHERE status = ? AND (? IS NULL OR chain_id = ?)",
+        (
+            RewardStatus::Queued.as_str(),
+            RewardStatus::Accrued.as_str(),
+            chain_id,
+            chain_id,
+        ),
+    )?;
+    Ok(conn.affected_rows())
+}
+
+// Marks the queued rewards of the oldest receivers as settled in the tick, returns their sums.
+pub fn claim_reward_backlog(
+    conn: &mut Conn,
+    chain_id: u64,
+    chronicles_md5: &str,
+    max_receivers: usize,
+) -> Result<Vec<(Address, U256)>, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("claim_reward_backlog");
+    let receivers: Vec<String> = conn.exec(
+        "SELECT receiver FROM reward_events
+            WHERE chain_id = ? AND status = ?
+            GROUP BY receiver
+            ORDER BY MIN(id)
+            LIMIT ?",
+        (
+            chain_id,
+            RewardStatus::Queued.as_str(),
+            max_receivers as u64,
+        ),
+    )?;
+    if receivers.is_empty() {
+        return Ok(Vec::new());
+    }
+    let placeholders = vec!["?"; receivers.len()].join(",");
+    let mut params: Vec<mysql::Value> = vec![
+        RewardStatus::Pending.as_str().into(),
+        chronicles_md5.into(),
+        chain_id.into(),
+        RewardStatus::Queued.as_str().into(),
+    ];
+    params.extend(receivers.iter().map(|el| el.as_str().into()));
+    conn.exec_drop(
+        format!(
+            "UPDATE reward_events SET status = ?, settled_in = ?
+                WHERE chain_id = ? AND status = ? AND receiver IN({})",
+            placeholders
+        ),
+        params,
+    )?;
+    let rows: Vec<(String, String)> = conn.exec(
+        "SELECT receiver, CAST(SUM(amount) AS CHAR) FROM reward_events
+            WHERE chain_id = ? AND status = ? AND settled_in = ?
+            GROUP BY receiver",
+        (chain_id, RewardStatus::Pending.as_str(), chronicles_md5),
+    )?;
+    let mut backlog = Vec::with_capacity(rows.len());
+    for (receiver, amount) in rows {
+        backlog.push((Address::from_str(&receiver)?, U256::from_dec_str(&amount)?));
+    }
+    Ok(backlog)
+}
+
+// Puts the backlog claimed by a failed tick back into the queue.
+pub fn release_reward_backlog(
+    conn: &mut Conn,
+    chain_id: u64,
+    chronicles_md5: &str,
+) -> Result<(), Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("release_reward_backlog");
+    conn.exec_drop(
+        "UPDATE reward_events SET status = ?, settled_in = NULL
+            WHERE chain_id = ? AND status = ? AND settled_in = ?",
+        (
+            RewardStatus::Queued.as_str(),
+            chain_id,
+            RewardStatus::Pending.as_str(),
+            chronicles_md5,
+        ),
+    )?;
+    Ok(())
+}
+
+pub fn read_keeper(conn: &mut Conn, addr: &Address) -> Result<Option<Keeper>, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("read_keeper");
+    let res: Option<KeeperRow> = conn.exec_first(
+        "SELECT address, avatar, referral_code, referred_from FROM whitelisted_addresses
+            WHERE address = ?",
+        (format!("{:#x}", addr),),
+    )?;
+    Ok(res.map(keeper_from_row))
+}
+
+pub fn read_keeper_by_referral_code(
+    conn: &mut Conn,
+    referral_code: &str,
+) -> Result<Option<Keeper>, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("read_keeper_by_referral_code");
+    let res: Option<KeeperRow> = conn.exec_first(
+        "SELECT address, avatar, referral_code, referred_from FROM whitelisted_addresses
+            WHERE referral_code = ?",
+        (referral_code,),
+    )?;
+    Ok(res.map(keeper_from_row))
+}
+
+// Referees of the time keeper within the depth, paginated in the order of the level and the address.
+// Referral codes are unique, so each referee has one parent and only the time keeper can close a
+// cycle. Returns the number of referees within the depth with the page.
+pub fn read_downline(
+    conn: &mut Conn,
+    keeper: &Keeper,
+    depth: u32,
+    offset: u64,
+    limit: u64,
+) -> Result<(u64, Vec<Link>), Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("read_downline");
+    let Some(referral_code) = keeper.referral_code() else {
+        return Ok((0, Vec::new()));
+    };
+    let downline = "WITH RECURSIVE downline AS (
+            SELECT address, avatar, referral_code, referred_from, 1 AS level,
+                    CAST(? AS CHAR(42)) AS parent
+                FROM whitelisted_addresses
+                WHERE referred_from = ? AND address != ?
+            UNION ALL
+            SELECT w.address, w.avatar, w.referral_code, w.referred_from, d.level + 1, d.address
+                FROM downline d
+                JOIN whitelisted_addresses w ON w.referred_from = d.referral_code
+                WHERE d.level < ? AND d.referral_code != '' AND w.address != ?
+        )";
+    let params = (
+        &keeper.address,
+        referral_code,
+        &keeper.address,
+        depth,
+        &keeper.address,
+    );
+    let total: Option<u64> = conn.exec_first(
+        format!("{} SELECT COUNT(*) FROM downline", downline),
+        params,
+    )?;
+    let rows: Vec<DownlineRow> = conn.exec(
+        format!(
+            "{} SELECT address, avatar, referral_code, referred_from, level, parent
+                    FROM downline
+                    ORDER BY level, address
+                    LIMIT ? OFFSET ?",
+            downline
+        ),
+        (
+            params.0, params.1, params.2, params.3, params.4, limit, offset,
+        ),
+    )?;
+    let links = rows
+        .into_iter()
+        .map(
+            |(address, avatar, referral_code, referred_from, level, parent)| {
+                let keeper = keeper_from_row((address, avatar, referral_code, referred_from));
+                (keeper, level, parent)
+            },
+        )
+        .collect();
+    Ok((total.unwrap_or_default(), links))
+}
+
+// Direct referees of the time keepers and how many of them got a time keeper reward within the period.
+pub fn read_referral_stats(
+    conn: &mut Conn,
+    addresses: &[&str],
+    active_period: Duration,
+) -> Result<HashMap<String, (u64, u64)>, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("read_referral_stats");
+    if addresses.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let placeholders = vec!["?"; addresses.len()].join(",");
+    let mut params: Vec<mysql::Value> = vec![active_period.as_secs().into()];
+    params.extend(addresses.iter().map(|el| (*el).into()));
+    let rows: Vec<(String, u64, u64)> = conn.exec(
+        format!(
+            "SELECT referrer.address, COUNT(DISTINCT referee.address), COUNT(DISTINCT reward.receiver)
+                FROM whitelisted_addresses AS referrer
+                JOIN whitelisted_addresses AS referee ON referee.referred_from = referrer.referral_code
+                LEFT JOIN reward_events AS reward ON reward.receiver = referee.address
+                    AND reward.referral_level = 0
+                    AND reward.created_at > NOW() - INTERVAL ? SECOND
+                WHERE referrer.address IN({}) AND referrer.referral_code != ''
+                GROUP BY referrer.address",
+            placeholders
+        ),
+        params,
+    )?;
+    Ok(rows
+        .into_iter()
+        .map(|(address, referees, active)| (address, (referees, active)))
+        .collect())
+}
+
+// Sums of the confirmed referral rewards of the receivers.
+pub fn read_referral_earnings(
+    conn: &mut Conn,
+    addresses: &[&str],
+) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("read_referral_earnings");
+    if addresses.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let placeholders = vec!["?"; addresses.len()].join(",");
+    let mut params: Vec<mysql::Value> = vec![RewardStatus::Confirmed.as_str().into()];
+    params.extend(addresses.iter().map(|el| (*el).into()));
+    let rows: Vec<(String, String)> = conn.exec(
+        format!(
+            "SELECT receiver, CAST(SUM(amount) AS CHAR) FROM reward_events
+                WHERE referral_level > 0 AND status = ? AND receiver IN({})
+                GROUP BY receiver",
+            placeholders
+        ),
+        params,
+    )?;
+    Ok(rows.into_iter().collect())
+}
+
+// An account with its direct referrer and the referrer's referral code.
+pub type ReferrerLink = (String, String, String);
+
+pub fn read_referrers(
+    conn: &mut Conn,
+    addresses: &[String],
+) -> Result<Vec<ReferrerLink>, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("read_referrers");
+    if addresses.is_empty() {
+        return Ok(Vec::new());
+    }
+    let stmt = format!(
+        "SELECT a1.address, a2.address, a2.referral_code
+            FROM whitelisted_addresses AS a1
+            JOIN whitelisted_addresses AS a2
+            ON a2.referral_code = a1.referred_from
+            WHERE a1.address IN({})
+            AND NULLIF(a1.referred_from, '') IS NOT NULL",
+        vec!["?"; addresses.len()].join(",")
+    );
+    let res: Vec<ReferrerLink> = conn.exec(stmt, addresses.to_vec())?;
+    Ok(res)
+}
+
+// The version of the last applied migration, 0 for an empty database. Creates the version table on first use.
+pub fn read_schema_version(conn: &mut Conn) -> Result<u32, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("read_schema_version");
+    conn.query_drop(
+        "CREATE TABLE IF NOT EXISTS schema_version(
+            version INT UNSIGNED NOT NULL,
+            name VARCHAR(255) NOT NULL,
+            applied_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (version)
+        )",
+    )?;
+    let res: Option<Option<u32>> = conn.query_first("SELECT MAX(version) FROM schema_version")?;
+    Ok(res.flatten().unwrap_or_default())
+}
+
+// MySQL commits the schema changes right away, so the version is stored after the last statement succeeds.
+pub fn apply_migration(
+    conn: &mut Conn,
+    version: u32,
+    name: &str,
+    statements: &[String],
+) -> Result<(), Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("apply_migration");
+    for statement in statements {
+        conn.query_drop(statement)?;
+    }
+    conn.exec_drop(
+        "INSERT INTO schema_version (version, name) VALUES (?, ?)",
+        (version, name),
+    )?;
+    Ok(())
+}
+
+// The schema changes aren't transactional, a failed migration is applied again over the columns
+// it already added.
+pub fn add_referred_at_column(conn: &mut Conn) -> Result<(), Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("add_referred_at_column");
+    let exists: Option<u64> = conn.query_first(
+        "SELECT 1 FROM information_schema.COLUMNS
+            WHERE TABLE_SCHEMA = DATABASE()
+                AND TABLE_NAME = 'whitelisted_addresses'
+                AND COLUMN_NAME = 'referred_at'",
+    )?;
+    if exists.is_none() {
+        conn.query_drop("ALTER TABLE whitelisted_addresses ADD COLUMN referred_at TIMESTAMP NULL")?;
+    }
+    Ok(())
+}
+
+type AddressRowTuple = (
+    String,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<u64>,
+);
+
+pub fn read_address_rows(conn: &mut Conn) -> Result<Vec<AddressRow>, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("read_address_rows");
+    let rows: Vec<AddressRowTuple> = conn.query(
+        "SELECT address, avatar, referral_code, referred_from, UNIX_TIMESTAMP(referred_at)
+            FROM whitelisted_addresses",
+    )?;
+    Ok(rows
+        .into_iter()
+        .map(
+            |(address, avatar, referral_code, referred_from, referred_at)| AddressRow {
+                address,
+                avatar,
+                referral_code,
+                referred_from,
+                referred_at,
+            },
+        )
+        .collect())
+}
+
+pub fn read_reward_receivers(conn: &mut Conn) -> Result<Vec<String>, Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("read_reward_receivers");
+    let res: Vec<String> = conn.query("SELECT DISTINCT receiver FROM reward_events")?;
+    Ok(res)
+}
+
+// Replaces the rows with the merged one in one transaction.
+pub fn merge_address_rows(conn: &mut Conn, merge: &AddressMerge) -> Result<(), Box<dyn Error>> {
+    check_conn(conn);
+    let _timer = METRICS.db_timer("merge_address_rows");
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    tx.exec_drop(
+        format!(
+            "DELETE FROM whitelisted_addresses WHERE address IN ({})",
+            vec!["?"; merge.rows.len()].join(",")
+        ),
+        merge.rows.clone(),
+    )?;
+    let keeper = &merge.keeper;
+    tx.exec_drop(
+        "INSERT INTO whitelisted_addresses (address, avatar, referral_code, referred_from, referred_at)
+            VALUES (?, ?, ?, ?, FROM_UNIXTIME(?))",
+        (
+            &keeper.address,
+            &keeper.avatar,
+            &keeper.referral_code,
+            &keeper.referred_from,
+            keeper.referred_at,
+        ),
+    )?;
+    for code in merge.dropped_codes.iter() {
+        tx.exec_drop(
+            "UPDATE whitelisted_addresses SET referred_from = ? WHERE referred_from = ?",
+            (&keeper.referral_code, code),
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+type KeeperRow = (String, Option<String>, Option<String>, Option<String>);
+
+type DownlineRow = (
+    String,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    u32,
+    String,
+);
+
+fn keeper_from_row((address, avatar, referral_code, referred_from): KeeperRow) -> Keeper {
+    Keeper {
+        address,
+        avatar,
+        referral_code,
+        referred_from,
+    }
+}
@@ -0,0 +1,201 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::providers::Middleware;
+use futures_util::future::join_all;
+use serde::Serialize;
+use serde_json::json;
+use tokio::time::timeout;
+
+use crate::store::{KeeperStore, StorePool};
+
+// End of the last completed tick, the beats stop if the ticker dies or a tick hangs.
+#[derive(Clone, Default)]
+pub struct TickerHeartbeat(Arc<Mutex<Option<Instant>>>);
+
+impl TickerHeartbeat {
+    pub fn beat(&self) {
+        if let Ok(mut last_beat) = self.0.lock() {
+            *last_beat = Some(Instant::now());
+        }
+    }
+
+    fn last_beat(&self) -> Option<Instant> {
+        self.0.lock().ok().and_then(|el| *el)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckReport {
+    pub name: String,
+    pub status: CheckStatus,
+    pub message: Option<String>,
+}
+
+impl CheckReport {
+    fn new(name: String, res: Result<(), String>) -> CheckReport {
+        let (status, message) = match res {
+            Ok(()) => (CheckStatus::Ok, None),
+            Err(err) => (CheckStatus::Failed, Some(err)),
+        };
+        CheckReport {
+            name,
+            status,
+            message,
+        }
+    }
+}
+
+// Dependencies the service can't work without.
+pub struct Readiness<S, M> {
+    pub db: S,
+    // Configured chain id with the client of the chain.
+    pub chains: Vec<(u64, Arc<M>)>,
+    pub heartbeat: TickerHeartbeat,
+    // The ticker is considered dead if no tick completed for longer.
+    pub max_tick_age: Duration,
+    // Bounds each check, an unreachable dependency mustn't hang the probe.
+    pub check_timeout: Duration,
+}
+
+impl<S: StorePool, M: Middleware> Readiness<S, M> {
+    pub async fn check(&self) -> Vec<CheckReport> {
+        let mut reports = vec![CheckReport::new(
+            "database".to_string(),
+            self.with_timeout(self.check_db()).await,
+        )];
+        let chains = self.chains.iter().map(|(chain_id, client)| async move {
+            CheckReport::new(
+                format!("chain_{}", chain_id),
+                self.with_timeout(check_chain_id(*chain_id, client)).await,
+            )
+        });
+        reports.extend(join_all(chains).await);
+        reports.push(CheckReport::new("ticker".to_string(), self.check_ticker()));
+        reports
+    }
+
+    async fn check_db(&self) -> Result<(), String> {
+        self.db
+            .with_store(|store| store.ping())
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    fn check_ticker(&self) -> Result<(), String> {
+        let last_beat = self.heartbeat.last_beat().ok_or("No tick completed yet")?;
+        let age = last_beat.elapsed();
+        if age > self.max_tick_age {
+            return Err(format!("The last tick completed {:?} ago", age));
+        }
+        Ok(())
+    }
+
+    async fn with_timeout(
+        &self,
+        check: impl Future<Output = Result<(), String>>,
+    ) -> Result<(), String> {
+        timeout(self.check_timeout, check)
+            .await
+            .unwrap_or_else(|_| Err(format!("Timed out after {:?}", self.check_timeout)))
+    }
+}
+
+async fn check_chain_id<M: Middleware>(chain_id: u64, client: &M) -> Result<(), String> {
+    let remote_id = client.get_chainid().await.map_err(|err| err.to_string())?;
+    if remote_id != chain_id.into() {
+        return Err(format!(
+            "The node is on the chain {} instead of {}",
+            remote_id, chain_id
+        ));
+    }
+    Ok(())
+}
+
+pub async fn handle_healthz() -> Json<serde_json::Value> {
+    Json(json!({"status": "ok"}))
+}
+
+pub async fn handle_readyz<S: StorePool, M: Middleware>(
+    readiness: Arc<Readiness<S, M>>,
+) -> Response {
+    let checks = readiness.check().await;
+    let is_ready = checks.iter().all(|el| el.status == CheckStatus::Ok);
+    let (code, status) = if is_ready {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not_ready")
+    };
+    (code, Json(json!({"status": status, "checks": checks}))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use ethers::{providers::Provider, types::U256};
+    use serde_json::json;
+
+    use crate::memory_store::MemoryPool;
+
+    use super::{Readiness, TickerHeartbeat};
+
+    #[tokio::test]
+    async fn test_readiness() -> Result<(), String> {
+        let (matching, matching_mock) = Provider::mocked();
+        matching_mock
+            .push(U256::from(21363))
+            .map_err(|err| err.to_string())?;
+        let (other, other_mock) = Provider::mocked();
+        other_mock
+            .push(U256::from(1))
+            .map_err(|err| err.to_string())?;
+        let readiness = Readiness {
+            db: MemoryPool::default(),
+            chains: vec![(21363, Arc::new(matching)), (84532, Arc::new(other))],
+            heartbeat: TickerHeartbeat::default(),
+            max_tick_age: Duration::from_secs(30),
+            check_timeout: Duration::from_secs(1),
+        };
+        let checks =
+            serde_json::to_value(readiness.check().await).map_err(|err| err.to_string())?;
+        assert_eq!(
+            checks,
+            json!([
+                {"name": "database", "status": "ok", "message": null},
+                {"name": "chain_21363", "status": "ok", "message": null},
+                {
+                    "name": "chain_84532",
+                    "status": "failed",
+                    "message": "The node is on the chain 1 instead of 84532"
+                },
+                {"name": "ticker", "status": "failed", "message": "No tick completed yet"},
+            ])
+        );
+
+        readiness.heartbeat.beat();
+        assert!(readiness.check_ticker().is_ok());
+        *readiness.heartbeat.0.lock().unwrap() =
+            Instant::now().checked_sub(Duration::from_secs(60));
+        assert!(readiness.check_ticker().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,306 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use ethers::types::{transaction::eip712::EIP712Domain, Address};
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{
+    select,
+    sync::{Mutex, Semaphore},
+    time::{interval, Instant, MissedTickBehavior},
+};
+
+use crate::{
+    rejection::Rejection,
+    replay_guard::ReplayGuard,
+    signed_request::SignedRequest,
+    store::StorePool,
+    time_pool::{add_time_sig, TimeSigInput, TimeSigPool, TimeSigRejection},
+    user_data::SessionData,
+};
+
+// A chronicle frame is a few hundred bytes.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024;
+// Missed heartbeats after which the connection is dropped.
+const MAX_MISSED_HEARTBEATS: u32 = 3;
+
+#[derive(Clone)]
+pub struct KeeperWsConfig {
+    pub heartbeat_interval: Duration,
+    // Permits for the open connections.
+    pub connections: Arc<Semaphore>,
+}
+
+// A chronicle with the id the client matches the ack by.
+#[derive(Debug, Deserialize)]
+struct KeeperFrame {
+    #[serde(default)]
+    id: Option<u64>,
+    #[serde(flatten)]
+    input: TimeSigInput,
+}
+
+pub async fn handle_keeper_ws<S: StorePool>(
+    ws: WebSocketUpgrade,
+    pool: Arc<Mutex<TimeSigPool>>,
+    db: S,
+    replay_guard: Arc<Mutex<ReplayGuard>>,
+    signing_domain: Arc<EIP712Domain>,
+    config: KeeperWsConfig,
+) -> Response {
+    let Ok(permit) = Arc::clone(&config.connections).try_acquire_owned() else {
+        warn!("Rejecting a keeper connection, all connections are in use");
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+    let session = KeeperSession {
+        pool,
+        db,
+        replay_guard,
+        signing_domain,
+        time_keeper: None,
+    };
+    ws.max_message_size(MAX_MESSAGE_SIZE)
+        .on_upgrade(move |socket| async move {
+            session.run(socket, config.heartbeat_interval).await;
+            drop(permit);
+        })
+}
+
+struct KeeperSession<S> {
+    pool: Arc<Mutex<TimeSigPool>>,
+    db: S,
+    replay_guard: Arc<Mutex<ReplayGuard>>,
+    signing_domain: Arc<EIP712Domain>,
+    // Set by the first frame, which must be a signed session request.
+    time_keeper: Option<Address>,
+}
+
+impl<S: StorePool> KeeperSession<S> {
+    // Frames are handled one at a time and the next one isn't read before the ack is sent,
+    // so a fast client is slowed down by the socket instead of queueing work here.
+    async fn run(mut self, mut socket: WebSocket, heartbeat_interval: Duration) {
+        let mut heartbeat = interval(heartbeat_interval);
+        heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut last_seen = Instant::now();
+        loop {
+            select! {
+                _ = heartbeat.tick() => {
+                    if last_seen.elapsed() > heartbeat_interval * MAX_MISSED_HEARTBEATS {
+                        warn!("Closing the idle keeper connection {:?}", self.time_keeper);
+                        break;
+                    }
+                    if socket.send(Message::Ping(Default::default())).await.is_err() {
+                        break;
+                    }
+                }
+                msg = socket.recv() => {
+                    let Some(Ok(msg)) = msg else {
+                        break;
+                    };
+                    last_seen = Instant::now();
+                    let (reply, is_closing) = match msg {
+                        Message::Text(text) => self.handle_frame(text.as_str()).await,
+                        Message::Binary(_) => (
+                            rejection(None, "malformed_input", "Frames must be JSON text"),
+                            false,
+                        ),
+                        Message::Close(_) => break,
+                        Message::Ping(_) | Message::Pong(_) => continue,
+                    };
+                    if socket.send(Message::Text(reply.to_string().into())).await.is_err() || is_closing {
+                        break;
+                    }
+                }
+            }
+        }
+        if let Some(time_keeper) = self.time_keeper {
+            info!("Keeper connection of {:#x} closed", time_keeper);
+        }
+    }
+
+    // Returns the reply and whether the connection closes after it.
+    async fn handle_frame(&mut self, frame: &str) -> (Value, bool) {
+        let Some(time_keeper) = self.time_keeper else {
+            return self.open_session(frame).await;
+        };
+        let frame: KeeperFrame = match serde_json::from_str(frame) {
+            Ok(frame) => frame,
+            Err(err) => {
+                let err = TimeSigRejection::MalformedInput(err.to_string());
+                return (rejection(None, err.code(), &err.message()), false);
+            }
+        };
+        if !frame.input.is_from(&time_keeper) {
+            return (
+                rejection(
+                    frame.id,
+                    "time_keeper_mismatch",
+                    "The chronicle isn't from the time keeper of the session",
+                ),
+                false,
+            );
+        }
+        let res = add_time_sig(
+            frame.input,
+            &self.pool,
+            &self.db,
+            &self.replay_guard,
+            &self.signing_domain,
+        )
+        .await;
+        match res {
+            Ok(()) => (json!({"id": frame.id, "status": "accepted"}), false),
+            Err(err) => (rejection(frame.id, err.code(), &err.message()), false),
+        }
+    }
+
+    async fn open_session(&mut self, frame: &str) -> (Value, bool) {
+        let request: SignedRequest<SessionData> = match serde_json::from_str(frame) {
+            Ok(request) => request,
+            Err(err) => {
+                let err = TimeSigRejection::MalformedInput(err.to_string());
+                return (rejection(None, err.code(), &err.message()), true);
+            }
+        };
+        if let Err(err) = request
+            .verify(&self.db, &self.replay_guard, &self.signing_domain)
+            .await
+        {
+            return (rejection(None, err.code(), &err.message()), true);
+        }
+        let time_keeper = request.data.time_keeper;
+        info!("Keeper connection of {:#x} opened", time_keeper);
+        self.time_keeper = Some(time_keeper);
+        (
+            json!({"status": "session_opened", "time_keeper": format!("{:#x}", time_keeper)}),
+            false,
+        )
+    }
+}
+
+fn rejection(id: Option<u64>, code: &str, message: &str) -> Value {
+    json!({
+        "id": id,
+        "status": "rejected",
+        "error": code,
+        "message": message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        str::FromStr,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use ethers::{
+        signers::{LocalWallet, Signer},
+        types::{Address, Bytes, U256},
+    };
+    use serde_json::json;
+    use tokio::sync::Mutex;
+
+    use crate::{
+        memory_store::MemoryPool, replay_guard::ReplayGuard, signed_request::SignedPayload,
+        time_signature::chronicle_domain, user_data::SessionData,
+    };
+
+    use super::KeeperSession;
+
+    #[tokio::test]
+    async fn test_keeper_session() -> Result<(), String> {
+        let wallet = LocalWallet::from_str(
+            "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )
+        .map_err(|err| err.to_string())?;
+        let db = MemoryPool::default();
+        db.0.lock()
+            .unwrap()
+            .insert_keeper(&wallet.address(), None, None);
+        let mut session = KeeperSession {
+            pool: Arc::new(Mutex::new(Vec::new())),
+            db,
+            replay_guard: Arc::new(Mutex::new(ReplayGuard::new(Duration::from_secs(60), 16))),
+            signing_domain: Arc::new(chronicle_domain(
+                21363,
+                Address::from_str("0xdD1B4D9337D0a8Ef2F133a39cC93EF85261b4A80").unwrap(),
+                &Bytes::from_str("0x01").unwrap(),
+            )),
+            time_keeper: None,
+        };
+        let curr_ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO);
+        let timestamp = U256::from(curr_ts.as_nanos());
+        let time_keeper = format!("{:#x}", wallet.address());
+
+        // Chronicles aren't accepted before the session is open.
+        let chronicle = |epoch: U256, signature: String, time_keeper: &str| {
+            json!({
+                "id": 1,
+                "epoch": epoch.to_string(),
+                "time_keeper": time_keeper,
+                "signature": signature,
+            })
+            .to_string()
+        };
+        let signature = wallet
+            .sign_message(timestamp.to_string())
+            .await
+            .map_err(|err| err.to_string())?;
+        let (reply, is_closing) = session
+            .handle_frame(&chronicle(timestamp, signature.to_string(), &time_keeper))
+            .await;
+        assert_eq!(reply["error"], "malformed_input");
+        assert!(is_closing);
+
+        let data = SessionData {
+            time_keeper: wallet.address(),
+            session: "client".to_string(),
+        };
+        let session_signature = wallet
+            .sign_message(data.message(&timestamp))
+            .await
+            .map_err(|err| err.to_string())?;
+        let request = json!({
+            "time_keeper": time_keeper,
+            "session": "client",
+            "timestamp": timestamp.to_string(),
+            "signature": session_signature.to_string(),
+        })
+        .to_string();
+        let (reply, is_closing) = session.handle_frame(&request).await;
+        assert_eq!(reply["status"], "session_opened");
+        assert!(!is_closing);
+
+        let (reply, _) = session
+            .handle_frame(&chronicle(timestamp, signature.to_string(), &time_keeper))
+            .await;
+        assert_eq!(reply, json!({"id": 1, "status": "accepted"}));
+        assert_eq!(session.pool.lock().await.len(), 1);
+        // The same chronicle again.
+        let (reply, _) = session
+            .handle_frame(&chronicle(timestamp, signature.to_string(), &time_keeper))
+            .await;
+        assert_eq!(reply["error"], "signature_replayed");
+        let (reply, _) = session
+            .handle_frame(&chronicle(
+                timestamp,
+                signature.to_string(),
+                &format!("{:#x}", Address::zero()),
+            ))
+            .await;
+        assert_eq!(reply["error"], "time_keeper_mismatch");
+        let (reply, is_closing) = session.handle_frame("{}").await;
+        assert_eq!(reply["error"], "malformed_input");
+        assert!(!is_closing);
+        Ok(())
+    }
+}
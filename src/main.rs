@@ -1,53 +1,125 @@
-use std::{error::Error, sync::Arc};
+use std::{fs, path::PathBuf, sync::Arc, time::SystemTime};
 
+use address_str::canonicalize_addresses;
 use axum::{
     http::{
         header::{ACCEPT, ACCEPT_LANGUAGE, CONTENT_LANGUAGE, CONTENT_TYPE},
         Method,
     },
+    middleware,
     routing::{get, post},
     serve, Json, Router,
 };
+use block_time_params::{watch_params, BlockTimeParamsCache};
 use call_breaker::CallBreakerData;
+use chain_target::ChainTarget;
 use claim_avatar::handle_claim_avatar;
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, Subcommand};
+use consensus::{ConsensusConfig, ConsensusMode, VotePolicy};
+use db::queue_reward_backlog;
+use db_pool::{DbError, DbPool};
 use ethers::{
     middleware::MiddlewareBuilder,
     providers::{Http, Provider},
     signers::{LocalWallet, Signer},
-    types::{Address, Bytes},
+    types::Bytes,
+    utils::{parse_ether, parse_units},
 };
 use get_time_keepers::handle_get_time_keepers;
-use log::{info, Level};
-use meantime::MeanTime;
-use mysql::Pool;
+use health::{handle_healthz, handle_readyz, Readiness, TickerHeartbeat};
+use keeper_ws::{handle_keeper_ws, KeeperWsConfig};
+use log::{error, info, warn, Level};
+use meantime::{ChainComp, MeanTime};
+use metrics::{handle_metrics, track_http};
+use migrations::{migrate, read_schema_status, SchemaStatus};
+use nonce_manager::NonceManager;
 use onboarding::handle_onboard;
+use preflight::{preflight, PreflightConfig, PreflightMode};
 use referral::{handle_read_referral, handle_write_referral};
 use referral_code::{handle_update_referral_code, handle_update_referred_from};
+use referral_schedule::{handle_get_referral_schedule, ReferralSchedule};
+use referral_tree::{handle_get_referral_downline, handle_get_referral_upline};
+use replay_guard::{prune_periodically, ReplayGuard};
+use reward_ledger::{handle_get_reward_history, handle_get_reward_totals};
 use serde_json::json;
 use stderrlog::Timestamp;
+use tick_feed::{handle_tick_feed, TickFeed};
 use time_pool::{handle_add_time_sig, handle_list_time_sigs, TimeSigPool};
+use time_signature::chronicle_domain;
 use timer::TimeTick;
-use tokio::{net::TcpListener, sync::Mutex, task::JoinSet};
+use tokio::{
+    net::TcpListener,
+    sync::{watch, Mutex, Semaphore},
+    task::JoinSet,
+};
 use tower_http::cors::{Any, CorsLayer};
+use tx_manager::{TxConfig, TxManager};
 
 mod address_str;
+mod block_time_params;
 mod call_breaker;
+mod chain_target;
 mod claim_avatar;
+mod consensus;
 mod db;
+mod db_pool;
 mod get_time_keepers;
+mod health;
+mod keeper_ws;
 mod meantime;
+#[cfg(test)]
+mod memory_store;
+mod metrics;
+mod migrations;
+mod nonce_manager;
 mod onboarding;
+mod preflight;
 mod referral;
 mod referral_code;
+mod referral_schedule;
+mod referral_tree;
 mod referrers_fetch;
+mod rejection;
+mod replay_guard;
+mod reward_ledger;
+mod send_failure;
+mod signed_request;
+mod store;
+mod tick_feed;
 mod time_pool;
 mod time_signature;
 mod timer;
+mod tx_manager;
 mod user_data;
 
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    // Releases the rewards accrued in the dry run mode and sends them in batches, then exits.
+    // Run it while the service is stopped, both use the solver nonces.
+    SettleBacklog {
+        // Only the backlog of this chain, all chains by default.
+        #[arg(long)]
+        chain_id: Option<u64>,
+    },
+    // Reports the schema version of the database and applies the pending migrations.
+    Migrate {
+        // Only report the status.
+        #[arg(long)]
+        check: bool,
+    },
+    // Rewrites the whitelisted addresses to lowercase full hex and merges the duplicates.
+    CanonicalizeAddresses {
+        // File with one full address per line, resolves the display addresses unknown to the database.
+        #[arg(long)]
+        known_addresses: Option<PathBuf>,
+    },
+}
+
 #[derive(Parser, Debug)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[arg(long, default_value_t = 8000)]
     pub port: u16,
 
@@ -66,38 +138,135 @@ pub struct Args {
     #[arg(long)]
     pub mysql_database: String,
 
+    // Applies the pending migrations at startup, otherwise the service doesn't start with an outdated schema.
+    #[arg(long, default_value="false", default_missing_value="true", num_args(0..=1), action=ArgAction::Set)]
+    pub auto_migrate: bool,
+
+    // Max number of database connections shared by the handlers and the ticker.
+    #[arg(long, default_value_t = 10)]
+    pub db_pool_size: usize,
+
+    // Bounds waiting for a free connection, connecting and each query.
+    #[arg(long, default_value = "5s")]
+    pub db_timeout: String,
+
     #[arg(long)]
     pub time_window: String,
 
+    #[arg(long, value_enum, default_value_t = ConsensusMode::Mean)]
+    pub consensus_mode: ConsensusMode,
+
+    // Which chronicle counts when a time keeper signs several times within the window.
+    #[arg(long, value_enum, default_value_t = VotePolicy::Latest)]
+    pub vote_policy: VotePolicy,
+
+    // Share of chronicles dropped from each end in the trimmed-mean mode.
+    #[arg(long, default_value_t = 0.1)]
+    pub consensus_trim_ratio: f64,
+
+    // Number of scaled MADs from the median a chronicle may deviate in the mad mode.
+    #[arg(long, default_value_t = 3.0)]
+    pub consensus_mad_threshold: f64,
+
+    // Maximum distance of a chronicle epoch from the server time.
+    #[arg(long, default_value = "1m")]
+    pub max_epoch_skew: String,
+
+    // Number of seen signatures kept in memory for the replay protection.
+    #[arg(long, default_value_t = 100000)]
+    pub seen_signatures_capacity: usize,
+
+    // Period of the pings on the keeper WebSocket connections, silent connections are closed after three of them.
+    #[arg(long, default_value = "15s")]
+    pub ws_heartbeat_interval: String,
+
+    // Max number of open keeper WebSocket connections.
+    #[arg(long, default_value_t = 1000)]
+    pub ws_max_connections: usize,
+
+    // The readiness check fails if no tick completed for longer, a tick waits for the receipts.
+    #[arg(long, default_value = "2m")]
+    pub ready_max_tick_age: String,
+
+    // Bounds each readiness check.
+    #[arg(long, default_value = "2s")]
+    pub ready_check_timeout: String,
+
     #[arg(long)]
     pub solver_private_key: LocalWallet,
 
     #[arg(long)]
     pub validator_private_key: LocalWallet,
 
-    #[arg(long)]
-    pub primary_chain_id: u64,
+    // Chain to submit the mean time to, repeat for every chain:
+    // id=<chain id>,url=<http url>,block_time=<address>,call_breaker=<address>[,enabled=<bool>]
+    #[arg(long = "chain", required = true)]
+    pub chains: Vec<ChainTarget>,
 
+    // Enabled chain whose id and BlockTime address make the EIP-712 domain of the chronicles and
+    // the signed requests. Changing it invalidates the signatures of the time keepers.
     #[arg(long)]
-    pub primary_http_chain_url: String,
+    pub signing_chain_id: u64,
 
-    #[arg(long)]
-    pub secondary_chain_id: u64,
+    // Initial delay before retrying a chain after a failed submission, doubled on every failure.
+    #[arg(long, default_value = "1s")]
+    pub retry_backoff: String,
 
-    #[arg(long)]
-    pub secondary_http_chain_url: String,
+    #[arg(long, default_value = "1m")]
+    pub max_retry_backoff: String,
 
-    #[arg(long)]
-    pub primary_block_time_address: Address,
+    // Percent of the reward one level below paid to each referral level, the last one repeats for deeper levels.
+    #[arg(long, default_value = "10,50")]
+    pub referral_percentages: String,
 
+    // Deepest paid referral level, unlimited by default.
     #[arg(long)]
-    pub primary_call_breaker_address: Address,
+    pub referral_max_depth: Option<u32>,
 
+    // Max referral reward of one referrer in one tick, in ether.
     #[arg(long)]
-    pub secondary_block_time_address: Address,
+    pub referral_cap_per_tick: Option<String>,
 
-    #[arg(long)]
-    pub secondary_call_breaker_address: Address,
+    // Smaller referral rewards aren't paid, in ether.
+    #[arg(long, default_value = "0")]
+    pub referral_min_payout: String,
+
+    // Time after the referrer of a time keeper is first set during which it can still be changed.
+    #[arg(long, default_value = "7d")]
+    pub referral_grace_period: String,
+
+    // Max number of receivers in one rewards transaction, the backlog fills the transactions up to it.
+    #[arg(long, default_value_t = 100)]
+    pub max_receivers_per_tx: usize,
+
+    // Time without a receipt after which a transaction is replaced with bumped fees.
+    #[arg(long, default_value = "1m")]
+    pub tx_stuck_timeout: String,
+
+    // Time without a receipt after which a send gives up, the transaction is resumed by the next tick.
+    // Bounds how long a stuck transaction holds up the ticks.
+    #[arg(long, default_value = "10m")]
+    pub tx_max_tracking_time: String,
+
+    // Nodes don't accept replacements with fees bumped by less than 10%.
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u64).range(10..))]
+    pub tx_fee_bump_percent: u64,
+
+    // Cap of the max fee per gas in gwei, fees are never bumped above it.
+    #[arg(long, default_value_t = 100.0)]
+    pub max_fee_per_gas_gwei: f64,
+
+    // Refuse to start or only log when the on-chain configuration check fails at startup.
+    #[arg(long, value_enum, default_value_t = PreflightMode::Strict)]
+    pub preflight: PreflightMode,
+
+    // Min native balance of the solver on every chain, in ether.
+    #[arg(long, default_value = "0.001")]
+    pub preflight_min_native_balance: String,
+
+    // Min CallBreaker sender balance of the solver on every chain, in ether.
+    #[arg(long, default_value = "0")]
+    pub preflight_min_sender_balance: String,
 
     #[arg(long)]
     pub app_id: Bytes,
@@ -111,11 +280,39 @@ pub struct Args {
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> Result<(), DbError> {
     let args = Args::parse();
     let time_sig_pool = Arc::new(Mutex::new(TimeSigPool::new()));
     let time_window = parse_duration::parse(&args.time_window)?;
     let tick_period = parse_duration::parse(&args.tick_period)?;
+    let max_epoch_skew = parse_duration::parse(&args.max_epoch_skew)?;
+    let retry_backoff = parse_duration::parse(&args.retry_backoff)?;
+    let max_retry_backoff = parse_duration::parse(&args.max_retry_backoff)?;
+    let referral_schedule = Arc::new(ReferralSchedule::new(
+        &args.referral_percentages,
+        args.referral_max_depth,
+        args.referral_cap_per_tick.as_deref(),
+        &args.referral_min_payout,
+    )?);
+    let referral_grace_period = parse_duration::parse(&args.referral_grace_period)?;
+    let heartbeat_interval = parse_duration::parse(&args.ws_heartbeat_interval)?;
+    if heartbeat_interval.is_zero() {
+        return Err("--ws-heartbeat-interval must be greater than zero".into());
+    }
+    let keeper_ws_config = KeeperWsConfig {
+        heartbeat_interval,
+        connections: Arc::new(Semaphore::new(args.ws_max_connections)),
+    };
+    let preflight_config = PreflightConfig {
+        min_native_balance: parse_ether(&args.preflight_min_native_balance)?,
+        min_sender_balance: parse_ether(&args.preflight_min_sender_balance)?,
+    };
+    let tx_config = TxConfig {
+        stuck_timeout: parse_duration::parse(&args.tx_stuck_timeout)?,
+        max_tracking_time: parse_duration::parse(&args.tx_max_tracking_time)?,
+        fee_bump_percent: args.tx_fee_bump_percent,
+        max_fee_per_gas: parse_units(args.max_fee_per_gas_gwei, "gwei")?.into(),
+    };
 
     stderrlog::new()
         .verbosity(Level::Info)
@@ -135,84 +332,238 @@ async fn main() -> Result<(), Box<dyn Error>> {
         "Connecting to the database with URL {} ...",
         mysql_display_url
     );
-    let db_conn = Pool::new(mysql_url.as_str())?.get_conn()?;
-    info!("Successfully created DB connection.");
-    let db_conn: Arc<Mutex<mysql::PooledConn>> = Arc::new(Mutex::new(db_conn));
+    let db = DbPool::new(
+        mysql_url.as_str(),
+        args.db_pool_size,
+        parse_duration::parse(&args.db_timeout)?,
+    )?;
+    info!("Successfully created DB pool.");
+
+    if let Some(Command::CanonicalizeAddresses { known_addresses }) = &args.command {
+        let known: Vec<String> = match known_addresses {
+            Some(path) => fs::read_to_string(path)?
+                .lines()
+                .map(str::to_string)
+                .collect(),
+            None => Vec::new(),
+        };
+        let unresolved = db
+            .run(move |conn| canonicalize_addresses(conn, &known))
+            .await?;
+        info!(
+            "Canonicalized the whitelisted addresses, {} unresolved.",
+            unresolved.len()
+        );
+        return Ok(());
+    }
+    if let Some(Command::Migrate { check }) = args.command {
+        let status = if check {
+            read_schema_status(&db).await?
+        } else {
+            migrate(&db).await?
+        };
+        info!("Database schema: {:?}", status);
+        return Ok(());
+    }
+    let status = match read_schema_status(&db).await? {
+        SchemaStatus::Pending { .. } if args.auto_migrate => migrate(&db).await?,
+        status => status,
+    };
+    match status {
+        SchemaStatus::UpToDate(version) => info!("Database schema version {}.", version),
+        SchemaStatus::Pending { current, latest } => {
+            return Err(format!(
+                "Database schema version {} is behind {}, run the migrate subcommand or pass --auto-migrate",
+                current, latest
+            )
+            .into())
+        }
+        SchemaStatus::Ahead { current, latest } => {
+            return Err(format!(
+                "Database schema version {} is ahead of {}, the service is outdated",
+                current, latest
+            )
+            .into())
+        }
+    }
+
+    let mut replay_guard = ReplayGuard::new(max_epoch_skew, args.seen_signatures_capacity);
+    replay_guard
+        .load(
+            &db,
+            SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?,
+        )
+        .await?;
+    info!("Loaded seen signatures.");
+    let replay_guard = Arc::new(Mutex::new(replay_guard));
+    let referral_writes = Arc::new(Mutex::new(()));
 
     let mut exec_set: JoinSet<()> = JoinSet::new();
-
-    let primary_wallet = args
-        .solver_private_key
-        .clone()
-        .with_chain_id(args.primary_chain_id);
-
-    let secondary_wallet = args
-        .solver_private_key
-        .clone()
-        .with_chain_id(args.secondary_chain_id);
+    exec_set.spawn(prune_periodically(db.clone(), max_epoch_skew));
 
     let validator_wallet = args.validator_private_key.clone();
 
     let app_id = args.app_id.clone();
 
-    info!(
-        "Using primary wallet {}",
-        format!("{:#x}", primary_wallet.address())
-    );
-
-    info!(
-        "Using secondary wallet {}",
-        format!("{:#x}", secondary_wallet.address())
-    );
-
-    info!(
-        "Connecting to the primary chain with URL {} ...",
-        args.primary_http_chain_url.as_str()
-    );
-    let primary_provider = Provider::<Http>::try_from(args.primary_http_chain_url.as_str())?;
-    info!(
-        "Successfully connected to the primary chain {}.",
-        args.primary_chain_id
-    );
-    info!(
-        "Connecting to the secondary chain with URL {} ...",
-        args.secondary_http_chain_url.as_str()
-    );
-    let secondary_provider = Provider::<Http>::try_from(args.secondary_http_chain_url.as_str())?;
-    info!(
-        "Successfully connected to the secondary chain {}.",
-        args.secondary_chain_id
-    );
-
-    let primary_client = Arc::new(primary_provider.with_signer(primary_wallet.clone()));
-    let primary_call_breaker_comp = Arc::new(CallBreakerData::new(
-        args.primary_call_breaker_address,
-        args.primary_block_time_address,
-        primary_client,
-        primary_wallet,
-        validator_wallet.clone(),
-        app_id.clone(),
-    ));
+    let chain_targets: Vec<&ChainTarget> = args.chains.iter().filter(|el| el.enabled).collect();
+    for chain_target in args.chains.iter().filter(|el| !el.enabled) {
+        info!("Skipping disabled chain {}", chain_target.chain_id);
+    }
+    if chain_targets.is_empty() {
+        return Err("No enabled chain targets".into());
+    }
+    let Some(signing_chain) = chain_targets
+        .iter()
+        .find(|el| el.chain_id == args.signing_chain_id)
+    else {
+        return Err(format!(
+            "--signing-chain-id {} isn't an enabled chain target",
+            args.signing_chain_id
+        )
+        .into());
+    };
 
-    let secondary_client = Arc::new(secondary_provider.with_signer(secondary_wallet.clone()));
-    let secondary_call_breaker_comp = Arc::new(CallBreakerData::new(
-        args.secondary_call_breaker_address,
-        args.secondary_block_time_address,
-        secondary_client,
-        secondary_wallet,
-        validator_wallet.clone(),
-        app_id.clone(),
+    let signing_domain = Arc::new(chronicle_domain(
+        signing_chain.chain_id,
+        signing_chain.block_time_address,
+        &app_id,
     ));
 
+    let mut chains = Vec::with_capacity(chain_targets.len());
+    let mut preflight_failures = 0;
+    let mut chain_clients = Vec::with_capacity(chain_targets.len());
+    for chain_target in chain_targets {
+        let wallet = args
+            .solver_private_key
+            .clone()
+            .with_chain_id(chain_target.chain_id);
+        info!(
+            "Using wallet {:#x} for the chain {}",
+            wallet.address(),
+            chain_target.chain_id
+        );
+        info!(
+            "Connecting to the chain {} with URL {} ...",
+            chain_target.chain_id, chain_target.http_chain_url
+        );
+        let provider = Provider::<Http>::try_from(chain_target.http_chain_url.as_str())?;
+        info!(
+            "Successfully connected to the chain {}.",
+            chain_target.chain_id
+        );
+        let client = Arc::new(provider.with_signer(wallet.clone()));
+        chain_clients.push((chain_target.chain_id, client.clone()));
+        let txs = TxManager::load(
+            &db,
+            chain_target.chain_id,
+            wallet.address(),
+            client.clone(),
+            tx_config,
+        )
+        .await?;
+        txs.refresh_balance().await;
+        let call_breaker_comp = Arc::new(CallBreakerData::new(
+            chain_target.call_breaker_address,
+            chain_target.block_time_address,
+            client,
+            wallet,
+            validator_wallet.clone(),
+            app_id.clone(),
+        ));
+
+        let failures = preflight(&call_breaker_comp, &preflight_config).await;
+        for failure in failures.iter() {
+            error!(
+                "Preflight check on the chain {} failed: {}",
+                chain_target.chain_id, failure
+            );
+        }
+        preflight_failures += failures.len();
+
+        // BlockTime params are refreshed when the max block width changes.
+        let params: BlockTimeParamsCache = Arc::new(Mutex::new(None));
+        exec_set.spawn(watch_params(call_breaker_comp.clone(), params.clone()));
+        let nonces = NonceManager::load(
+            &db,
+            chain_target.chain_id,
+            call_breaker_comp.solver_wallet.address(),
+        )
+        .await?;
+        info!(
+            "Next user objective nonce on the chain {} is {}",
+            chain_target.chain_id,
+            nonces.next()
+        );
+        chains.push(ChainComp::new(
+            chain_target.chain_id,
+            call_breaker_comp,
+            params,
+            nonces,
+            Arc::new(txs),
+        ));
+    }
+
+    if preflight_failures > 0 {
+        if args.preflight == PreflightMode::Strict {
+            return Err(format!(
+                "{} preflight checks failed, fix the configuration or pass --preflight degraded",
+                preflight_failures
+            )
+            .into());
+        }
+        warn!(
+            "Running degraded, {} preflight checks failed",
+            preflight_failures
+        );
+    } else {
+        info!("Preflight checks passed.");
+    }
+
+    if let Some(Command::SettleBacklog { chain_id }) = args.command {
+        // Queued only once the chains passed the preflight, the ticks settle the queued rewards too.
+        let queued = db
+            .run(move |conn| queue_reward_backlog(conn, chain_id))
+            .await?;
+        info!("Queued {} backlog rewards for settlement.", queued);
+        let chains = chains
+            .iter_mut()
+            .filter(|el| chain_id.is_none() || chain_id == Some(el.chain_id));
+        for chain in chains {
+            let settled = chain.settle_backlog(&db, args.max_receivers_per_tx).await?;
+            info!(
+                "Settled the backlog of {} receivers on the chain {}.",
+                settled, chain.chain_id
+            );
+        }
+        return Ok(());
+    }
+
+    let tick_feed: Arc<TickFeed> = Arc::new(watch::channel(None).0);
     let meantime_comp = Arc::new(Mutex::new(MeanTime::new(
         time_sig_pool.clone(),
-        primary_call_breaker_comp,
-        secondary_call_breaker_comp,
+        chains,
         time_window,
+        ConsensusConfig::new(
+            args.consensus_mode,
+            args.vote_policy,
+            args.consensus_trim_ratio,
+            args.consensus_mad_threshold,
+        ),
+        referral_schedule.clone(),
+        retry_backoff,
+        max_retry_backoff,
+        args.max_receivers_per_tx,
         args.dry_run,
+        Arc::clone(&tick_feed),
     )));
 
-    let mut time_tick = TimeTick::new(tick_period, meantime_comp, db_conn.clone());
+    let ticker_heartbeat = TickerHeartbeat::default();
+    let mut time_tick = TimeTick::new(
+        tick_period,
+        meantime_comp,
+        db.clone(),
+        ticker_heartbeat.clone(),
+    );
     exec_set.spawn(async move {
         time_tick.ticker().await;
     });
@@ -222,8 +573,24 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .allow_origin(Any)
         .allow_headers([ACCEPT, ACCEPT_LANGUAGE, CONTENT_LANGUAGE, CONTENT_TYPE]);
 
+    let readiness = Arc::new(Readiness {
+        db: db.clone(),
+        chains: chain_clients,
+        heartbeat: ticker_heartbeat,
+        max_tick_age: parse_duration::parse(&args.ready_max_tick_age)?,
+        check_timeout: parse_duration::parse(&args.ready_check_timeout)?,
+    });
+
     let app = Router::new()
         .route("/", get(|| async { "Blockclock Backend" }))
+        .route("/healthz", get(handle_healthz))
+        .route(
+            "/readyz",
+            get({
+                let readiness = Arc::clone(&readiness);
+                move || handle_readyz(readiness)
+            }),
+        )
         .route(
             "/list_time_sigs",
             get({
@@ -231,6 +598,20 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 move || handle_list_time_sigs(time_sig_pool)
             }),
         )
+        .route(
+            "/metrics",
+            get({
+                let time_sig_pool = Arc::clone(&time_sig_pool);
+                move || handle_metrics(time_sig_pool)
+            }),
+        )
+        .route(
+            "/ticks",
+            get({
+                let tick_feed = Arc::clone(&tick_feed);
+                move || handle_tick_feed(tick_feed)
+            }),
+        )
         .route(
             "/get_time_margin",
             get({
@@ -240,63 +621,156 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 move || async { output }
             }),
         )
+        .route(
+            "/get_signing_domain",
+            get({
+                let output = Json(json!(*signing_domain));
+                move || async { output }
+            }),
+        )
         .route(
             "/add_time_sig",
             post({
                 let time_sig_pool = Arc::clone(&time_sig_pool);
-                let db_conn = Arc::clone(&db_conn);
-                move |input| handle_add_time_sig(input, time_sig_pool, db_conn)
+                let db = db.clone();
+                let replay_guard = Arc::clone(&replay_guard);
+                let signing_domain = Arc::clone(&signing_domain);
+                move |input| {
+                    handle_add_time_sig(input, time_sig_pool, db, replay_guard, signing_domain)
+                }
+            }),
+        )
+        .route(
+            "/ws/keeper",
+            get({
+                let time_sig_pool = Arc::clone(&time_sig_pool);
+                let db = db.clone();
+                let replay_guard = Arc::clone(&replay_guard);
+                let signing_domain = Arc::clone(&signing_domain);
+                move |ws| {
+                    handle_keeper_ws(
+                        ws,
+                        time_sig_pool,
+                        db,
+                        replay_guard,
+                        signing_domain,
+                        keeper_ws_config,
+                    )
+                }
             }),
         )
         .route(
             "/claim_avatar",
             post({
-                let db_conn = Arc::clone(&db_conn);
-                move |input| handle_claim_avatar(input, db_conn)
+                let db = db.clone();
+                let replay_guard = Arc::clone(&replay_guard);
+                let signing_domain = Arc::clone(&signing_domain);
+                move |input| handle_claim_avatar(input, db, replay_guard, signing_domain)
             }),
         )
         .route(
             "/onboard",
             post({
-                let db_conn = Arc::clone(&db_conn);
-                move |input| handle_onboard(input, db_conn)
+                let db = db.clone();
+                let replay_guard = Arc::clone(&replay_guard);
+                let signing_domain = Arc::clone(&signing_domain);
+                move |input| handle_onboard(input, db, replay_guard, signing_domain)
             }),
         )
         .route(
             "/update_referral_code",
             post({
-                let db_conn = Arc::clone(&db_conn);
-                move |input| handle_update_referral_code(input, db_conn)
+                let db = db.clone();
+                let replay_guard = Arc::clone(&replay_guard);
+                let signing_domain = Arc::clone(&signing_domain);
+                let referral_writes = Arc::clone(&referral_writes);
+                move |input| {
+                    handle_update_referral_code(
+                        input,
+                        db,
+                        replay_guard,
+                        signing_domain,
+                        referral_writes,
+                    )
+                }
             }),
         )
         .route(
             "/update_referred_from",
             post({
-                let db_conn = Arc::clone(&db_conn);
-                move |input| handle_update_referred_from(input, db_conn)
+                let db = db.clone();
+                let replay_guard = Arc::clone(&replay_guard);
+                let signing_domain = Arc::clone(&signing_domain);
+                let referral_writes = Arc::clone(&referral_writes);
+                move |input| {
+                    handle_update_referred_from(
+                        input,
+                        db,
+                        replay_guard,
+                        signing_domain,
+                        referral_grace_period,
+                        referral_writes,
+                    )
+                }
             }),
         )
         .route(
             "/get_time_keepers_count",
             get({
-                let db_conn = Arc::clone(&db_conn);
-                move || handle_get_time_keepers(db_conn)
+                let db = db.clone();
+                move || handle_get_time_keepers(db)
+            }),
+        )
+        .route(
+            "/get_referral_schedule",
+            get({
+                let referral_schedule = Arc::clone(&referral_schedule);
+                move || handle_get_referral_schedule(referral_schedule)
+            }),
+        )
+        .route(
+            "/get_reward_history",
+            get({
+                let db = db.clone();
+                move |params| handle_get_reward_history(params, db)
+            }),
+        )
+        .route(
+            "/get_reward_totals",
+            get({
+                let db = db.clone();
+                move |params| handle_get_reward_totals(params, db)
+            }),
+        )
+        .route(
+            "/get_referral_upline",
+            get({
+                let db = db.clone();
+                move |params| handle_get_referral_upline(params, db)
+            }),
+        )
+        .route(
+            "/get_referral_downline",
+            get({
+                let db = db.clone();
+                move |params| handle_get_referral_downline(params, db)
             }),
         )
         .route(
             "/read_referral",
             get({
-                let db_conn = Arc::clone(&db_conn);
-                move |params| handle_read_referral(params, db_conn)
+                let db = db.clone();
+                move |params| handle_read_referral(params, db)
             }),
         )
         .route(
             "/write_referral",
             post({
-                let db_conn = Arc::clone(&db_conn);
-                move |input| handle_write_referral(input, db_conn)
+                let db = db.clone();
+                move |input| handle_write_referral(input, db)
             }),
         )
+        .route_layer(middleware::from_fn(track_http))
         .layer(cors);
 
     let tcp_listener = TcpListener::bind(format!("0.0.0.0:{}", args.port))
@@ -1,52 +1,248 @@
 use std::{
-    collections::BTreeMap,
-    sync::{
-        atomic::{AtomicU32, Ordering},
-        Arc,
-    },
-    time::{Duration, SystemTime},
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
 
 use ethers::{
     abi::{encode, Token},
     providers::Middleware,
     signers::{LocalWallet, Signer},
-    types::{Address, Bytes, U256},
-    utils::{keccak256, parse_units},
+    types::{Address, Bytes, Eip1559TransactionRequest, TransactionReceipt, U256},
+    utils::keccak256,
 };
 
-use log::{error, info};
-use md5::{Context, Digest};
-use mysql::PooledConn;
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use log::{error, info, warn};
+use md5::Context;
 use tokio::{spawn, sync::Mutex};
 
 use crate::{
-    address_str::get_address_strings,
+    block_time_params::{get_params, BlockTimeParams, BlockTimeParamsCache, TickCheck},
     call_breaker::{AdditionalData, CallBreakerData, CallObject, MevTimeData, UserObjective},
-    referrers_fetch::read_referrers_list,
+    chain_target::TargetState,
+    consensus::{Consensus, ConsensusConfig},
+    db::{read_confirmed_tick, store_confirmed_tick},
+    db_pool::{DbError, DbPool},
+    metrics::METRICS,
+    nonce_manager::NonceManager,
+    referral_schedule::ReferralSchedule,
+    referrers_fetch::{read_referrers_list, Reward},
+    reward_ledger::{
+        claim_backlog, merge_backlog, record_rewards, reward_shares, settle_rewards, RewardShare,
+        RewardStatus,
+    },
+    send_failure::SendFailure,
+    tick_feed::{ChainTickStatus, TickEvent, TickFeed},
     time_pool::TimeSigPool,
     time_signature::Chronicle,
+    tx_manager::{Resumed, TxManager, TxTick},
 };
 
+// A chain target with its own contracts, BlockTime params and submission state.
+pub struct ChainComp<M: Middleware> {
+    pub chain_id: u64,
+    pub call_breaker_comp: Arc<CallBreakerData<M>>,
+    pub params: BlockTimeParamsCache,
+    nonces: NonceManager,
+    txs: Arc<TxManager<M>>,
+    state: TargetState,
+}
+
+impl<M: Middleware> ChainComp<M> {
+    pub fn new(
+        chain_id: u64,
+        call_breaker_comp: Arc<CallBreakerData<M>>,
+        params: BlockTimeParamsCache,
+        nonces: NonceManager,
+        txs: Arc<TxManager<M>>,
+    ) -> ChainComp<M> {
+        ChainComp {
+            chain_id,
+            call_breaker_comp,
+            params,
+            nonces,
+            txs,
+            state: TargetState::new(),
+        }
+    }
+
+    // Sends the queued backlog in batches of up to `max_receivers_per_tx` receivers, returns the
+    // number of paid receivers. The batches carry the chronicles of the last confirmed tick, the
+    // ones BlockTime already accepted. Stops at the first failed batch, its rewards go back to the queue.
+    pub async fn settle_backlog(
+        &mut self,
+        db: &DbPool,
+        max_receivers_per_tx: usize,
+    ) -> Result<usize, DbError> {
+        let chain_id = self.chain_id;
+        let Some((mean_time, chronicles)) = db
+            .run(move |conn| read_confirmed_tick(conn, chain_id))
+            .await?
+        else {
+            return Err(format!(
+                "No confirmed tick on the chain {} to settle the backlog with",
+                chain_id
+            )
+            .into());
+        };
+        let chronicles: Vec<Chronicle> = serde_json::from_str(&chronicles)?;
+        let started_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO);
+        let mut settled = 0;
+        for batch in 0.. {
+            // Identifies the batch in `settled_in` like the chronicles of a tick.
+            let batch_md5 = format!(
+                "{:x}",
+                md5::compute(format!(
+                    "backlog-{}-{}-{}",
+                    chain_id,
+                    started_at.as_nanos(),
+                    batch
+                ))
+            );
+            let backlog = claim_backlog(db, chain_id, &batch_md5, max_receivers_per_tx).await;
+            if backlog.is_empty() {
+                break;
+            }
+            let nonce = self.nonces.reserve(db).await?;
+            let (receivers, amounts) = merge_backlog(&[], &backlog);
+            let res = match prepare_rewards_tx(
+                chronicles.clone(),
+                mean_time,
+                receivers,
+                amounts,
+                self.call_breaker_comp.clone(),
+                U256::from(nonce),
+            )
+            .await
+            {
+                Ok(tx) => {
+                    let tick = TxTick {
+                        chain_id,
+                        chronicles_md5: batch_md5.clone(),
+                        nonce,
+                    };
+                    let (resumed, res) = self.txs.send(tx, tick, db.clone()).await;
+                    if let Some(resumed) = resumed {
+                        self.settle_resumed(db, resumed).await;
+                    }
+                    res
+                }
+                Err(failure) => Err(failure),
+            };
+            match res {
+                Ok(receipt) => {
+                    info!(
+                        "Backlog of {} receivers sent to chain {}, txhash: {:#x}",
+                        backlog.len(),
+                        chain_id,
+                        receipt.transaction_hash
+                    );
+                    self.nonces.confirm(nonce);
+                    settle_rewards(
+                        db,
+                        chain_id,
+                        &batch_md5,
+                        RewardStatus::Confirmed,
+                        Some(receipt.transaction_hash),
+                        None,
+                    )
+                    .await;
+                    settled += backlog.len();
+                }
+                Err(failure @ SendFailure::Stuck(_)) => {
+                    // The transaction may still be mined, its rewards stay pending.
+                    return Err(failure.to_string().into());
+                }
+                Err(failure) => {
+                    self.nonces.release(db, nonce).await;
+                    settle_rewards(
+                        db,
+                        chain_id,
+                        &batch_md5,
+                        RewardStatus::Failed,
+                        failure.tx_hash(),
+                        Some(&failure.to_string()),
+                    )
+                    .await;
+                    return Err(failure.to_string().into());
+                }
+            }
+        }
+        Ok(settled)
+    }
+
+    // Settles the tick of a transaction resumed from an earlier send, unless it's still pending.
+    async fn settle_resumed(&mut self, db: &DbPool, resumed: Resumed) {
+        let Some(status) = settled_status(&resumed.res) else {
+            return;
+        };
+        let tick = resumed.tick;
+        // The nonce of a transaction sent before the restart isn't in flight yet.
+        self.nonces.resume(tick.nonce);
+        let (tx_hash, reason) = match &resumed.res {
+            Ok(receipt) => {
+                self.nonces.confirm(tick.nonce);
+                (Some(receipt.transaction_hash), None)
+            }
+            Err(failure) => {
+                self.nonces.release(db, tick.nonce).await;
+                (failure.tx_hash(), Some(failure.to_string()))
+            }
+        };
+        info!(
+            "Resumed transaction on the chain {} settled the rewards of {} as {}",
+            tick.chain_id,
+            tick.chronicles_md5,
+            status.as_str()
+        );
+        settle_rewards(
+            db,
+            tick.chain_id,
+            &tick.chronicles_md5,
+            status,
+            tx_hash,
+            reason.as_deref(),
+        )
+        .await;
+    }
+}
+
+// Status the rewards of a sent tick settle with, none while its transaction may still be mined.
+fn settled_status(res: &Result<TransactionReceipt, SendFailure>) -> Option<RewardStatus> {
+    match res {
+        Ok(_) => Some(RewardStatus::Confirmed),
+        Err(SendFailure::Stuck(_)) => None,
+        Err(_) => Some(RewardStatus::Failed),
+    }
+}
+
 pub struct MeanTime<M: Middleware> {
     pool: Arc<Mutex<TimeSigPool>>,
-    primary_call_breaker_comp: Arc<CallBreakerData<M>>,
-    secondary_call_breaker_comp: Arc<CallBreakerData<M>>,
+    chains: Vec<ChainComp<M>>,
     time_window: Duration,
-    curr_md5: Digest,
+    consensus: ConsensusConfig,
+    referral_schedule: Arc<ReferralSchedule>,
+    retry_backoff: Duration,
+    max_retry_backoff: Duration,
+    max_receivers_per_tx: usize,
     is_dry_run: bool,
+    feed: Arc<TickFeed>,
+    // Chronicles of the ticks the BlockTime params hold, by chain id.
+    held: HashMap<u64, Vec<Chronicle>>,
 }
 
-const TIME_KEEPER_REWARD: f64 = 1.0;
-static NONCE: AtomicU32 = AtomicU32::new(0);
-
-async fn send_rewards<M: Middleware>(
+// Builds the rewards transaction, the estimation finds the calls that would revert.
+async fn prepare_rewards_tx<M: Middleware>(
     last_sigs: Vec<Chronicle>,
     mean_time: U256,
     all_receivers: Vec<Address>,
     all_amounts: Vec<U256>,
     call_breaker_data: Arc<CallBreakerData<M>>,
-) -> bool {
+    nonce: U256,
+) -> Result<Eip1559TransactionRequest, SendFailure> {
     // generate user_objective
     let user_objective: UserObjective = prepare_call_and_user_objective(
         &last_sigs,
@@ -54,6 +250,7 @@ async fn send_rewards<M: Middleware>(
         &all_receivers,
         &all_amounts,
         &call_breaker_data,
+        nonce,
     );
 
     let user_objectives = vec![user_objective];
@@ -69,55 +266,24 @@ async fn send_rewards<M: Middleware>(
         &call_breaker_data.validator_wallet,
     );
 
-    let estimated_gas = call_breaker_data
-        .call_breaker_contract
-        .execute_and_verify(
-            user_objectives.clone(),
-            returns_bytes.clone(),
-            order_of_execution.clone(),
-            mev_time_data.clone(),
-        )
+    let call = call_breaker_data.call_breaker_contract.execute_and_verify(
+        user_objectives,
+        returns_bytes,
+        order_of_execution,
+        mev_time_data,
+    );
+    let estimated_gas = call
         .estimate_gas()
-        .await;
-
-    let gas_limit = estimated_gas.unwrap() * 120 / 100;
-    match call_breaker_data
-        .call_breaker_contract
-        .execute_and_verify(
-            user_objectives,
-            returns_bytes,
-            order_of_execution,
-            mev_time_data,
-        )
-        .gas(gas_limit)
-        .send()
         .await
-    {
-        Ok(pending) => {
-            info!("Transaction is sent, txhash: {}", pending.tx_hash());
-            match pending.await {
-                Ok(receipt) => {
-                    if let Some(receipt) = receipt {
-                        if let Some(status) = receipt.status {
-                            info!("Got transaction status: {}", status);
-                            NONCE.fetch_add(1, Ordering::SeqCst);
-                            return true;
-                        }
-                    }
-                    error!("Transaction status wasn't received.");
-                    return false;
-                }
-                Err(err) => {
-                    error!("Error pending transaction: {}", err);
-                    return false;
-                }
-            }
-        }
-        Err(err) => {
-            error!("Error sending transaction: {}", err);
-            return false;
-        }
-    }
+        .map_err(SendFailure::from_contract_error)?;
+
+    let gas_limit = estimated_gas * 120 / 100;
+    Ok(Eip1559TransactionRequest::new()
+        .to(call_breaker_data.call_breaker_contract.address())
+        .data(call.calldata().ok_or(SendFailure::Encoding(
+            "executeAndVerify calldata".to_string(),
+        ))?)
+        .gas(gas_limit))
 }
 
 fn prepare_call_and_user_objective<M: Middleware>(
@@ -126,6 +292,7 @@ fn prepare_call_and_user_objective<M: Middleware>(
     all_receivers: &[Address],
     all_amounts: &[U256],
     call_breaker_data: &Arc<CallBreakerData<M>>,
+    nonce: U256,
 ) -> UserObjective {
     let call = call_breaker_data
         .block_time_contract
@@ -155,7 +322,7 @@ fn prepare_call_and_user_objective<M: Middleware>(
 
     UserObjective::new(
         call_breaker_data.app_id.clone(),
-        U256::from(NONCE.load(Ordering::SeqCst)),
+        nonce,
         U256::from(0),
         U256::from(1),
         U256::from(0),
@@ -199,162 +366,534 @@ fn prepare_mev_time_data(
     MevTimeData::new(validator_wallet.clone(), mev_time_data_values)
 }
 
+// Keeps the chronicles of the tick for the backlog settlement, the tick is done anyway.
+async fn record_confirmed_tick(
+    db: &DbPool,
+    chain_id: u64,
+    mean_time: &U256,
+    last_sigs: &[Chronicle],
+) {
+    let chronicles = match serde_json::to_string(last_sigs) {
+        Ok(chronicles) => chronicles,
+        Err(err) => {
+            error!("Error encoding the chronicles of the tick: {}", err);
+            return;
+        }
+    };
+    let mean_time = *mean_time;
+    if let Err(err) = db
+        .run(move |conn| store_confirmed_tick(conn, chain_id, &mean_time, &chronicles))
+        .await
+    {
+        error!(
+            "Error storing the confirmed tick of the chain {}: {}",
+            chain_id, err
+        );
+    }
+}
+
+// Checks the tick against the chain's BlockTime params, unknown params don't block the tick.
+fn tick_skip_reason(
+    chain_id: u64,
+    params: &Option<BlockTimeParams>,
+    last_sigs: &[Chronicle],
+) -> Option<String> {
+    match params.map(|params| params.check(last_sigs)) {
+        // The held chains don't send the tick.
+        None | Some(TickCheck::Pass) | Some(TickCheck::Hold(_)) => None,
+        Some(TickCheck::Skip(reason)) => {
+            warn!("Skipping the chain {} tick: {}", chain_id, reason);
+            Some(reason)
+        }
+    }
+}
+
 impl<M: Middleware + 'static> MeanTime<M> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         pool: Arc<Mutex<TimeSigPool>>,
-        primary_call_breaker_comp: Arc<CallBreakerData<M>>,
-        secondary_call_breaker_comp: Arc<CallBreakerData<M>>,
+        chains: Vec<ChainComp<M>>,
         time_window: Duration,
+        consensus: ConsensusConfig,
+        referral_schedule: Arc<ReferralSchedule>,
+        retry_backoff: Duration,
+        max_retry_backoff: Duration,
+        max_receivers_per_tx: usize,
         is_dry_run: bool,
+        feed: Arc<TickFeed>,
     ) -> MeanTime<M> {
         MeanTime {
             pool,
-            primary_call_breaker_comp,
-            secondary_call_breaker_comp,
+            chains,
             time_window,
-            curr_md5: md5::compute("--dummy--"),
+            consensus,
+            referral_schedule,
+            retry_backoff,
+            max_retry_backoff,
+            max_receivers_per_tx,
             is_dry_run,
+            feed,
+            held: HashMap::new(),
         }
     }
 
-    async fn compute_mean_time(&self, curr_ts: Duration) -> Option<(U256, Vec<Chronicle>)> {
+    async fn compute_mean_time(&self, curr_ts: Duration) -> Option<Consensus> {
         // Check the latest signature.
         let mut pool = self.pool.lock().await;
         if pool.is_empty() {
             return None;
         }
         pool.sort_by_key(|el| el.epoch);
-        // Filter latest time signatures in the time window.
-        let upper_bound: U256;
-        if pool.last().unwrap().epoch > curr_ts.as_nanos().into() {
+        // Filter latest time signatures in the time window.
+        let upper_bound: U256 = if pool.last().unwrap().epoch > curr_ts.as_nanos().into() {
             // The last time is newer than the current server time, considering server time
-            upper_bound = curr_ts.as_nanos().into();
+            curr_ts.as_nanos().into()
         } else {
             // The last time is earlier than the current server time, considering the last time
-            upper_bound = pool.last().unwrap().epoch;
-        }
+            pool.last().unwrap().epoch
+        };
         let lower_bound = upper_bound - self.time_window.as_nanos();
-        let last_sigs: Vec<&Chronicle> = pool
-            .as_slice()
-            .into_iter()
-            .filter(|el| el.epoch > lower_bound.into() && el.epoch <= upper_bound)
+        let last_sigs: Vec<Chronicle> = pool
+            .iter()
+            .filter(|el| el.epoch > lower_bound && el.epoch <= upper_bound)
+            .cloned()
             .collect();
-        // Final mean time computation.
-        let sum_time: u128 = last_sigs
-            .as_slice()
-            .into_iter()
-            .map(|el| el.epoch.as_u128())
-            .sum();
         if last_sigs.is_empty() {
             return None;
         }
-        let mean_time = sum_time / last_sigs.len() as u128;
-        let last_sigs = last_sigs.into_iter().map(|el| el.clone()).collect();
+        // Final mean time computation.
+        let consensus = self.consensus.aggregate(last_sigs);
         pool.clear();
-        return Some((mean_time.into(), last_sigs));
+        consensus
     }
 
-    pub async fn handle_time_tick(&mut self, curr_ts: SystemTime, conn: Arc<Mutex<PooledConn>>) {
+    pub async fn handle_time_tick(&mut self, curr_ts: SystemTime, db: DbPool) {
         // Get mean time
+        let started_at = Instant::now();
         let curr_ts_epoch = curr_ts.duration_since(SystemTime::UNIX_EPOCH).unwrap();
-        if let Some((mean_time, last_sigs)) = self.compute_mean_time(curr_ts_epoch).await {
-            let curr_md5_ctx =
-                last_sigs
-                    .as_slice()
-                    .into_iter()
-                    .fold(Context::new(), |mut acc, el| {
-                        acc.consume(&el.signature);
-                        return acc;
-                    });
-            let curr_md5 = curr_md5_ctx.compute();
-            if curr_md5 == self.curr_md5 {
-                // No changes, no need to update the time.
-                return;
+        let Some(consensus) = self.compute_mean_time(curr_ts_epoch).await else {
+            return;
+        };
+        let mean_time = consensus.mean_time;
+        info!(
+            "Mean time {} computed with {} ({} votes) from {} keepers, rejected: {:?}",
+            mean_time,
+            self.consensus.mode,
+            self.consensus.vote_policy,
+            consensus.accepted.len(),
+            consensus
+                .rejected
+                .iter()
+                .map(|el| format!("{:#x}@{}", el.time_keeper, el.epoch))
+                .collect::<Vec<_>>()
+        );
+        let chain_ids: Vec<u64> = self.chains.iter().map(|el| el.chain_id).collect();
+        let mut tick = TickEvent::new(&consensus, curr_ts_epoch, &chain_ids);
+        METRICS.record_consensus(&consensus);
+        self.publish(&tick);
+        // Held chains send their held chronicles along with the new ones, with their own mean time.
+        let (held_chains, other_chains): (Vec<usize>, Vec<usize>) = (0..self.chains.len())
+            .partition(|idx| self.held.contains_key(&self.chains[*idx].chain_id));
+        let accepted = consensus.accepted.clone();
+        self.submit_tick(consensus, &other_chains, &mut tick, db.clone())
+            .await;
+        for idx in held_chains {
+            let mut chronicles = self.held[&self.chains[idx].chain_id].clone();
+            chronicles.extend(accepted.iter().cloned());
+            if let Some(consensus) = self.consensus.aggregate(chronicles) {
+                self.submit_tick(consensus, &[idx], &mut tick, db.clone())
+                    .await;
             }
-            // Send the mean time and signatures to the contract
-            let mut accounts_and_amounts =
-                last_sigs
-                    .as_slice()
-                    .iter()
-                    .fold(BTreeMap::new(), |mut acc, el| {
-                        let (account, _) = get_address_strings(&el.time_keeper);
-                        match acc.get(&account) {
-                            Some(amount) => {
-                                acc.insert(account, amount + TIME_KEEPER_REWARD);
-                            }
-                            None => {
-                                acc.insert(account, TIME_KEEPER_REWARD);
-                            }
-                        }
-                        acc
-                    });
+        }
+        METRICS.record_tick_duration(started_at.elapsed());
+        self.publish(&tick);
+    }
 
-            {
-                let mut conn = conn.lock().await;
-                if let Err(err) =
-                    read_referrers_list(conn.as_mut(), &mut accounts_and_amounts).await
+    fn publish(&self, tick: &TickEvent) {
+        self.feed.send_replace(Some(Arc::new(tick.clone())));
+    }
+
+    // Rewards of the time keepers of the tick and their referrers. Without the referrers only the
+    // time keepers' own rewards are returned, with the error.
+    async fn tick_shares(
+        &self,
+        last_sigs: &[Chronicle],
+        db: &DbPool,
+    ) -> (Vec<RewardShare>, Option<String>) {
+        let keeper_rewards = last_sigs.iter().fold(BTreeMap::new(), |mut acc, el| {
+            // One reward per time keeper, no matter how many chronicles were sent.
+            let account = format!("{:#x}", el.time_keeper);
+            acc.insert(
+                account,
+                Reward {
+                    amount: self.referral_schedule.time_keeper_reward,
+                    level: 0,
+                },
+            );
+            acc
+        });
+        let schedule = self.referral_schedule.clone();
+        let accounts_and_amounts = keeper_rewards.clone();
+        match db
+            .run(move |conn| {
+                let mut accounts_and_amounts = accounts_and_amounts;
+                read_referrers_list(conn, &mut accounts_and_amounts, &schedule)?;
+                Ok(accounts_and_amounts)
+            })
+            .await
+        {
+            Ok(accounts_and_amounts) => (reward_shares(accounts_and_amounts), None),
+            Err(err) => {
+                error!("Error getting referrers: {}", err);
+                (
+                    reward_shares(keeper_rewards),
+                    Some(format!("Error getting referrers: {}", err)),
+                )
+            }
+        }
+    }
+
+    // Sends the tick to the chains of `chain_idxs`, the status of every chain goes to the tick event.
+    // Every chain that doesn't send the tick records the rewards with the reason.
+    async fn submit_tick(
+        &mut self,
+        consensus: Consensus,
+        chain_idxs: &[usize],
+        tick: &mut TickEvent,
+        db: DbPool,
+    ) {
+        let mean_time = consensus.mean_time;
+        let last_sigs = consensus.accepted;
+        let mut all_params = vec![None; self.chains.len()];
+        for idx in chain_idxs.iter().copied() {
+            let chain = &self.chains[idx];
+            all_params[idx] = get_params(&chain.call_breaker_comp, &chain.params).await;
+        }
+        let curr_md5_ctx = last_sigs
+            .as_slice()
+            .iter()
+            .fold(Context::new(), |mut acc, el| {
+                acc.consume(&el.signature);
+                acc
+            });
+        let curr_md5 = curr_md5_ctx.compute();
+        let chronicles_md5 = format!("{:x}", curr_md5);
+        let now = Instant::now();
+        // Chains that don't send the tick, with the status and the reason of their rewards.
+        let mut not_sent = Vec::new();
+        let pending_chains: Vec<usize> = chain_idxs
+            .iter()
+            .copied()
+            .filter(|idx| {
+                let chain = &self.chains[*idx];
+                if let Some(TickCheck::Hold(reason)) =
+                    all_params[*idx].map(|params| params.check(&last_sigs))
                 {
-                    error!("Error getting referrers: {}", err);
-                    return;
+                    info!("Holding the chain {} tick: {}", chain.chain_id, reason);
+                    tick.set_status(
+                        chain.chain_id,
+                        ChainTickStatus::Held,
+                        None,
+                        Some(reason.clone()),
+                    );
+                    // The chronicles count in the next tick of the chain, their rewards are
+                    // recorded as held when the chain starts holding.
+                    if self
+                        .held
+                        .insert(chain.chain_id, last_sigs.clone())
+                        .is_none()
+                    {
+                        not_sent.push((chain.chain_id, RewardStatus::Held, reason));
+                    }
+                    return false;
+                }
+                self.held.remove(&chain.chain_id);
+                if chain.state.curr_md5 == curr_md5 {
+                    // No changes, no need to update the time.
+                    tick.set_status(chain.chain_id, ChainTickStatus::Unchanged, None, None);
+                    return false;
                 }
+                if !chain.state.is_ready(now) {
+                    info!(
+                        "Chain {} is backing off after {} failures",
+                        chain.chain_id,
+                        chain.state.failures()
+                    );
+                    tick.set_status(chain.chain_id, ChainTickStatus::BackingOff, None, None);
+                    not_sent.push((
+                        chain.chain_id,
+                        RewardStatus::Skipped,
+                        format!("Backing off after {} failures", chain.state.failures()),
+                    ));
+                    return false;
+                }
+                true
+            })
+            .collect();
+        if pending_chains.is_empty() && not_sent.is_empty() {
+            return;
+        }
+        let (shares, referrers_err) = self.tick_shares(&last_sigs, &db).await;
+        for (chain_id, status, reason) in not_sent {
+            record_rewards(
+                &db,
+                chain_id,
+                &mean_time,
+                &chronicles_md5,
+                &shares,
+                status,
+                Some(&reason),
+            )
+            .await;
+        }
+        if let Some(reason) = referrers_err {
+            for idx in pending_chains {
+                let chain_id = self.chains[idx].chain_id;
+                record_rewards(
+                    &db,
+                    chain_id,
+                    &mean_time,
+                    &chronicles_md5,
+                    &shares,
+                    RewardStatus::Failed,
+                    Some(&reason),
+                )
+                .await;
+                tick.set_status(
+                    chain_id,
+                    ChainTickStatus::Failed,
+                    None,
+                    Some(reason.clone()),
+                );
             }
-            let (all_receivers, all_amounts) = accounts_and_amounts.into_iter().fold(
-                (Vec::new(), Vec::new()),
-                |mut acc: (Vec<Address>, Vec<U256>), el| {
-                    if let Ok(account) = el.0.parse::<Address>() {
-                        acc.0.push(account);
-                        if let Ok(amount) = parse_units(el.1, "ether") {
-                            acc.1.push(amount.into());
-                        }
-                    }
-                    acc
-                },
+            return;
+        }
+        // Added for suspending rewards during airdrop.
+        if self.is_dry_run {
+            // The rewards are accrued to the backlog, `settle-backlog` releases them later.
+            info!(
+                "Accruing rewards due to dry_run mode, accrued rewards:\n{:#?}",
+                shares
             );
-            // Added for suspending rewards during airdrop.
-            if self.is_dry_run {
-                info!(
-                    "Skipping sending rewards due to dry_run mode, skipped rewards:\n{:#?} {:#?}",
-                    all_receivers, all_amounts
+            for idx in pending_chains {
+                tick.set_status(
+                    self.chains[idx].chain_id,
+                    ChainTickStatus::Accrued,
+                    None,
+                    Some("dry run".to_string()),
                 );
-                return;
+                record_rewards(
+                    &db,
+                    self.chains[idx].chain_id,
+                    &mean_time,
+                    &chronicles_md5,
+                    &shares,
+                    RewardStatus::Accrued,
+                    Some("dry run"),
+                )
+                .await;
             }
-            let primary_last_sigs = last_sigs.clone();
-            let primary_all_receivers = all_receivers.clone();
-            let primary_all_amounts = all_amounts.clone();
-            let primary_call_breaker_comp = self.primary_call_breaker_comp.clone();
-            let primary_handle = spawn(async move {
-                return send_rewards(
-                    primary_last_sigs,
-                    mean_time,
-                    primary_all_receivers,
-                    primary_all_amounts,
-                    primary_call_breaker_comp,
+            return;
+        }
+        let mut handles = Vec::with_capacity(pending_chains.len());
+        for idx in pending_chains {
+            let chain = &mut self.chains[idx];
+            if let Some(reason) = tick_skip_reason(chain.chain_id, &all_params[idx], &last_sigs) {
+                record_rewards(
+                    &db,
+                    chain.chain_id,
+                    &mean_time,
+                    &chronicles_md5,
+                    &shares,
+                    RewardStatus::Skipped,
+                    Some(&reason),
                 )
                 .await;
-            });
-
-            let secondary_call_breaker_comp = self.secondary_call_breaker_comp.clone();
-            let secondary_handle = spawn(async move {
-                return send_rewards(
-                    last_sigs,
+                tick.set_status(chain.chain_id, ChainTickStatus::Skipped, None, Some(reason));
+                continue;
+            }
+            let nonce = match chain.nonces.reserve(&db).await {
+                Ok(nonce) => nonce,
+                Err(err) => {
+                    error!(
+                        "Error reserving a nonce on the chain {}: {}",
+                        chain.chain_id, err
+                    );
+                    tick.set_status(
+                        chain.chain_id,
+                        ChainTickStatus::Failed,
+                        None,
+                        Some(format!("Error reserving a nonce: {}", err)),
+                    );
+                    continue;
+                }
+            };
+            record_rewards(
+                &db,
+                chain.chain_id,
+                &mean_time,
+                &chronicles_md5,
+                &shares,
+                RewardStatus::Pending,
+                None,
+            )
+            .await;
+            // Queued backlog receivers fill the transaction up to the receivers limit.
+            let backlog = claim_backlog(
+                &db,
+                chain.chain_id,
+                &chronicles_md5,
+                self.max_receivers_per_tx.saturating_sub(shares.len()),
+            )
+            .await;
+            if !backlog.is_empty() {
+                info!(
+                    "Settling the backlog of {} receivers on the chain {}",
+                    backlog.len(),
+                    chain.chain_id
+                );
+            }
+            let (chain_all_receivers, chain_all_amounts) = merge_backlog(&shares, &backlog);
+            let chain_last_sigs = last_sigs.clone();
+            let call_breaker_comp = chain.call_breaker_comp.clone();
+            let txs = chain.txs.clone();
+            let db = db.clone();
+            let tx_tick = TxTick {
+                chain_id: chain.chain_id,
+                chronicles_md5: chronicles_md5.clone(),
+                nonce,
+            };
+            let handle = spawn(async move {
+                let tx = match prepare_rewards_tx(
+                    chain_last_sigs,
                     mean_time,
-                    all_receivers,
-                    all_amounts,
-                    secondary_call_breaker_comp,
+                    chain_all_receivers,
+                    chain_all_amounts,
+                    call_breaker_comp,
+                    U256::from(nonce),
                 )
-                .await;
+                .await
+                {
+                    Ok(tx) => tx,
+                    Err(failure) => return (None, Err(failure)),
+                };
+                txs.send(tx, tx_tick, db).await
             });
-            match primary_handle.await {
-                Ok(success) => {
-                    if success {
-                        self.curr_md5 = curr_md5;
+            handles.push(async move { (idx, nonce, handle.await) });
+        }
+        // The chains that aren't sent to are settled already.
+        self.publish(tick);
+        let mut handles: FuturesUnordered<_> = handles.into_iter().collect();
+        while let Some((idx, nonce, res)) = handles.next().await {
+            let chain = &mut self.chains[idx];
+            let res = match res {
+                Ok((resumed, res)) => {
+                    if let Some(resumed) = resumed {
+                        chain.settle_resumed(&db, resumed).await;
                     }
+                    Ok(res)
+                }
+                Err(err) => Err(err),
+            };
+            match res {
+                Ok(Ok(receipt)) => {
+                    info!(
+                        "Rewards sent to chain {}, txhash: {:#x}, gas used: {:?}",
+                        chain.chain_id, receipt.transaction_hash, receipt.gas_used
+                    );
+                    chain.nonces.confirm(nonce);
+                    chain.state.record_success(curr_md5);
+                    record_confirmed_tick(&db, chain.chain_id, &mean_time, &last_sigs).await;
+                    tick.set_confirmed(chain.chain_id, receipt.transaction_hash, receipt.gas_used);
+                    settle_rewards(
+                        &db,
+                        chain.chain_id,
+                        &chronicles_md5,
+                        RewardStatus::Confirmed,
+                        Some(receipt.transaction_hash),
+                        None,
+                    )
+                    .await;
+                }
+                Ok(Err(failure @ SendFailure::Stuck(_))) => {
+                    // The transaction may still be mined, so the nonce stays reserved and the
+                    // rewards and the claimed backlog stay pending. The next send resumes the
+                    // transaction and settles them.
+                    chain.state.record_failure(
+                        Instant::now(),
+                        self.retry_backoff,
+                        self.max_retry_backoff,
+                    );
+                    warn!("Sending rewards to chain {}: {}", chain.chain_id, failure);
+                    tick.set_status(
+                        chain.chain_id,
+                        ChainTickStatus::Failed,
+                        failure.tx_hash(),
+                        Some(failure.to_string()),
+                    );
+                }
+                Ok(Err(failure)) => {
+                    // The user objective didn't take effect: it wasn't sent, the revert rolled it
+                    // back, or the account nonce went to another transaction while none of ours
+                    // was mined. So the nonce and the signatures are tried again, and the claimed
+                    // backlog goes back to the queue.
+                    chain.nonces.release(&db, nonce).await;
+                    chain.state.record_failure(
+                        Instant::now(),
+                        self.retry_backoff,
+                        self.max_retry_backoff,
+                    );
+                    warn!(
+                        "Sending rewards to chain {} failed {} times in a row: {}",
+                        chain.chain_id,
+                        chain.state.failures(),
+                        failure
+                    );
+                    tick.set_status(
+                        chain.chain_id,
+                        ChainTickStatus::Failed,
+                        failure.tx_hash(),
+                        Some(failure.to_string()),
+                    );
+                    settle_rewards(
+                        &db,
+                        chain.chain_id,
+                        &chronicles_md5,
+                        RewardStatus::Failed,
+                        failure.tx_hash(),
+                        Some(&failure.to_string()),
+                    )
+                    .await;
+                }
+                Err(err) => {
+                    chain.nonces.release(&db, nonce).await;
+                    chain.state.record_failure(
+                        Instant::now(),
+                        self.retry_backoff,
+                        self.max_retry_backoff,
+                    );
+                    error!(
+                        "Error executing the chain {} awards disbursement: {}",
+                        chain.chain_id, err
+                    );
+                    tick.set_status(
+                        chain.chain_id,
+                        ChainTickStatus::Failed,
+                        None,
+                        Some(err.to_string()),
+                    );
+                    settle_rewards(
+                        &db,
+                        chain.chain_id,
+                        &chronicles_md5,
+                        RewardStatus::Failed,
+                        None,
+                        Some(&err.to_string()),
+                    )
+                    .await;
                 }
-                Err(err) => error!("Error executing the primary awards disbursement: {}", err),
-            }
-            if let Err(err) = secondary_handle.await {
-                error!("Error executing the secondary awards disbursement: {}", err)
             }
+            self.publish(tick);
         }
     }
 }
@@ -366,51 +905,39 @@ mod tests {
     use ethers::{
         providers::{MockProvider, Provider},
         signers::LocalWallet,
-        types::{Address, Bytes},
+        types::{Address, Bytes, TransactionReceipt, H256, U256},
     };
-    use tokio::sync::Mutex;
+    use tokio::sync::{watch, Mutex};
 
-    use crate::{call_breaker::CallBreakerData, time_signature::Chronicle};
+    use crate::{
+        call_breaker::CallBreakerData,
+        consensus::{ConsensusConfig, ConsensusMode, VotePolicy},
+        nonce_manager::NonceManager,
+        referral_schedule::ReferralSchedule,
+        reward_ledger::RewardStatus,
+        send_failure::SendFailure,
+        time_signature::Chronicle,
+        tx_manager::{TxConfig, TxManager},
+    };
 
-    use super::MeanTime;
+    use super::{settled_status, ChainComp, MeanTime};
 
-    #[tokio::test]
-    async fn test_compute_mean_time() -> Result<(), String> {
-        let pool_vec = vec![
-            Chronicle::new(
-                Duration::new(1734220767, 0).as_nanos().into(),
-                Address::from_str("0x25ee756f5d93e26f5011b7ed4866afb192ce483e").unwrap(),
-                Bytes::from_str("0x72315c2259bd482317373295b6f3985e889fcdea6b50ef7344e89a417f7bf6645aac1039674909c314e02be38dc377997a8ea682b366fe1af9a4eb919815140f1c").unwrap()
-            ),
-            Chronicle::new(
-                Duration::new(1734220768, 0).as_nanos().into(),
-                Address::from_str("0x25ee756f5d93e26f5011b7ed4866afb192ce483e").unwrap(),
-                Bytes::from_str("0x72315c2259bd482317373295b6f3985e889fcdea6b50ef7344e89a417f7bf6645aac1039674909c314e02be38dc377997a8ea682b366fe1af9a4eb919815140f1c").unwrap()
-            ),
-            Chronicle::new(
-                Duration::new(1734220760, 0).as_nanos().into(),
-                Address::from_str("0x25ee756f5d93e26f5011b7ed4866afb192ce483e").unwrap(),
-                Bytes::from_str("0x72315c2259bd482317373295b6f3985e889fcdea6b50ef7344e89a417f7bf6645aac1039674909c314e02be38dc377997a8ea682b366fe1af9a4eb919815140f1c").unwrap()
-            ),
-        ];
-        let time_window = parse_duration::parse("2s").unwrap();
-        let pool = Arc::new(Mutex::new(pool_vec));
-        let primary_call_breaker_comp = Arc::new(CallBreakerData::new(
-            Address::from_str("0x8ab3c48c839376d2b79ab98f23f5b2406a06a022").unwrap(),
-            Address::from_str("0x8ab3c48c839376d2b79ab98f23f5b2406a06a029").unwrap(),
+    fn tx_manager(chain_id: u64) -> Arc<TxManager<Provider<MockProvider>>> {
+        Arc::new(TxManager::new(
+            chain_id,
+            Address::zero(),
             Arc::new(Provider::new(MockProvider::new())),
-            LocalWallet::from_str(
-                "0x0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
-            )
-            .unwrap(),
-            LocalWallet::from_str(
-                "0x0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
-            )
-            .unwrap(),
-            Bytes::from_str("0x0000000000000000000000000000000000000000000000000000000000000001")
-                .unwrap(),
-        ));
-        let secondary_call_breaker_comp = Arc::new(CallBreakerData::new(
+            TxConfig {
+                stuck_timeout: Duration::from_secs(60),
+                max_tracking_time: Duration::from_secs(600),
+                fee_bump_percent: 20,
+                max_fee_per_gas: U256::MAX,
+            },
+        ))
+    }
+
+    fn chain_comp(chain_id: u64) -> ChainComp<Provider<MockProvider>> {
+        let call_breaker_comp = Arc::new(CallBreakerData::new(
             Address::from_str("0x8ab3c48c839376d2b79ab98f23f5b2406a06a022").unwrap(),
             Address::from_str("0x8ab3c48c839376d2b79ab98f23f5b2406a06a029").unwrap(),
             Arc::new(Provider::new(MockProvider::new())),
@@ -425,18 +952,55 @@ mod tests {
             Bytes::from_str("0x0000000000000000000000000000000000000000000000000000000000000001")
                 .unwrap(),
         ));
-        let mean_time = MeanTime::new(
-            pool.clone(),
-            primary_call_breaker_comp,
-            secondary_call_breaker_comp,
-            time_window,
+        ChainComp::new(
+            chain_id,
+            call_breaker_comp,
+            Arc::new(Mutex::new(None)),
+            NonceManager::new(chain_id, Address::zero(), 0),
+            tx_manager(chain_id),
+        )
+    }
+
+    // Primary and secondary chains, a 2s time window and the plain mean of the latest votes.
+    fn test_mean_time(pool: Vec<Chronicle>) -> MeanTime<Provider<MockProvider>> {
+        MeanTime::new(
+            Arc::new(Mutex::new(pool)),
+            vec![chain_comp(21363), chain_comp(84532)],
+            parse_duration::parse("2s").unwrap(),
+            ConsensusConfig::new(ConsensusMode::Mean, VotePolicy::Latest, 0.1, 3.0),
+            Arc::new(ReferralSchedule::default()),
+            Duration::from_secs(1),
+            Duration::from_secs(60),
+            100,
             false,
-        );
+            Arc::new(watch::channel(None).0),
+        )
+    }
+
+    fn chronicle(secs: u64, keeper: &str) -> Chronicle {
+        Chronicle::new(
+            Duration::new(secs, 0).as_nanos().into(),
+            Address::from_str(keeper).unwrap(),
+            Bytes::from_str("0x72315c2259bd482317373295b6f3985e889fcdea6b50ef7344e89a417f7bf6645aac1039674909c314e02be38dc377997a8ea682b366fe1af9a4eb919815140f1c").unwrap()
+        )
+    }
+
+    const KEEPER_A: &str = "0x25ee756f5d93e26f5011b7ed4866afb192ce483e";
+    const KEEPER_B: &str = "0x2c57d1cfc6d5f8e4182a56b4cf75421472ebaea4";
+
+    #[tokio::test]
+    async fn test_compute_mean_time() -> Result<(), String> {
+        let mean_time = test_mean_time(vec![
+            chronicle(1734220767, KEEPER_A),
+            chronicle(1734220768, KEEPER_B),
+            chronicle(1734220760, KEEPER_A),
+        ]);
         let test_res_opt = mean_time
             .compute_mean_time(Duration::new(1734220768, 0))
             .await;
         assert_ne!(test_res_opt, None);
-        let (mean_time_val, sigs) = test_res_opt.unwrap();
+        let consensus = test_res_opt.unwrap();
+        let (mean_time_val, sigs) = (consensus.mean_time, consensus.accepted);
         assert_eq!(
             mean_time_val,
             Duration::new(1734220767, 500000000).as_nanos().into()
@@ -449,67 +1013,17 @@ mod tests {
 
     #[tokio::test]
     async fn test_compute_mean_time_last_too_new() -> Result<(), String> {
-        let pool_vec = vec![
-            Chronicle::new(
-                Duration::new(1734220767, 0).as_nanos().into(),
-                Address::from_str("0x25ee756f5d93e26f5011b7ed4866afb192ce483e").unwrap(),
-                Bytes::from_str("0x72315c2259bd482317373295b6f3985e889fcdea6b50ef7344e89a417f7bf6645aac1039674909c314e02be38dc377997a8ea682b366fe1af9a4eb919815140f1c").unwrap()
-            ),
-            Chronicle::new(
-                Duration::new(1734220768, 0).as_nanos().into(),
-                Address::from_str("0x25ee756f5d93e26f5011b7ed4866afb192ce483e").unwrap(),
-                Bytes::from_str("0x72315c2259bd482317373295b6f3985e889fcdea6b50ef7344e89a417f7bf6645aac1039674909c314e02be38dc377997a8ea682b366fe1af9a4eb919815140f1c").unwrap()
-            ),
-            Chronicle::new(
-                Duration::new(1734220760, 0).as_nanos().into(),
-                Address::from_str("0x25ee756f5d93e26f5011b7ed4866afb192ce483e").unwrap(),
-                Bytes::from_str("0x72315c2259bd482317373295b6f3985e889fcdea6b50ef7344e89a417f7bf6645aac1039674909c314e02be38dc377997a8ea682b366fe1af9a4eb919815140f1c").unwrap()
-            ),
-        ];
-        let time_window = parse_duration::parse("2s").unwrap();
-        let pool = Arc::new(Mutex::new(pool_vec));
-        let primary_call_breaker_comp = Arc::new(CallBreakerData::new(
-            Address::from_str("0x8ab3c48c839376d2b79ab98f23f5b2406a06a022").unwrap(),
-            Address::from_str("0x8ab3c48c839376d2b79ab98f23f5b2406a06a029").unwrap(),
-            Arc::new(Provider::new(MockProvider::new())),
-            LocalWallet::from_str(
-                "0x0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
-            )
-            .unwrap(),
-            LocalWallet::from_str(
-                "0x0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
-            )
-            .unwrap(),
-            Bytes::from_str("0x0000000000000000000000000000000000000000000000000000000000000001")
-                .unwrap(),
-        ));
-        let secondary_call_breaker_comp = Arc::new(CallBreakerData::new(
-            Address::from_str("0x8ab3c48c839376d2b79ab98f23f5b2406a06a022").unwrap(),
-            Address::from_str("0x8ab3c48c839376d2b79ab98f23f5b2406a06a029").unwrap(),
-            Arc::new(Provider::new(MockProvider::new())),
-            LocalWallet::from_str(
-                "0x0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
-            )
-            .unwrap(),
-            LocalWallet::from_str(
-                "0x0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
-            )
-            .unwrap(),
-            Bytes::from_str("0x0000000000000000000000000000000000000000000000000000000000000001")
-                .unwrap(),
-        ));
-        let mean_time = MeanTime::new(
-            pool.clone(),
-            primary_call_breaker_comp,
-            secondary_call_breaker_comp,
-            time_window,
-            false,
-        );
+        let mean_time = test_mean_time(vec![
+            chronicle(1734220767, KEEPER_A),
+            chronicle(1734220768, KEEPER_B),
+            chronicle(1734220760, KEEPER_A),
+        ]);
         let test_res_opt = mean_time
             .compute_mean_time(Duration::new(1734220767, 0))
             .await;
         assert_ne!(test_res_opt, None);
-        let (mean_time_val, sigs) = test_res_opt.unwrap();
+        let consensus = test_res_opt.unwrap();
+        let (mean_time_val, sigs) = (consensus.mean_time, consensus.accepted);
         assert_eq!(
             mean_time_val,
             Duration::new(1734220767, 0).as_nanos().into()
@@ -521,51 +1035,51 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_compute_mean_time_empty() -> Result<(), String> {
-        let pool_vec = Vec::new();
-        let time_window = parse_duration::parse("2s").unwrap();
-        let pool = Arc::new(Mutex::new(pool_vec));
-        let primary_call_breaker_comp = Arc::new(CallBreakerData::new(
-            Address::from_str("0x8ab3c48c839376d2b79ab98f23f5b2406a06a022").unwrap(),
-            Address::from_str("0x8ab3c48c839376d2b79ab98f23f5b2406a06a029").unwrap(),
-            Arc::new(Provider::new(MockProvider::new())),
-            LocalWallet::from_str(
-                "0x0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
-            )
-            .unwrap(),
-            LocalWallet::from_str(
-                "0x0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
-            )
-            .unwrap(),
-            Bytes::from_str("0x0000000000000000000000000000000000000000000000000000000000000001")
-                .unwrap(),
-        ));
-        let secondary_call_breaker_comp = Arc::new(CallBreakerData::new(
-            Address::from_str("0x8ab3c48c839376d2b79ab98f23f5b2406a06a022").unwrap(),
-            Address::from_str("0x8ab3c48c839376d2b79ab98f23f5b2406a06a029").unwrap(),
-            Arc::new(Provider::new(MockProvider::new())),
-            LocalWallet::from_str(
-                "0x0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
-            )
-            .unwrap(),
-            LocalWallet::from_str(
-                "0x0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
-            )
-            .unwrap(),
-            Bytes::from_str("0x0000000000000000000000000000000000000000000000000000000000000001")
-                .unwrap(),
-        ));
-        let mean_time = MeanTime::new(
-            pool.clone(),
-            primary_call_breaker_comp,
-            secondary_call_breaker_comp,
-            time_window,
-            false,
+    async fn test_compute_mean_time_one_vote_per_keeper() -> Result<(), String> {
+        let mean_time = test_mean_time(vec![
+            chronicle(1734220767, KEEPER_A),
+            chronicle(1734220768, KEEPER_A),
+        ]);
+        let test_res_opt = mean_time
+            .compute_mean_time(Duration::new(1734220768, 0))
+            .await;
+        assert_ne!(test_res_opt, None);
+        let consensus = test_res_opt.unwrap();
+        assert_eq!(
+            consensus.mean_time,
+            Duration::new(1734220768, 0).as_nanos().into()
         );
+        assert_eq!(consensus.accepted.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compute_mean_time_empty() -> Result<(), String> {
+        let mean_time = test_mean_time(Vec::new());
         let test_res_opt = mean_time
             .compute_mean_time(Duration::new(1734220768, 0))
             .await;
         assert_eq!(test_res_opt, None);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_settled_status() -> Result<(), String> {
+        let tx_hash = H256::repeat_byte(1);
+        assert_eq!(
+            settled_status(&Ok(TransactionReceipt::default())),
+            Some(RewardStatus::Confirmed)
+        );
+        // A stuck transaction may still be mined, its rewards and claimed backlog stay pending.
+        assert_eq!(settled_status(&Err(SendFailure::Stuck(tx_hash))), None);
+        // A transaction blocked behind it was never sent, its backlog goes back to the queue.
+        let blocked = SendFailure::Blocked(tx_hash);
+        assert_eq!(blocked.tx_hash(), None);
+        assert_eq!(settled_status(&Err(blocked)), Some(RewardStatus::Failed));
+        assert_eq!(
+            settled_status(&Err(SendFailure::Dropped(tx_hash))),
+            Some(RewardStatus::Failed)
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,375 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    error::Error,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use ethers::types::{Address, Bytes, U256};
+
+use crate::{
+    db::ReferrerLink,
+    db_pool::DbError,
+    referral::ReferralData,
+    referral_tree::{Keeper, Link},
+    store::{KeeperStore, NonceStore, ReferralStore, StoreFuture, StorePool},
+};
+
+// In-memory store with the semantics of the MySQL tables, addresses are always stored in full.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    keepers: BTreeMap<String, Keeper>,
+    referred_at: HashMap<String, u64>,
+    referrals: HashMap<String, String>,
+    seen_signatures: HashSet<Bytes>,
+    chronicle_nonces: HashMap<String, u64>,
+    // By chain id and solver.
+    user_objective_nonces: HashMap<(u64, Address), u64>,
+    released_nonces: BTreeSet<(u64, Address, u64)>,
+}
+
+impl MemoryStore {
+    // Adds a time keeper with its referral links as they would be in the database.
+    pub fn insert_keeper(
+        &mut self,
+        addr: &Address,
+        referral_code: Option<&str>,
+        referred_from: Option<&str>,
+    ) {
+        let address = format!("{:#x}", addr);
+        self.keepers.insert(
+            address.clone(),
+            Keeper {
+                address,
+                avatar: None,
+                referral_code: referral_code.map(str::to_string),
+                referred_from: referred_from.map(str::to_string),
+            },
+        );
+    }
+
+    pub fn set_referred_at(&mut self, addr: &Address, referred_at: u64) {
+        self.referred_at.insert(format!("{:#x}", addr), referred_at);
+    }
+
+    pub fn keeper(&self, addr: &Address) -> Option<&Keeper> {
+        self.keepers.get(&format!("{:#x}", addr))
+    }
+
+    fn keeper_mut(&mut self, addr: &Address) -> Option<&mut Keeper> {
+        self.keepers.get_mut(&format!("{:#x}", addr))
+    }
+}
+
+impl KeeperStore for MemoryStore {
+    fn store_user_data(&mut self, addr: &Address, avatar: &str) -> Result<(), Box<dyn Error>> {
+        let address = format!("{:#x}", addr);
+        self.keepers.entry(address.clone()).or_insert(Keeper {
+            address,
+            avatar: Some(avatar.to_string()),
+            referral_code: None,
+            referred_from: None,
+        });
+        Ok(())
+    }
+
+    fn is_address_whitelisted(&mut self, addr: &Address) -> Result<bool, Box<dyn Error>> {
+        Ok(self.keeper(addr).is_some())
+    }
+
+    fn get_time_keepers_count(&mut self) -> Result<u64, Box<dyn Error>> {
+        Ok(self.keepers.len() as u64)
+    }
+
+    fn is_avatar_available(
+        &mut self,
+        addr: &Address,
+        avatar: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        let address = format!("{:#x}", addr);
+        Ok(!self
+            .keepers
+            .values()
+            .any(|el| el.address != address && el.avatar.as_deref() == Some(avatar)))
+    }
+
+    fn update_avatar(&mut self, addr: &Address, avatar: &str) -> Result<bool, Box<dyn Error>> {
+        if !self.is_avatar_available(addr, avatar)? {
+            return Ok(false);
+        }
+        if let Some(keeper) = self.keeper_mut(addr) {
+            keeper.avatar = Some(avatar.to_string());
+        }
+        Ok(true)
+    }
+
+    fn update_referral_code(
+        &mut self,
+        addr: &Address,
+        referral_code: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        let address = format!("{:#x}", addr);
+        if self
+            .keepers
+            .values()
+            .any(|el| el.address != address && el.referral_code.as_deref() == Some(referral_code))
+        {
+            return Ok(false);
+        }
+        let Some(keeper) = self.keeper_mut(addr) else {
+            return Ok(true);
+        };
+        let existing = keeper.referral_code.replace(referral_code.to_string());
+        if let Some(existing) = existing {
+            for keeper in self.keepers.values_mut() {
+                if keeper.referred_from.as_deref() == Some(existing.as_str()) {
+                    keeper.referred_from = Some(referral_code.to_string());
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn update_referred_from(
+        &mut self,
+        addr: &Address,
+        referred_from: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(keeper) = self.keeper_mut(addr) else {
+            return Ok(());
+        };
+        keeper.referred_from = Some(referred_from.to_string());
+        let curr_ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO);
+        self.referred_at
+            .entry(format!("{:#x}", addr))
+            .or_insert(curr_ts.as_secs());
+        Ok(())
+    }
+
+    fn read_referred_at(&mut self, addr: &Address) -> Result<Option<u64>, Box<dyn Error>> {
+        Ok(self.referred_at.get(&format!("{:#x}", addr)).copied())
+    }
+
+    fn read_keeper(&mut self, addr: &Address) -> Result<Option<Keeper>, Box<dyn Error>> {
+        Ok(self.keeper(addr).cloned())
+    }
+
+    fn read_keeper_by_referral_code(
+        &mut self,
+        referral_code: &str,
+    ) -> Result<Option<Keeper>, Box<dyn Error>> {
+        Ok(self
+            .keepers
+            .values()
+            .find(|el| el.referral_code.as_deref() == Some(referral_code))
+            .cloned())
+    }
+
+    fn store_seen_signature(
+        &mut self,
+        signature: &Bytes,
+        _epoch: &U256,
+    ) -> Result<bool, Box<dyn Error>> {
+        Ok(self.seen_signatures.insert(signature.clone()))
+    }
+
+    fn advance_chronicle_nonce(
+        &mut self,
+        addr: &Address,
+        nonce: u64,
+    ) -> Result<bool, Box<dyn Error>> {
+        let address = format!("{:#x}", addr);
+        match self.chronicle_nonces.get(&address) {
+            Some(last) if *last >= nonce => Ok(false),
+            _ => {
+                self.chronicle_nonces.insert(address, nonce);
+                Ok(true)
+            }
+        }
+    }
+
+    fn ping(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+impl ReferralStore for MemoryStore {
+    fn read_referral(&mut self, ref_key: &str) -> Result<String, Box<dyn Error>> {
+        Ok(self.referrals.get(ref_key).cloned().unwrap_or_default())
+    }
+
+    fn write_referral(&mut self, ref_data: &ReferralData) -> Result<(), Box<dyn Error>> {
+        self.referrals
+            .entry(ref_data.refkey.clone())
+            .or_insert(ref_data.refvalue.clone());
+        Ok(())
+    }
+
+    fn read_referrers(
+        &mut self,
+        addresses: &[String],
+    ) -> Result<Vec<ReferrerLink>, Box<dyn Error>> {
+        let mut referrers = Vec::new();
+        for address in addresses {
+            let Some(referred_from) = self.keepers.get(address).and_then(|el| el.referred_from())
+            else {
+                continue;
+            };
+            for referrer in self.keepers.values() {
+                if referrer.referral_code.as_deref() == Some(referred_from) {
+                    referrers.push((
+                        address.clone(),
+                        referrer.address.clone(),
+                        referred_from.to_string(),
+                    ));
+                }
+            }
+        }
+        Ok(referrers)
+    }
+
+    fn read_downline(
+        &mut self,
+        keeper: &Keeper,
+        depth: u32,
+        offset: u64,
+        limit: u64,
+    ) -> Result<(u64, Vec<Link>), Box<dyn Error>> {
+        let mut downline = Vec::new();
+        let mut level_parents = match keeper.referral_code() {
+            Some(code) => vec![(code.to_string(), keeper.address.clone())],
+            None => Vec::new(),
+        };
+        for level in 1..=depth {
+            let mut next_parents = Vec::new();
+            for (code, parent) in &level_parents {
+                for referee in self.keepers.values().filter(|el| {
+                    el.address != keeper.address && el.referred_from.as_deref() == Some(code)
+                }) {
+                    if let Some(referee_code) = referee.referral_code() {
+                        next_parents.push((referee_code.to_string(), referee.address.clone()));
+                    }
+                    downline.push((referee.clone(), level, parent.clone()));
+                }
+            }
+            level_parents = next_parents;
+        }
+        downline.sort_by(|a, b| (a.1, &a.0.address).cmp(&(b.1, &b.0.address)));
+        let total = downline.len() as u64;
+        let page = downline
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect();
+        Ok((total, page))
+    }
+
+    // The memory store keeps no rewards, so no referee is active.
+    fn read_referral_stats(
+        &mut self,
+        addresses: &[&str],
+        _active_period: Duration,
+    ) -> Result<HashMap<String, (u64, u64)>, Box<dyn Error>> {
+        let mut stats = HashMap::new();
+        for address in addresses {
+            let Some(code) = self.keepers.get(*address).and_then(|el| el.referral_code()) else {
+                continue;
+            };
+            let referees = self
+                .keepers
+                .values()
+                .filter(|el| el.referred_from.as_deref() == Some(code))
+                .count() as u64;
+            if referees > 0 {
+                stats.insert(address.to_string(), (referees, 0));
+            }
+        }
+        Ok(stats)
+    }
+
+    fn read_referral_earnings(
+        &mut self,
+        _addresses: &[&str],
+    ) -> Result<HashMap<String, String>, Box<dyn Error>> {
+        Ok(HashMap::new())
+    }
+}
+
+// Shares one memory store like the pool shares the database.
+#[derive(Clone, Default)]
+pub struct MemoryPool(pub Arc<Mutex<MemoryStore>>);
+
+impl NonceStore for MemoryStore {
+    fn read_user_objective_nonce(
+        &mut self,
+        chain_id: u64,
+        solver: &Address,
+    ) -> Result<Option<u64>, Box<dyn Error>> {
+        Ok(self
+            .user_objective_nonces
+            .get(&(chain_id, *solver))
+            .copied())
+    }
+
+    fn store_user_objective_nonce(
+        &mut self,
+        chain_id: u64,
+        solver: &Address,
+        next_nonce: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        self.user_objective_nonces
+            .insert((chain_id, *solver), next_nonce);
+        Ok(())
+    }
+
+    fn read_released_nonces(
+        &mut self,
+        chain_id: u64,
+        solver: &Address,
+    ) -> Result<Vec<u64>, Box<dyn Error>> {
+        Ok(self
+            .released_nonces
+            .iter()
+            .filter(|el| el.0 == chain_id && el.1 == *solver)
+            .map(|el| el.2)
+            .collect())
+    }
+
+    fn store_released_nonce(
+        &mut self,
+        chain_id: u64,
+        solver: &Address,
+        nonce: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        self.released_nonces.insert((chain_id, *solver, nonce));
+        Ok(())
+    }
+
+    fn delete_released_nonce(
+        &mut self,
+        chain_id: u64,
+        solver: &Address,
+        nonce: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        self.released_nonces.remove(&(chain_id, *solver, nonce));
+        Ok(())
+    }
+}
+
+impl StorePool for MemoryPool {
+    type Store = MemoryStore;
+
+    fn with_store<T, F>(&self, queries: F) -> StoreFuture<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut MemoryStore) -> Result<T, Box<dyn Error>> + Send + 'static,
+    {
+        let res = match self.0.lock() {
+            Ok(mut store) => queries(&mut store).map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        Box::pin(async move { res.map_err(DbError::from) })
+    }
+}
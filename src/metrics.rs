@@ -0,0 +1,253 @@
+use std::{
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http::{header::CONTENT_TYPE, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ethers::{types::U256, utils::format_units};
+use log::error;
+use prometheus::{
+    exponential_buckets, Encoder, Gauge, GaugeVec, Histogram, HistogramOpts, HistogramTimer,
+    HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use tokio::sync::Mutex;
+
+use crate::{consensus::Consensus, time_pool::TimeSigPool};
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    // Labeled by `accepted` or the rejection code.
+    time_sigs: IntCounterVec,
+    pool_size: IntGauge,
+    tick_duration: Histogram,
+    // Distance between the earliest and the latest accepted chronicle of the last tick.
+    mean_time_spread: Gauge,
+    chronicles_per_tick: Histogram,
+    // Labeled by the chain and `sent`, `confirmed` or `reverted`.
+    txs: IntCounterVec,
+    gas_used: IntCounterVec,
+    solver_balance: GaugeVec,
+    db_query_duration: HistogramVec,
+    http_request_duration: HistogramVec,
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let metrics = Metrics {
+            registry: Registry::new_custom(Some("blockclock".to_string()), None)
+                .expect("valid registry"),
+            time_sigs: IntCounterVec::new(
+                Opts::new("time_sigs_total", "Chronicles received by result"),
+                &["result"],
+            )
+            .expect("valid metric"),
+            pool_size: IntGauge::new("time_sig_pool_size", "Chronicles waiting for the tick")
+                .expect("valid metric"),
+            tick_duration: Histogram::with_opts(HistogramOpts::new(
+                "tick_duration_seconds",
+                "Time to compute the mean time and submit it to all chains",
+            ))
+            .expect("valid metric"),
+            mean_time_spread: Gauge::new(
+                "mean_time_spread_seconds",
+                "Spread of the accepted chronicles of the last tick",
+            )
+            .expect("valid metric"),
+            chronicles_per_tick: Histogram::with_opts(
+                HistogramOpts::new("chronicles_per_tick", "Accepted chronicles per tick")
+                    .buckets(exponential_buckets(1.0, 2.0, 12).expect("valid buckets")),
+            )
+            .expect("valid metric"),
+            txs: IntCounterVec::new(
+                Opts::new("txs_total", "Solver transactions by status"),
+                &["chain_id", "status"],
+            )
+            .expect("valid metric"),
+            gas_used: IntCounterVec::new(
+                Opts::new(
+                    "gas_used_total",
+                    "Gas used by the mined solver transactions",
+                ),
+                &["chain_id"],
+            )
+            .expect("valid metric"),
+            solver_balance: GaugeVec::new(
+                Opts::new("solver_balance_ether", "Native balance of the solver"),
+                &["chain_id"],
+            )
+            .expect("valid metric"),
+            db_query_duration: HistogramVec::new(
+                HistogramOpts::new("db_query_duration_seconds", "Latency of the db queries")
+                    .buckets(exponential_buckets(0.0005, 2.0, 14).expect("valid buckets")),
+                &["query"],
+            )
+            .expect("valid metric"),
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "Latency of the HTTP routes",
+                ),
+                &["method", "route", "status"],
+            )
+            .expect("valid metric"),
+        };
+        let collectors: [Box<dyn prometheus::core::Collector>; 10] = [
+            Box::new(metrics.time_sigs.clone()),
+            Box::new(metrics.pool_size.clone()),
+            Box::new(metrics.tick_duration.clone()),
+            Box::new(metrics.mean_time_spread.clone()),
+            Box::new(metrics.chronicles_per_tick.clone()),
+            Box::new(metrics.txs.clone()),
+            Box::new(metrics.gas_used.clone()),
+            Box::new(metrics.solver_balance.clone()),
+            Box::new(metrics.db_query_duration.clone()),
+            Box::new(metrics.http_request_duration.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).expect("unique metric");
+        }
+        metrics
+    }
+
+    pub fn record_time_sig(&self, result: &str) {
+        self.time_sigs.with_label_values(&[result]).inc();
+    }
+
+    pub fn record_consensus(&self, consensus: &Consensus) {
+        self.chronicles_per_tick
+            .observe(consensus.accepted.len() as f64);
+        self.mean_time_spread.set(spread_secs(consensus));
+    }
+
+    pub fn record_tick_duration(&self, duration: Duration) {
+        self.tick_duration.observe(duration.as_secs_f64());
+    }
+
+    pub fn record_tx(&self, chain_id: u64, status: &str) {
+        self.txs
+            .with_label_values(&[&chain_id.to_string(), status])
+            .inc();
+    }
+
+    pub fn record_gas_used(&self, chain_id: u64, gas_used: U256) {
+        self.gas_used
+            .with_label_values(&[&chain_id.to_string()])
+            .inc_by(gas_used.low_u64());
+    }
+
+    pub fn set_solver_balance(&self, chain_id: u64, balance: U256) {
+        match format_units(balance, "ether").map(|el| el.parse::<f64>()) {
+            Ok(Ok(balance)) => self
+                .solver_balance
+                .with_label_values(&[&chain_id.to_string()])
+                .set(balance),
+            _ => error!("Can't convert the solver balance {}", balance),
+        }
+    }
+
+    // Observes the query latency when dropped.
+    pub fn db_timer(&self, query: &str) -> HistogramTimer {
+        self.db_query_duration
+            .with_label_values(&[query])
+            .start_timer()
+    }
+
+    fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+}
+
+// Chronicle epochs are in nanoseconds.
+fn spread_secs(consensus: &Consensus) -> f64 {
+    let epochs = consensus.accepted.iter().map(|el| el.epoch);
+    match (epochs.clone().min(), epochs.max()) {
+        (Some(min), Some(max)) => Duration::from_nanos((max - min).low_u64()).as_secs_f64(),
+        _ => 0.0,
+    }
+}
+
+// Unmatched requests are left out, the route label would take any path.
+pub async fn track_http(path: MatchedPath, request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let started_at = Instant::now();
+    let response = next.run(request).await;
+    METRICS
+        .http_request_duration
+        .with_label_values(&[method.as_str(), path.as_str(), response.status().as_str()])
+        .observe(started_at.elapsed().as_secs_f64());
+    response
+}
+
+pub async fn handle_metrics(pool: Arc<Mutex<TimeSigPool>>) -> Response {
+    METRICS.pool_size.set(pool.lock().await.len() as i64);
+    match METRICS.render() {
+        Ok(body) => ([(CONTENT_TYPE, TextEncoder::new().format_type())], body).into_response(),
+        Err(err) => {
+            error!("Error encoding the metrics: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, sync::Arc, time::Duration};
+
+    use axum::body::to_bytes;
+    use ethers::types::{Address, Bytes, U256};
+    use tokio::sync::Mutex;
+
+    use crate::{consensus::Consensus, time_signature::Chronicle};
+
+    use super::{handle_metrics, METRICS};
+
+    #[tokio::test]
+    async fn test_metrics() -> Result<(), String> {
+        let chronicle = |epoch: u64| {
+            Chronicle::new(
+                U256::from(epoch),
+                Address::from_low_u64_be(epoch),
+                Bytes::from_str("0x01").unwrap(),
+            )
+        };
+        let consensus = Consensus {
+            mean_time: U256::from(2_000_000_000u64),
+            accepted: vec![chronicle(1_500_000_000), chronicle(2_500_000_000)],
+            rejected: vec![],
+        };
+        METRICS.record_consensus(&consensus);
+        METRICS.record_tick_duration(Duration::from_millis(20));
+        METRICS.record_time_sig("signature_replayed");
+        METRICS.record_tx(21363, "confirmed");
+        METRICS.set_solver_balance(21363, U256::exp10(18) / 2);
+        drop(METRICS.db_timer("read_keeper"));
+
+        let pool = Arc::new(Mutex::new(vec![chronicle(1)]));
+        let response = handle_metrics(pool).await;
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .map_err(|err| err.to_string())?;
+        let body = String::from_utf8_lossy(&body);
+        for line in [
+            "blockclock_time_sig_pool_size 1",
+            "blockclock_mean_time_spread_seconds 1",
+            "blockclock_solver_balance_ether{chain_id=\"21363\"} 0.5",
+            "blockclock_time_sigs_total{result=\"signature_replayed\"}",
+            "blockclock_txs_total{chain_id=\"21363\",status=\"confirmed\"}",
+            "blockclock_db_query_duration_seconds_count{query=\"read_keeper\"}",
+            "blockclock_chronicles_per_tick_bucket{le=\"2\"}",
+        ] {
+            assert!(body.contains(line), "missing {}", line);
+        }
+        Ok(())
+    }
+}
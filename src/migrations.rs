@@ -0,0 +1,159 @@
+use std::error::Error;
+
+use log::info;
+use mysql::Conn;
+
+use crate::{
+    address_str::canonicalize_addresses_strict,
+    db::{add_referred_at_column, apply_migration, read_schema_version},
+    db_pool::{DbError, DbPool},
+};
+
+pub type DataMigration = fn(&mut Conn) -> Result<(), Box<dyn Error>>;
+
+// Schema changes in the order they are applied, versions never change once released.
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+    // Rewrites the data before the statements, for changes that SQL alone can't make.
+    pub before: Option<DataMigration>,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "baseline",
+        sql: include_str!("../db/migrations/001_baseline.sql"),
+        before: None,
+    },
+    Migration {
+        version: 2,
+        name: "signatures_and_rewards",
+        sql: include_str!("../db/migrations/002_signatures_and_rewards.sql"),
+        before: Some(add_referred_at_column),
+    },
+    Migration {
+        version: 3,
+        name: "canonical_addresses",
+        sql: include_str!("../db/migrations/003_canonical_addresses.sql"),
+        before: Some(canonicalize_addresses_strict),
+    },
+    Migration {
+        version: 4,
+        name: "chronicle_nonces",
+        sql: include_str!("../db/migrations/004_chronicle_nonces.sql"),
+        before: None,
+    },
+    Migration {
+        version: 5,
+        name: "confirmed_ticks",
+        sql: include_str!("../db/migrations/005_confirmed_ticks.sql"),
+        before: None,
+    },
+    Migration {
+        version: 6,
+        name: "released_nonces",
+        sql: include_str!("../db/migrations/006_released_nonces.sql"),
+        before: None,
+    },
+];
+
+#[derive(Debug, PartialEq)]
+pub enum SchemaStatus {
+    UpToDate(u32),
+    Pending { current: u32, latest: u32 },
+    // The database was migrated by a newer version of the service.
+    Ahead { current: u32, latest: u32 },
+}
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|el| el.version).unwrap_or_default()
+}
+
+pub fn schema_status(current: u32) -> SchemaStatus {
+    let latest = latest_version();
+    if current < latest {
+        SchemaStatus::Pending { current, latest }
+    } else if current > latest {
+        SchemaStatus::Ahead { current, latest }
+    } else {
+        SchemaStatus::UpToDate(current)
+    }
+}
+
+// Splits a migration into statements, the migrations don't have semicolons in strings.
+pub fn split_statements(sql: &str) -> Vec<String> {
+    let sql: Vec<&str> = sql
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("--"))
+        .collect();
+    sql.join("\n")
+        .split(';')
+        .map(|statement| statement.trim().to_string())
+        .filter(|statement| !statement.is_empty())
+        .collect()
+}
+
+pub async fn read_schema_status(db: &DbPool) -> Result<SchemaStatus, DbError> {
+    let current = db.run(read_schema_version).await?;
+    Ok(schema_status(current))
+}
+
+// Applies the pending migrations one by one, returns the status after them.
+pub async fn migrate(db: &DbPool) -> Result<SchemaStatus, DbError> {
+    let current = db.run(read_schema_version).await?;
+    if let SchemaStatus::Ahead { .. } = schema_status(current) {
+        return Ok(schema_status(current));
+    }
+    for migration in MIGRATIONS.iter().filter(|el| el.version > current) {
+        info!(
+            "Applying migration {} {} ...",
+            migration.version, migration.name
+        );
+        let statements = split_statements(migration.sql);
+        db.run(move |conn| {
+            if let Some(before) = migration.before {
+                before(conn)?;
+            }
+            apply_migration(conn, migration.version, migration.name, &statements)
+        })
+        .await?;
+    }
+    read_schema_status(db).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{schema_status, split_statements, SchemaStatus, MIGRATIONS};
+
+    #[tokio::test]
+    async fn test_migrations() -> Result<(), String> {
+        for (idx, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version as usize, idx + 1);
+            assert!(!split_statements(migration.sql).is_empty());
+        }
+        assert_eq!(
+            split_statements(
+                "-- Comment; with a semicolon\nCREATE TABLE a(id INT);\n\n-- Trailing\n"
+            ),
+            ["CREATE TABLE a(id INT)"]
+        );
+        assert_eq!(split_statements(MIGRATIONS[1].sql).len(), 5);
+
+        let latest = MIGRATIONS.len() as u32;
+        assert_eq!(
+            schema_status(0),
+            SchemaStatus::Pending { current: 0, latest }
+        );
+        assert_eq!(schema_status(latest), SchemaStatus::UpToDate(latest));
+        assert_eq!(
+            schema_status(latest + 1),
+            SchemaStatus::Ahead {
+                current: latest + 1,
+                latest
+            }
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::BTreeSet;
+
+use ethers::types::Address;
+use log::error;
+
+use crate::{
+    db_pool::DbError,
+    store::{NonceStore, StorePool},
+};
+
+// Tracks the user objective nonces of one solver on one chain.
+// The next unused nonce is persisted before it's handed out, so restarts never reuse a nonce.
+// Released nonces are persisted too, the CallBreaker keeps no nonce per solver to recover them.
+pub struct NonceManager {
+    chain_id: u64,
+    solver: Address,
+    next: u64,
+    in_flight: BTreeSet<u64>,
+    released: BTreeSet<u64>,
+}
+
+impl NonceManager {
+    pub fn new(chain_id: u64, solver: Address, next: u64) -> NonceManager {
+        NonceManager {
+            chain_id,
+            solver,
+            next,
+            in_flight: BTreeSet::new(),
+            released: BTreeSet::new(),
+        }
+    }
+
+    // Starts from the persisted nonces, or from 0 if the solver never sent on this chain.
+    pub async fn load<S: StorePool>(
+        db: &S,
+        chain_id: u64,
+        solver: Address,
+    ) -> Result<NonceManager, DbError> {
+        let (next, released) = db
+            .with_store(move |store| {
+                let next = store.read_user_objective_nonce(chain_id, &solver)?;
+                let released = store.read_released_nonces(chain_id, &solver)?;
+                Ok((next.unwrap_or(0), released))
+            })
+            .await?;
+        let mut nonces = NonceManager::new(chain_id, solver, next);
+        nonces.released.extend(released);
+        Ok(nonces)
+    }
+
+    // Reserves a nonce for a new user objective, reusing released ones first.
+    pub async fn reserve<S: StorePool>(&mut self, db: &S) -> Result<u64, DbError> {
+        let (chain_id, solver) = (self.chain_id, self.solver);
+        if let Some(nonce) = self.released.first().copied() {
+            db.with_store(move |store| store.delete_released_nonce(chain_id, &solver, nonce))
+                .await?;
+            self.released.remove(&nonce);
+            self.in_flight.insert(nonce);
+            return Ok(nonce);
+        }
+        let nonce = self.next;
+        db.with_store(move |store| store.store_user_objective_nonce(chain_id, &solver, nonce + 1))
+            .await?;
+        self.next = nonce + 1;
+        self.in_flight.insert(nonce);
+        Ok(nonce)
+    }
+
+    // A user objective sent before the restart is in flight again, its transaction is resumed.
+    pub fn resume(&mut self, nonce: u64) {
+        if nonce < self.next && !self.released.contains(&nonce) {
+            self.in_flight.insert(nonce);
+        }
+    }
+
+    // The user objective with the nonce was executed on chain.
+    pub fn confirm(&mut self, nonce: u64) {
+        self.in_flight.remove(&nonce);
+    }
+
+    // The user objective with the nonce failed or was dropped, the nonce can be used again.
+    // If persisting fails the nonce is still reused until the restart, and skipped after it.
+    pub async fn release<S: StorePool>(&mut self, db: &S, nonce: u64) {
+        if !self.in_flight.remove(&nonce) {
+            return;
+        }
+        let (chain_id, solver) = (self.chain_id, self.solver);
+        if let Err(err) = db
+            .with_store(move |store| store.store_released_nonce(chain_id, &solver, nonce))
+            .await
+        {
+            error!(
+                "Error persisting the released nonce {} on the chain {}: {}",
+                nonce, chain_id, err
+            );
+        }
+        self.released.insert(nonce);
+    }
+
+    pub fn next(&self) -> u64 {
+        self.next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::Address;
+
+    use crate::memory_store::MemoryPool;
+
+    use super::NonceManager;
+
+    #[tokio::test]
+    async fn test_release_and_confirm() -> Result<(), String> {
+        let db = MemoryPool::default();
+        let solver = Address::from_low_u64_be(1);
+        let mut nonces = NonceManager::load(&db, 21363, solver)
+            .await
+            .map_err(|err| err.to_string())?;
+        assert_eq!(nonces.reserve(&db).await.map_err(|err| err.to_string())?, 0);
+        assert_eq!(nonces.reserve(&db).await.map_err(|err| err.to_string())?, 1);
+        nonces.confirm(0);
+        nonces.release(&db, 1).await;
+        // Releasing an unknown nonce is ignored.
+        nonces.release(&db, 42).await;
+
+        // The released nonce survives a restart and is reused before the next one.
+        let mut nonces = NonceManager::load(&db, 21363, solver)
+            .await
+            .map_err(|err| err.to_string())?;
+        assert_eq!(nonces.next(), 2);
+        assert_eq!(nonces.reserve(&db).await.map_err(|err| err.to_string())?, 1);
+        assert_eq!(nonces.reserve(&db).await.map_err(|err| err.to_string())?, 2);
+
+        // Other chains keep their own nonces.
+        let other = NonceManager::load(&db, 84532, solver)
+            .await
+            .map_err(|err| err.to_string())?;
+        assert_eq!(other.next(), 0);
+
+        let mut nonces = NonceManager::load(&db, 21363, solver)
+            .await
+            .map_err(|err| err.to_string())?;
+        assert_eq!(nonces.next(), 3);
+        assert_eq!(nonces.reserve(&db).await.map_err(|err| err.to_string())?, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resume() -> Result<(), String> {
+        let db = MemoryPool::default();
+        let solver = Address::from_low_u64_be(1);
+        let mut nonces = NonceManager::load(&db, 21363, solver)
+            .await
+            .map_err(|err| err.to_string())?;
+        assert_eq!(nonces.reserve(&db).await.map_err(|err| err.to_string())?, 0);
+
+        // The transaction of the nonce 0 is resumed after the restart and fails.
+        let mut nonces = NonceManager::load(&db, 21363, solver)
+            .await
+            .map_err(|err| err.to_string())?;
+        nonces.release(&db, 0).await;
+        assert_eq!(nonces.reserve(&db).await.map_err(|err| err.to_string())?, 1);
+        nonces.resume(0);
+        // Nonces that were never handed out stay unknown.
+        nonces.resume(7);
+        nonces.release(&db, 0).await;
+        nonces.release(&db, 7).await;
+        assert_eq!(nonces.reserve(&db).await.map_err(|err| err.to_string())?, 0);
+        assert_eq!(nonces.reserve(&db).await.map_err(|err| err.to_string())?, 2);
+        Ok(())
+    }
+}
@@ -1,30 +1,40 @@
 use std::sync::Arc;
 
-use axum::{http::StatusCode, Json};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::types::transaction::eip712::EIP712Domain;
 use log::error;
-use mysql::PooledConn;
 use tokio::sync::Mutex;
 
-use crate::{db::store_user_data, user_data::UserData};
+use crate::{
+    replay_guard::ReplayGuard,
+    signed_request::SignedRequest,
+    store::{KeeperStore, StorePool},
+    user_data::UserData,
+};
 
-pub async fn handle_onboard(
-    input_json: Json<UserData>,
-    db_conn: Arc<Mutex<PooledConn>>,
-) -> Result<(), StatusCode> {
-    let mut db_conn = db_conn.lock().await;
-    match store_user_data(
-        db_conn.as_mut(),
-        &input_json.time_keeper,
-        &input_json.avatar,
-    )
-    .await
+pub async fn handle_onboard<S: StorePool>(
+    Json(input): Json<SignedRequest<UserData>>,
+    db: S,
+    replay_guard: Arc<Mutex<ReplayGuard>>,
+    signing_domain: Arc<EIP712Domain>,
+) -> Result<(), Response> {
+    input
+        .verify(&db, &replay_guard, &signing_domain)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let input_json = input.data;
+    match db
+        .with_store(move |store| store.store_user_data(&input_json.time_keeper, &input_json.avatar))
+        .await
     {
-        Ok(_) => {
-            return Ok(());
-        }
+        Ok(_) => Ok(()),
         Err(err) => {
             error!("Error storing whilelisted address: {}", err);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
         }
     }
 }
@@ -0,0 +1,303 @@
+use std::fmt::{self, Display};
+
+use clap::ValueEnum;
+use ethers::{
+    contract::ContractError,
+    providers::Middleware,
+    signers::Signer,
+    types::{Address, U256},
+    utils::format_ether,
+};
+
+use crate::call_breaker::CallBreakerData;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum PreflightMode {
+    // Refuses to start if any check fails.
+    Strict,
+    // Logs the failed checks and starts anyway.
+    Degraded,
+}
+
+impl Display for PreflightMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => write!(f, "{:?}", self),
+        }
+    }
+}
+
+// Balances below these fail the preflight, in wei.
+#[derive(Clone, Copy, Debug)]
+pub struct PreflightConfig {
+    pub min_native_balance: U256,
+    pub min_sender_balance: U256,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PreflightFailure {
+    NoCode {
+        contract: &'static str,
+        address: Address,
+    },
+    ValidatorMismatch {
+        registered: Address,
+        configured: Address,
+    },
+    MissingRole {
+        role: &'static str,
+        account: Address,
+    },
+    LowNativeBalance(U256),
+    LowSenderBalance(U256),
+    Provider(String),
+}
+
+impl Display for PreflightFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreflightFailure::NoCode { contract, address } => {
+                write!(f, "No {} contract code at {:#x}", contract, address)
+            }
+            PreflightFailure::ValidatorMismatch {
+                registered,
+                configured,
+            } => write!(
+                f,
+                "The CallBreaker validator of the app is {:#x}, the validator key is {:#x}",
+                registered, configured
+            ),
+            PreflightFailure::MissingRole { role, account } => {
+                write!(f, "{:#x} doesn't hold the BlockTime {}", account, role)
+            }
+            PreflightFailure::LowNativeBalance(balance) => {
+                write!(f, "Solver balance is {} ether", format_ether(*balance))
+            }
+            PreflightFailure::LowSenderBalance(balance) => write!(
+                f,
+                "CallBreaker sender balance of the solver is {} ether",
+                format_ether(*balance)
+            ),
+            PreflightFailure::Provider(err) => write!(f, "Provider error: {}", err),
+        }
+    }
+}
+
+impl<M: Middleware> From<ContractError<M>> for PreflightFailure {
+    fn from(err: ContractError<M>) -> Self {
+        PreflightFailure::Provider(err.to_string())
+    }
+}
+
+// Checks the on-chain configuration the submissions depend on, returns the failed checks.
+pub async fn preflight<M: Middleware>(
+    data: &CallBreakerData<M>,
+    config: &PreflightConfig,
+) -> Vec<PreflightFailure> {
+    let mut failures = Vec::new();
+    let call_breaker = data.call_breaker_contract.address();
+    let block_time = data.block_time_contract.address();
+    // The contract calls make no sense without code.
+    for (contract, address) in [("CallBreaker", call_breaker), ("BlockTime", block_time)] {
+        match has_code(data, address).await {
+            Ok(true) => {}
+            Ok(false) => failures.push(PreflightFailure::NoCode { contract, address }),
+            Err(failure) => failures.push(failure),
+        }
+    }
+    if !failures.is_empty() {
+        return failures;
+    }
+    let checks = [
+        check_validator(data).await,
+        check_roles(data).await,
+        check_balances(data, config).await,
+    ];
+    failures.extend(checks.into_iter().flatten());
+    failures
+}
+
+async fn has_code<M: Middleware>(
+    data: &CallBreakerData<M>,
+    address: Address,
+) -> Result<bool, PreflightFailure> {
+    let code = data
+        .call_breaker_contract
+        .client()
+        .get_code(address, None)
+        .await
+        .map_err(|err| PreflightFailure::Provider(err.to_string()))?;
+    Ok(!code.is_empty())
+}
+
+async fn check_validator<M: Middleware>(data: &CallBreakerData<M>) -> Vec<PreflightFailure> {
+    let registered = match data
+        .call_breaker_contract
+        .get_validator_address(data.app_id.clone())
+        .call()
+        .await
+    {
+        Ok(registered) => registered,
+        Err(err) => return vec![err.into()],
+    };
+    let configured = data.validator_wallet.address();
+    if registered != configured {
+        return vec![PreflightFailure::ValidatorMismatch {
+            registered,
+            configured,
+        }];
+    }
+    Vec::new()
+}
+
+// BlockTime only lets the scheduler move the time, the CallBreaker calls it when executing
+// the user objective of the solver.
+async fn check_roles<M: Middleware>(data: &CallBreakerData<M>) -> Vec<PreflightFailure> {
+    let account = data.call_breaker_contract.address();
+    let role = match data.block_time_contract.scheduler_role().call().await {
+        Ok(role) => role,
+        Err(err) => return vec![err.into()],
+    };
+    match data
+        .block_time_contract
+        .has_role(role, account)
+        .call()
+        .await
+    {
+        Ok(true) => Vec::new(),
+        Ok(false) => vec![PreflightFailure::MissingRole {
+            role: "SCHEDULER_ROLE",
+            account,
+        }],
+        Err(err) => vec![err.into()],
+    }
+}
+
+async fn check_balances<M: Middleware>(
+    data: &CallBreakerData<M>,
+    config: &PreflightConfig,
+) -> Vec<PreflightFailure> {
+    let solver = data.solver_wallet.address();
+    let mut failures = Vec::new();
+    match data
+        .call_breaker_contract
+        .client()
+        .get_balance(solver, None)
+        .await
+    {
+        Ok(balance) if balance < config.min_native_balance => {
+            failures.push(PreflightFailure::LowNativeBalance(balance))
+        }
+        Ok(_) => {}
+        Err(err) => failures.push(PreflightFailure::Provider(err.to_string())),
+    }
+    match data
+        .call_breaker_contract
+        .sender_balances(solver)
+        .call()
+        .await
+    {
+        Ok(balance) if balance < config.min_sender_balance => {
+            failures.push(PreflightFailure::LowSenderBalance(balance))
+        }
+        Ok(_) => {}
+        Err(err) => failures.push(err.into()),
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, sync::Arc};
+
+    use ethers::{
+        abi::AbiEncode,
+        providers::Provider,
+        signers::{LocalWallet, Signer},
+        types::{Address, Bytes, U256},
+        utils::keccak256,
+    };
+
+    use crate::call_breaker::CallBreakerData;
+
+    use super::{preflight, PreflightConfig, PreflightFailure};
+
+    #[tokio::test]
+    async fn test_preflight() -> Result<(), String> {
+        let solver = LocalWallet::from_str(
+            "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )
+        .map_err(|err| err.to_string())?;
+        let validator = LocalWallet::from_str(
+            "0x0123456789012345678901234567890123456789012345678901234567890123",
+        )
+        .map_err(|err| err.to_string())?;
+        let (provider, mock) = Provider::mocked();
+        let data = CallBreakerData::new(
+            Address::from_low_u64_be(1),
+            Address::from_low_u64_be(2),
+            Arc::new(provider),
+            solver.clone(),
+            validator.clone(),
+            Bytes::from_str("0x01").unwrap(),
+        );
+        let config = PreflightConfig {
+            min_native_balance: U256::exp10(16),
+            min_sender_balance: U256::zero(),
+        };
+        let push = |value: String| mock.push::<String, _>(value).map_err(|err| err.to_string());
+        let code = "0x6080".to_string();
+        let role = keccak256("SCHEDULER_ROLE");
+
+        // The responses are popped from the back.
+        push(U256::from(5).encode_hex())?;
+        push(format!("{:#x}", U256::exp10(15)))?;
+        push(false.encode_hex())?;
+        push(role.encode_hex())?;
+        push(Address::from_low_u64_be(3).encode_hex())?;
+        push(code.clone())?;
+        push(code.clone())?;
+        assert_eq!(
+            preflight(&data, &config).await,
+            [
+                PreflightFailure::ValidatorMismatch {
+                    registered: Address::from_low_u64_be(3),
+                    configured: validator.address(),
+                },
+                PreflightFailure::MissingRole {
+                    role: "SCHEDULER_ROLE",
+                    account: Address::from_low_u64_be(1),
+                },
+                PreflightFailure::LowNativeBalance(U256::exp10(15)),
+            ]
+        );
+
+        push(U256::zero().encode_hex())?;
+        push(format!("{:#x}", U256::exp10(17)))?;
+        push(true.encode_hex())?;
+        push(role.encode_hex())?;
+        push(validator.address().encode_hex())?;
+        push(code.clone())?;
+        push(code)?;
+        assert_eq!(preflight(&data, &config).await, []);
+
+        // The other checks are skipped without the contracts.
+        push("0x".to_string())?;
+        push("0x".to_string())?;
+        assert_eq!(
+            preflight(&data, &config).await,
+            [
+                PreflightFailure::NoCode {
+                    contract: "CallBreaker",
+                    address: Address::from_low_u64_be(1),
+                },
+                PreflightFailure::NoCode {
+                    contract: "BlockTime",
+                    address: Address::from_low_u64_be(2),
+                },
+            ]
+        );
+        Ok(())
+    }
+}
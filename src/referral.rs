@@ -1,11 +1,10 @@
-use std::{collections::HashMap, sync::Arc};
+use std::collections::HashMap;
 
 use axum::{extract::Query, http::StatusCode, Json};
 use log::error;
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
 
-use crate::db::{read_referral, write_referral};
+use crate::store::{ReferralStore, StorePool};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ReferralData {
@@ -13,16 +12,16 @@ pub struct ReferralData {
     pub refvalue: String, // Referral code
 }
 
-pub async fn handle_read_referral(
+pub async fn handle_read_referral<S: StorePool>(
     params: Query<HashMap<String, String>>,
-    db_conn: Arc<Mutex<mysql::PooledConn>>,
+    db: S,
 ) -> Result<Json<ReferralData>, StatusCode> {
-    if let Some(ref_key) = params.get("ref_key") {
-        let mut db_conn = db_conn.lock().await;
-        match read_referral(db_conn.as_mut(), ref_key) {
+    if let Some(ref_key) = params.get("ref_key").cloned() {
+        let key = ref_key.clone();
+        match db.with_store(move |store| store.read_referral(&key)).await {
             Ok(referral_code) => {
                 return Ok(Json(ReferralData {
-                    refkey: ref_key.to_string(),
+                    refkey: ref_key,
                     refvalue: referral_code,
                 }));
             }
@@ -35,18 +34,18 @@ pub async fn handle_read_referral(
     Err(StatusCode::BAD_REQUEST)
 }
 
-pub async fn handle_write_referral(
+pub async fn handle_write_referral<S: StorePool>(
     input_json: Json<ReferralData>,
-    db_conn: Arc<Mutex<mysql::PooledConn>>,
+    db: S,
 ) -> Result<(), StatusCode> {
-    let mut db_conn = db_conn.lock().await;
-    match write_referral(db_conn.as_mut(), &input_json.0) {
-        Ok(_) => {
-            return Ok(());
-        }
+    match db
+        .with_store(move |store| store.write_referral(&input_json.0))
+        .await
+    {
+        Ok(_) => Ok(()),
         Err(err) => {
             error!("Error storing the referral: {}", err);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
@@ -1,59 +1,356 @@
-use std::sync::Arc;
+use std::{
+    collections::HashSet,
+    error::Error,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
-use axum::{http::StatusCode, Json};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::types::transaction::eip712::EIP712Domain;
 use log::{error, warn};
-use mysql::PooledConn;
 use tokio::sync::Mutex;
 
 use crate::{
-    db::{is_referral_code_available, update_referral_code, update_referred_from},
+    referral_tree::Keeper,
+    rejection::Rejection,
+    replay_guard::ReplayGuard,
+    signed_request::SignedRequest,
+    store::{KeeperStore, StorePool},
     user_data::{ReferralCodeData, ReferredFromData},
 };
 
-pub async fn handle_update_referral_code(
-    input: Json<ReferralCodeData>,
-    db_conn: Arc<Mutex<PooledConn>>,
-) -> Result<(), StatusCode> {
-    let mut conn = db_conn.lock().await;
-    match is_referral_code_available(conn.as_mut(), &input.time_keeper, &input.referral_code).await
-    {
-        Ok(is_avail) => {
-            if !is_avail {
-                warn!(
-                    "The referral code {} is already in use",
-                    input.referral_code
-                );
-                return Err(StatusCode::CONFLICT);
-            }
+// Referral writes are serialized by `referral_writes`, the cycle check of a referrer change reads
+// the codes and the referrers the other requests write.
+pub async fn handle_update_referral_code<S: StorePool>(
+    Json(input): Json<SignedRequest<ReferralCodeData>>,
+    db: S,
+    replay_guard: Arc<Mutex<ReplayGuard>>,
+    signing_domain: Arc<EIP712Domain>,
+    referral_writes: Arc<Mutex<()>>,
+) -> Result<(), Response> {
+    input
+        .verify(&db, &replay_guard, &signing_domain)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let input = input.data;
+    let referral_code = input.referral_code.clone();
+    let _guard = referral_writes.lock().await;
+    let is_updated = db
+        .with_store(move |store| {
+            store.update_referral_code(&input.time_keeper, &input.referral_code)
+        })
+        .await;
+    match is_updated {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            warn!("The referral code {} is already in use", referral_code);
+            Err(StatusCode::CONFLICT.into_response())
         }
         Err(err) => {
-            error!("Error checking the referral code: {}", err);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            error!("Error updating referral code: {}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
         }
     }
-    match update_referral_code(conn.as_mut(), &input.time_keeper, &input.referral_code).await {
-        Ok(_) => {
-            return Ok(());
+}
+
+// Reasons for rejecting a referrer change, each one has its own status and error code.
+#[derive(Debug, PartialEq)]
+pub enum ReferredFromRejection {
+    NotWhitelisted,
+    UnknownCode,
+    SelfReferral,
+    Cycle,
+    Locked,
+    Internal,
+}
+
+impl Rejection for ReferredFromRejection {
+    fn status(&self) -> StatusCode {
+        match self {
+            ReferredFromRejection::NotWhitelisted => StatusCode::FORBIDDEN,
+            ReferredFromRejection::UnknownCode => StatusCode::NOT_FOUND,
+            ReferredFromRejection::SelfReferral => StatusCode::UNPROCESSABLE_ENTITY,
+            ReferredFromRejection::Cycle => StatusCode::CONFLICT,
+            ReferredFromRejection::Locked => StatusCode::LOCKED,
+            ReferredFromRejection::Internal => StatusCode::INTERNAL_SERVER_ERROR,
         }
-        Err(err) => {
-            error!("Error updating referral code: {}", err);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ReferredFromRejection::NotWhitelisted => "not_whitelisted",
+            ReferredFromRejection::UnknownCode => "unknown_referral_code",
+            ReferredFromRejection::SelfReferral => "self_referral",
+            ReferredFromRejection::Cycle => "referral_cycle",
+            ReferredFromRejection::Locked => "referred_from_locked",
+            ReferredFromRejection::Internal => "internal_error",
         }
     }
-}
 
-pub async fn handle_update_referred_from(
-    input: Json<ReferredFromData>,
-    db_conn: Arc<Mutex<PooledConn>>,
-) -> Result<(), StatusCode> {
-    let mut conn = db_conn.lock().await;
-    match update_referred_from(conn.as_mut(), &input.time_keeper, &input.referred_from).await {
-        Ok(_) => {
-            return Ok(());
+    fn message(&self) -> String {
+        match self {
+            ReferredFromRejection::NotWhitelisted => {
+                "The time keeper isn't whitelisted".to_string()
+            }
+            ReferredFromRejection::UnknownCode => "The referral code doesn't exist".to_string(),
+            ReferredFromRejection::SelfReferral => {
+                "The referral code is the time keeper's own".to_string()
+            }
+            ReferredFromRejection::Cycle => {
+                "The referrer is referred by the time keeper".to_string()
+            }
+            ReferredFromRejection::Locked => {
+                "The referrer can't be changed after the grace period".to_string()
+            }
+            ReferredFromRejection::Internal => "Internal server error".to_string(),
         }
+    }
+}
+
+impl IntoResponse for ReferredFromRejection {
+    fn into_response(self) -> Response {
+        self.response()
+    }
+}
+
+pub async fn handle_update_referred_from<S: StorePool>(
+    Json(input): Json<SignedRequest<ReferredFromData>>,
+    db: S,
+    replay_guard: Arc<Mutex<ReplayGuard>>,
+    signing_domain: Arc<EIP712Domain>,
+    grace_period: Duration,
+    referral_writes: Arc<Mutex<()>>,
+) -> Result<(), Response> {
+    input
+        .verify(&db, &replay_guard, &signing_domain)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let input = input.data;
+    // Two keepers referring each other at once would both pass the cycle check.
+    let _guard = referral_writes.lock().await;
+    match db
+        .with_store(move |store| Ok(update_referrer(store, &input, grace_period)))
+        .await
+    {
+        Ok(res) => res.map_err(IntoResponse::into_response),
         Err(err) => {
             error!("Error updating referred from: {}", err);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            Err(ReferredFromRejection::Internal.into_response())
+        }
+    }
+}
+
+fn update_referrer<S: KeeperStore + ?Sized>(
+    store: &mut S,
+    input: &ReferredFromData,
+    grace_period: Duration,
+) -> Result<(), ReferredFromRejection> {
+    let internal = |err: Box<dyn Error>| {
+        error!("Error updating referred from: {}", err);
+        ReferredFromRejection::Internal
+    };
+    let keeper = store
+        .read_keeper(&input.time_keeper)
+        .map_err(internal)?
+        .ok_or(ReferredFromRejection::NotWhitelisted)?;
+    let referred_at = store
+        .read_referred_at(&input.time_keeper)
+        .map_err(internal)?;
+    let curr_ts = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO);
+    let is_locked = match referred_at {
+        Some(referred_at) => Duration::from_secs(referred_at) + grace_period < curr_ts,
+        None => false,
+    };
+    if !check_referred_from(&keeper, &input.referred_from, is_locked)? {
+        return Ok(());
+    }
+    let referrer = store
+        .read_keeper_by_referral_code(&input.referred_from)
+        .map_err(internal)?
+        .ok_or(ReferredFromRejection::UnknownCode)?;
+    // The time keeper must not be in the upline of the referrer, the walk also ends at existing cycles.
+    let mut visited = HashSet::from([referrer.address.clone()]);
+    let mut curr = referrer;
+    while let Some(code) = curr.referred_from() {
+        if keeper.referral_code() == Some(code) {
+            warn!(
+                "Referral code {} would create a cycle for {:#x}",
+                input.referred_from, input.time_keeper
+            );
+            return Err(ReferredFromRejection::Cycle);
+        }
+        match store.read_keeper_by_referral_code(code).map_err(internal)? {
+            Some(next) if visited.insert(next.address.clone()) => curr = next,
+            _ => break,
+        }
+    }
+    store
+        .update_referred_from(&input.time_keeper, &input.referred_from)
+        .map_err(internal)
+}
+
+// Checks the change against the time keeper's own codes, returns false if there's nothing to change.
+fn check_referred_from(
+    keeper: &Keeper,
+    referred_from: &str,
+    is_locked: bool,
+) -> Result<bool, ReferredFromRejection> {
+    if keeper.referred_from() == Some(referred_from) {
+        return Ok(false);
+    }
+    if is_locked && keeper.referred_from().is_some() {
+        return Err(ReferredFromRejection::Locked);
+    }
+    if referred_from.is_empty() {
+        return Err(ReferredFromRejection::UnknownCode);
+    }
+    if keeper.referral_code() == Some(referred_from) {
+        return Err(ReferredFromRejection::SelfReferral);
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        str::FromStr,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use axum::{response::IntoResponse, Json};
+    use ethers::{
+        signers::{LocalWallet, Signer},
+        types::{Address, Bytes, U256},
+    };
+    use tokio::sync::Mutex;
+
+    use crate::{
+        memory_store::MemoryPool,
+        referral_tree::Keeper,
+        rejection::Rejection,
+        replay_guard::ReplayGuard,
+        signed_request::{SignedPayload, SignedRequest},
+        time_signature::{chronicle_domain, SignatureScheme},
+        user_data::ReferredFromData,
+    };
+
+    use super::{check_referred_from, handle_update_referred_from, ReferredFromRejection};
+
+    #[tokio::test]
+    async fn test_check_referred_from() -> Result<(), String> {
+        let mut keeper = Keeper {
+            address: "0x25ee756f5d93e26f5011b7ed4866afb192ce483e".to_string(),
+            avatar: None,
+            referral_code: Some("own".to_string()),
+            referred_from: None,
+        };
+        assert_eq!(check_referred_from(&keeper, "other", false), Ok(true));
+        // Nothing to lock before the first referrer is set.
+        assert_eq!(check_referred_from(&keeper, "other", true), Ok(true));
+        assert_eq!(
+            check_referred_from(&keeper, "own", false),
+            Err(ReferredFromRejection::SelfReferral)
+        );
+        assert_eq!(
+            check_referred_from(&keeper, "", false),
+            Err(ReferredFromRejection::UnknownCode)
+        );
+
+        keeper.referred_from = Some("other".to_string());
+        assert_eq!(check_referred_from(&keeper, "other", true), Ok(false));
+        assert_eq!(check_referred_from(&keeper, "third", false), Ok(true));
+        assert_eq!(
+            check_referred_from(&keeper, "third", true),
+            Err(ReferredFromRejection::Locked)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_update_referred_from() -> Result<(), String> {
+        let wallet = LocalWallet::from_str(
+            "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )
+        .map_err(|err| err.to_string())?;
+        let domain = Arc::new(chronicle_domain(
+            21363,
+            Address::from_str("0xdD1B4D9337D0a8Ef2F133a39cC93EF85261b4A80").unwrap(),
+            &Bytes::from_str("0x01").unwrap(),
+        ));
+        let referrer = Address::from_low_u64_be(1);
+        let referee = Address::from_low_u64_be(2);
+        let db = MemoryPool::default();
+        {
+            let mut store = db.0.lock().unwrap();
+            store.insert_keeper(&wallet.address(), Some("own"), None);
+            store.insert_keeper(&referrer, Some("referrer"), None);
+            store.insert_keeper(&referee, Some("referee"), Some("own"));
+            store.insert_keeper(&Address::from_low_u64_be(3), Some("other"), None);
+        }
+        let replay_guard = Arc::new(Mutex::new(ReplayGuard::new(Duration::from_secs(60), 16)));
+
+        let mut statuses = Vec::new();
+        for (idx, referred_from) in ["own", "referee", "unknown", "referrer", "other"]
+            .into_iter()
+            .enumerate()
+        {
+            if referred_from == "other" {
+                // The grace period is over.
+                db.0.lock().unwrap().set_referred_at(&wallet.address(), 0);
+            }
+            let data = ReferredFromData {
+                time_keeper: wallet.address(),
+                referred_from: referred_from.to_string(),
+            };
+            // Every request needs its own signature.
+            let curr_ts = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO);
+            let timestamp = U256::from(curr_ts.as_nanos()) + idx;
+            let signature = wallet
+                .sign_message(data.message(&timestamp))
+                .await
+                .map_err(|err| err.to_string())?;
+            let request = SignedRequest {
+                data,
+                timestamp: timestamp.to_string(),
+                signature: signature.to_string(),
+                scheme: SignatureScheme::Eip191,
+            };
+            let res = handle_update_referred_from(
+                Json(request),
+                db.clone(),
+                Arc::clone(&replay_guard),
+                Arc::clone(&domain),
+                Duration::from_secs(60),
+                Arc::new(Mutex::new(())),
+            )
+            .await;
+            statuses.push(match res {
+                Ok(()) => None,
+                Err(err) => Some(err.into_response().status()),
+            });
         }
+        assert_eq!(
+            statuses,
+            [
+                Some(ReferredFromRejection::SelfReferral.status()),
+                Some(ReferredFromRejection::Cycle.status()),
+                Some(ReferredFromRejection::UnknownCode.status()),
+                None,
+                Some(ReferredFromRejection::Locked.status()),
+            ]
+        );
+        let store = db.0.lock().unwrap();
+        let keeper = store.keeper(&wallet.address()).unwrap();
+        assert_eq!(keeper.referred_from(), Some("referrer"));
+        Ok(())
     }
 }
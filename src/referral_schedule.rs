@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use axum::Json;
+use ethers::{
+    types::U256,
+    utils::{parse_units, ParseUnits},
+};
+use serde_json::{json, Value};
+
+// One token in wei.
+const TIME_KEEPER_REWARD: u128 = 1_000_000_000_000_000_000;
+const BASIS_POINTS: u64 = 10_000;
+
+// How the referrers of a time keeper are rewarded, amounts in wei.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReferralSchedule {
+    pub time_keeper_reward: U256,
+    // Share of the reward one level below paid to each level, the last one repeats for deeper levels.
+    pub level_basis_points: Vec<u64>,
+    // Deepest paid level, unlimited if not set.
+    pub max_depth: Option<u32>,
+    // Max referral reward of one referrer in one tick.
+    pub cap_per_tick: Option<U256>,
+    // Smaller referral rewards aren't paid.
+    pub min_payout: U256,
+}
+
+impl Default for ReferralSchedule {
+    // 10% for the first level and a half of the level below after that.
+    fn default() -> Self {
+        ReferralSchedule {
+            time_keeper_reward: U256::from(TIME_KEEPER_REWARD),
+            level_basis_points: vec![1_000, 5_000],
+            max_depth: None,
+            cap_per_tick: None,
+            min_payout: U256::zero(),
+        }
+    }
+}
+
+impl ReferralSchedule {
+    // Percentages are comma separated with up to two decimals, amounts are in ether.
+    pub fn new(
+        percentages: &str,
+        max_depth: Option<u32>,
+        cap_per_tick: Option<&str>,
+        min_payout: &str,
+    ) -> Result<ReferralSchedule, String> {
+        let mut level_basis_points = Vec::new();
+        for percentage in percentages.split(',') {
+            let basis_points = parse_units(percentage.trim(), 2).map_err(|err| {
+                format!("Invalid referral percentage \"{}\": {}", percentage, err)
+            })?;
+            match basis_points {
+                ParseUnits::U256(basis_points) if basis_points <= U256::from(BASIS_POINTS) => {
+                    level_basis_points.push(basis_points.as_u64())
+                }
+                _ => {
+                    return Err(format!(
+                        "Referral percentage \"{}\" must be between 0 and 100",
+                        percentage
+                    ))
+                }
+            }
+        }
+        Ok(ReferralSchedule {
+            time_keeper_reward: U256::from(TIME_KEEPER_REWARD),
+            level_basis_points,
+            max_depth,
+            cap_per_tick: cap_per_tick.map(parse_ether).transpose()?,
+            min_payout: parse_ether(min_payout)?,
+        })
+    }
+
+    pub fn allows_level(&self, level: u32) -> bool {
+        match self.max_depth {
+            Some(max_depth) => level <= max_depth,
+            None => true,
+        }
+    }
+
+    // The reward of a referrer at the level, rounded down to whole wei.
+    pub fn referral_amount(&self, src_amount: &U256, level: u32) -> U256 {
+        if level == 0 {
+            return self.time_keeper_reward;
+        }
+        if !self.allows_level(level) {
+            return U256::zero();
+        }
+        let idx = (level as usize - 1).min(self.level_basis_points.len().saturating_sub(1));
+        let basis_points = self
+            .level_basis_points
+            .get(idx)
+            .copied()
+            .unwrap_or_default();
+        src_amount * basis_points / BASIS_POINTS
+    }
+
+    pub fn cap(&self, amount: U256) -> U256 {
+        match self.cap_per_tick {
+            Some(cap) => amount.min(cap),
+            None => amount,
+        }
+    }
+
+    pub fn is_paid(&self, amount: &U256) -> bool {
+        !amount.is_zero() && *amount >= self.min_payout
+    }
+}
+
+fn parse_ether(amount: &str) -> Result<U256, String> {
+    match parse_units(amount.trim(), "ether") {
+        Ok(ParseUnits::U256(amount)) => Ok(amount),
+        Ok(ParseUnits::I256(_)) => Err(format!("Negative amount \"{}\"", amount)),
+        Err(err) => Err(format!("Invalid amount \"{}\": {}", amount, err)),
+    }
+}
+
+pub async fn handle_get_referral_schedule(schedule: Arc<ReferralSchedule>) -> Json<Value> {
+    Json(json!({
+        "time_keeper_reward": schedule.time_keeper_reward.to_string(),
+        "level_basis_points": schedule.level_basis_points,
+        "max_depth": schedule.max_depth,
+        "cap_per_tick": schedule.cap_per_tick.map(|el| el.to_string()),
+        "min_payout": schedule.min_payout.to_string(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{types::U256, utils::parse_units};
+    use proptest::prelude::*;
+
+    use super::ReferralSchedule;
+
+    fn ether(amount: &str) -> U256 {
+        parse_units(amount, "ether").unwrap().into()
+    }
+
+    #[tokio::test]
+    async fn test_ref_amount() {
+        let schedule = ReferralSchedule::default();
+        assert_eq!(schedule.referral_amount(&U256::zero(), 0), ether("1"));
+        assert_eq!(schedule.referral_amount(&ether("1"), 1), ether("0.1"));
+        assert_eq!(schedule.referral_amount(&ether("0.1"), 2), ether("0.05"));
+        assert_eq!(
+            schedule.referral_amount(&ether("0.025"), 4),
+            ether("0.0125")
+        );
+        // Rounded down to whole wei.
+        assert_eq!(schedule.referral_amount(&U256::from(19), 1), U256::from(1));
+        assert_eq!(schedule.referral_amount(&U256::from(3), 2), U256::from(1));
+    }
+
+    #[tokio::test]
+    async fn test_schedule() -> Result<(), String> {
+        assert_eq!(
+            ReferralSchedule::new("10,50", None, None, "0")?,
+            ReferralSchedule::default()
+        );
+        let schedule = ReferralSchedule::new("12.5, 20, 5", Some(3), Some("0.1"), "0.01")?;
+        assert_eq!(schedule.level_basis_points, vec![1_250, 2_000, 500]);
+        assert_eq!(schedule.referral_amount(&ether("1"), 1), ether("0.125"));
+        assert_eq!(schedule.referral_amount(&ether("0.125"), 2), ether("0.025"));
+        assert_eq!(schedule.referral_amount(&ether("1"), 3), ether("0.05"));
+        assert_eq!(schedule.referral_amount(&ether("1"), 4), U256::zero());
+        assert!(schedule.allows_level(3));
+        assert!(!schedule.allows_level(4));
+        assert_eq!(schedule.cap(ether("0.5")), ether("0.1"));
+        assert_eq!(schedule.cap(ether("0.05")), ether("0.05"));
+        assert!(schedule.is_paid(&ether("0.01")));
+        assert!(!schedule.is_paid(&ether("0.009")));
+
+        assert!(ReferralSchedule::new("10,101", None, None, "0").is_err());
+        assert!(ReferralSchedule::new("10,-1", None, None, "0").is_err());
+        assert!(ReferralSchedule::new("ten", None, None, "0").is_err());
+        assert!(ReferralSchedule::new("10", None, None, "-1").is_err());
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn test_ref_amount_chain(levels in 1u32..32, percentages in prop::collection::vec(0u64..=100, 1..4)) {
+            // Every referral level gets at most the amount of the level below.
+            let percentages = percentages
+                .iter()
+                .map(|el| el.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            let schedule = ReferralSchedule::new(&percentages, None, None, "0").unwrap();
+            let mut amount = schedule.time_keeper_reward;
+            for level in 1..=levels {
+                let next = schedule.referral_amount(&amount, level);
+                prop_assert!(next <= amount);
+                amount = next;
+            }
+        }
+    }
+}
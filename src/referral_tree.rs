@@ -0,0 +1,352 @@
+use std::{collections::HashSet, error::Error, str::FromStr, time::Duration};
+
+use axum::{extract::Query, http::StatusCode, Json};
+use ethers::types::Address;
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::store::{KeeperStore, ReferralStore, StorePool};
+
+const DEFAULT_UPLINE_DEPTH: u32 = 10;
+const MAX_UPLINE_DEPTH: u32 = 50;
+const DEFAULT_DOWNLINE_DEPTH: u32 = 3;
+const MAX_DOWNLINE_DEPTH: u32 = 10;
+const DEFAULT_PAGE_LIMIT: u64 = 50;
+const MAX_PAGE_LIMIT: u64 = 500;
+// Time keepers rewarded within this period count as active.
+const ACTIVE_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+// A time keeper with its referral links.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keeper {
+    pub address: String,
+    pub avatar: Option<String>,
+    pub referral_code: Option<String>,
+    pub referred_from: Option<String>,
+}
+
+impl Keeper {
+    pub fn referral_code(&self) -> Option<&str> {
+        self.referral_code.as_deref().filter(|el| !el.is_empty())
+    }
+
+    pub fn referred_from(&self) -> Option<&str> {
+        self.referred_from.as_deref().filter(|el| !el.is_empty())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ReferralNode {
+    pub address: String,
+    pub avatar: Option<String>,
+    // Distance from the requested time keeper.
+    pub level: u32,
+    // The node one level closer to the requested time keeper.
+    pub parent: String,
+    // Direct referees of the node, and how many of them were rewarded recently.
+    pub referees: u64,
+    pub active_referees: u64,
+    // Confirmed referral rewards of the node, in wei.
+    pub referral_earnings: String,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ReferralTree {
+    pub address: String,
+    pub total: u64,
+    pub nodes: Vec<ReferralNode>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UplineParams {
+    address: String,
+    depth: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DownlineParams {
+    address: String,
+    depth: Option<u32>,
+    #[serde(default)]
+    offset: u64,
+    limit: Option<u64>,
+}
+
+// Referrers of the time keeper, the direct referrer first.
+pub async fn handle_get_referral_upline<S: StorePool>(
+    Query(params): Query<UplineParams>,
+    db: S,
+) -> Result<Json<ReferralTree>, StatusCode> {
+    let address = parse_address(&params.address)?;
+    let depth = params
+        .depth
+        .unwrap_or(DEFAULT_UPLINE_DEPTH)
+        .min(MAX_UPLINE_DEPTH);
+    let tree = db
+        .with_store(move |store| {
+            let Some(keeper) = store.read_keeper(&address)? else {
+                return Ok(None);
+            };
+            let links = read_upline(store, &keeper, depth)?;
+            build_tree(store, &keeper, links.len() as u64, links).map(Some)
+        })
+        .await
+        .map_err(|err| {
+            error!("Error reading the referral upline: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    tree.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+// Referees of the time keeper level by level, paginated.
+pub async fn handle_get_referral_downline<S: StorePool>(
+    Query(params): Query<DownlineParams>,
+    db: S,
+) -> Result<Json<ReferralTree>, StatusCode> {
+    let address = parse_address(&params.address)?;
+    let depth = params
+        .depth
+        .unwrap_or(DEFAULT_DOWNLINE_DEPTH)
+        .min(MAX_DOWNLINE_DEPTH);
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_PAGE_LIMIT)
+        .min(MAX_PAGE_LIMIT);
+    let offset = params.offset;
+    let tree = db
+        .with_store(move |store| {
+            let Some(keeper) = store.read_keeper(&address)? else {
+                return Ok(None);
+            };
+            let (total, page) = store.read_downline(&keeper, depth, offset, limit)?;
+            build_tree(store, &keeper, total, page).map(Some)
+        })
+        .await
+        .map_err(|err| {
+            error!("Error reading the referral downline: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    tree.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+fn parse_address(address: &str) -> Result<Address, StatusCode> {
+    Address::from_str(address).map_err(|err| {
+        error!("Error extracting address: {}", err);
+        StatusCode::BAD_REQUEST
+    })
+}
+
+// A keeper found in the graph with its level and parent address.
+pub type Link = (Keeper, u32, String);
+
+fn read_upline<S: KeeperStore + ?Sized>(
+    store: &mut S,
+    keeper: &Keeper,
+    depth: u32,
+) -> Result<Vec<Link>, Box<dyn Error>> {
+    let mut visited = HashSet::from([keeper.address.clone()]);
+    let mut links = Vec::new();
+    let mut curr = keeper.clone();
+    for level in 1..=depth {
+        let Some(code) = curr.referred_from() else {
+            break;
+        };
+        let Some(referrer) = store.read_keeper_by_referral_code(code)? else {
+            break;
+        };
+        // Stop at a cycle.
+        if !visited.insert(referrer.address.clone()) {
+            break;
+        }
+        links.push((referrer.clone(), level, curr.address.clone()));
+        curr = referrer;
+    }
+    Ok(links)
+}
+
+fn build_tree<S: ReferralStore + ?Sized>(
+    store: &mut S,
+    keeper: &Keeper,
+    total: u64,
+    links: Vec<Link>,
+) -> Result<ReferralTree, Box<dyn Error>> {
+    let addresses: Vec<&str> = links.iter().map(|el| el.0.address.as_str()).collect();
+    let stats = store.read_referral_stats(&addresses, ACTIVE_PERIOD)?;
+    let earnings = store.read_referral_earnings(&addresses)?;
+    let nodes = links
+        .into_iter()
+        .map(|(node, level, parent)| {
+            let (referees, active_referees) = stats.get(&node.address).copied().unwrap_or_default();
+            ReferralNode {
+                referral_earnings: earnings
+                    .get(&node.address)
+                    .cloned()
+                    .unwrap_or("0".to_string()),
+                address: node.address,
+                avatar: node.avatar,
+                level,
+                parent,
+                referees,
+                active_referees,
+            }
+        })
+        .collect();
+    Ok(ReferralTree {
+        address: keeper.address.clone(),
+        total,
+        nodes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Query, http::StatusCode};
+    use ethers::types::Address;
+
+    use crate::memory_store::MemoryPool;
+
+    use super::{
+        handle_get_referral_downline, handle_get_referral_upline, read_upline, DownlineParams,
+        Keeper, ReferralTree, UplineParams,
+    };
+
+    fn address(idx: u64) -> String {
+        format!("{:#x}", Address::from_low_u64_be(idx))
+    }
+
+    #[tokio::test]
+    async fn test_keeper_links() -> Result<(), String> {
+        let keeper = Keeper {
+            address: format!("0x{:040x}", 1),
+            avatar: None,
+            referral_code: Some("code1".to_string()),
+            referred_from: Some(String::new()),
+        };
+        assert_eq!(keeper.referral_code(), Some("code1"));
+        assert_eq!(keeper.referred_from(), None);
+        let keeper = Keeper {
+            referral_code: Some(String::new()),
+            referred_from: Some("code0".to_string()),
+            ..keeper
+        };
+        assert_eq!(keeper.referral_code(), None);
+        assert_eq!(keeper.referred_from(), Some("code0"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_upline() -> Result<(), String> {
+        let db = MemoryPool::default();
+        let mut store = db.0.lock().unwrap();
+        // 1 is referred by 2, 2 by 3 and 3 by 1 again.
+        store.insert_keeper(&Address::from_low_u64_be(1), Some("code1"), Some("code2"));
+        store.insert_keeper(&Address::from_low_u64_be(2), Some("code2"), Some("code3"));
+        store.insert_keeper(&Address::from_low_u64_be(3), Some("code3"), Some("code1"));
+        // 4 is referred by a code nobody holds.
+        store.insert_keeper(&Address::from_low_u64_be(4), Some("code4"), Some("gone"));
+
+        let keeper = store.keeper(&Address::from_low_u64_be(1)).cloned().unwrap();
+        let links = read_upline(&mut *store, &keeper, 10).map_err(|err| err.to_string())?;
+        let links: Vec<(String, u32, String)> = links
+            .into_iter()
+            .map(|(node, level, parent)| (node.address, level, parent))
+            .collect();
+        assert_eq!(
+            links,
+            vec![(address(2), 1, address(1)), (address(3), 2, address(2))]
+        );
+        let links = read_upline(&mut *store, &keeper, 1).map_err(|err| err.to_string())?;
+        assert_eq!(links.len(), 1);
+
+        let keeper = store.keeper(&Address::from_low_u64_be(4)).cloned().unwrap();
+        let links = read_upline(&mut *store, &keeper, 10).map_err(|err| err.to_string())?;
+        assert!(links.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_get_referral_upline() -> Result<(), String> {
+        let db = MemoryPool::default();
+        {
+            let mut store = db.0.lock().unwrap();
+            store.insert_keeper(&Address::from_low_u64_be(1), Some("code1"), Some("code2"));
+            store.insert_keeper(&Address::from_low_u64_be(2), Some("code2"), None);
+        }
+        let upline = |address: String| {
+            handle_get_referral_upline(
+                Query(UplineParams {
+                    address,
+                    depth: None,
+                }),
+                db.clone(),
+            )
+        };
+        let tree = upline(address(1)).await.map_err(|err| err.to_string())?.0;
+        assert_eq!(tree.total, 1);
+        assert_eq!(tree.nodes[0].address, address(2));
+        assert_eq!(tree.nodes[0].referees, 1);
+        assert_eq!(tree.nodes[0].referral_earnings, "0");
+        assert_eq!(upline(address(3)).await.err(), Some(StatusCode::NOT_FOUND));
+        assert_eq!(
+            upline("0x01".to_string()).await.err(),
+            Some(StatusCode::BAD_REQUEST)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_get_referral_downline() -> Result<(), String> {
+        let db = MemoryPool::default();
+        {
+            let mut store = db.0.lock().unwrap();
+            // 2 and 3 are referred by 1, 4 by 2 and 5 by 4. 6 refers 1 back, the cycle ends at 1.
+            store.insert_keeper(&Address::from_low_u64_be(1), Some("code1"), Some("code6"));
+            store.insert_keeper(&Address::from_low_u64_be(2), Some("code2"), Some("code1"));
+            store.insert_keeper(&Address::from_low_u64_be(3), None, Some("code1"));
+            store.insert_keeper(&Address::from_low_u64_be(4), Some("code4"), Some("code2"));
+            store.insert_keeper(&Address::from_low_u64_be(5), Some("code5"), Some("code4"));
+            store.insert_keeper(&Address::from_low_u64_be(6), Some("code6"), Some("code5"));
+        }
+        let downline = |depth: u32, offset: u64, limit: u64| {
+            handle_get_referral_downline(
+                Query(DownlineParams {
+                    address: address(1),
+                    depth: Some(depth),
+                    offset,
+                    limit: Some(limit),
+                }),
+                db.clone(),
+            )
+        };
+        let nodes = |tree: ReferralTree| {
+            tree.nodes
+                .into_iter()
+                .map(|el| (el.address, el.level, el.parent))
+                .collect::<Vec<_>>()
+        };
+
+        let tree = downline(2, 0, 10).await.map_err(|err| err.to_string())?.0;
+        assert_eq!(tree.total, 3);
+        assert_eq!(
+            nodes(tree),
+            vec![
+                (address(2), 1, address(1)),
+                (address(3), 1, address(1)),
+                (address(4), 2, address(2)),
+            ]
+        );
+        // The deepest levels stop before the time keeper.
+        let tree = downline(10, 0, 10).await.map_err(|err| err.to_string())?.0;
+        assert_eq!(tree.total, 5);
+
+        let tree = downline(2, 1, 1).await.map_err(|err| err.to_string())?.0;
+        assert_eq!(tree.total, 3);
+        assert_eq!(nodes(tree), vec![(address(3), 1, address(1))]);
+        let tree = downline(2, 2, 5).await.map_err(|err| err.to_string())?.0;
+        assert_eq!(nodes(tree), vec![(address(4), 2, address(2))]);
+        let tree = downline(2, 3, 5).await.map_err(|err| err.to_string())?.0;
+        assert_eq!(tree.total, 3);
+        assert!(tree.nodes.is_empty());
+        Ok(())
+    }
+}
@@ -1,105 +1,200 @@
 use std::{
-    collections::{BTreeMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     error::Error,
 };
 
-use mysql::{prelude::Queryable, Conn};
+use ethers::types::U256;
 
-use crate::db::check_conn;
+use crate::{referral_schedule::ReferralSchedule, store::ReferralStore};
 
-pub async fn read_referrers_list(
-    conn: &mut Conn,
-    total_accounts: &mut BTreeMap<String, f64>,
-) -> Result<(), Box<dyn Error>> {
-    check_conn(conn);
-
-    // Detect cyclic references, put referral codes of source accounts into visited;
-    let mut visited_refs = HashSet::new();
+// Reward of an account in wei, the level is 0 for time keepers and the referral depth for referrers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reward {
+    pub amount: U256,
+    pub level: u32,
+}
 
-    let stmt = format!(
-        "SELECT referral_code
-            FROM whitelisted_addresses
-            WHERE address IN({})
-            AND NULLIF(referral_code, '') IS NOT NULL",
-        vec!["?"; total_accounts.len()].join(",")
-    );
-    let first_result = conn.exec_iter(stmt, total_accounts.keys().collect::<Vec<_>>())?;
-    for row_res in first_result {
-        let row_res = row_res?;
-        let referral_res: Option<String> = row_res.get(0);
-        if let Some(referral_code) = referral_res {
-            visited_refs.insert(referral_code);
-        }
-    }
+// Adds the referrers of the time keepers with their rewards by the schedule.
+// Each time keeper's reward goes up its own referrer chain, a referrer gets the sum over its
+// referees capped per tick. A time keeper who is also a referrer gets both in its keeper reward.
+pub fn read_referrers_list<S: ReferralStore + ?Sized>(
+    store: &mut S,
+    total_accounts: &mut BTreeMap<String, Reward>,
+    schedule: &ReferralSchedule,
+) -> Result<(), Box<dyn Error>> {
+    // The account reached by the path with its reward and the accounts already on the path,
+    // which stop the cycles written before the referrers were validated.
+    let mut paths: Vec<(String, U256, HashSet<String>)> = total_accounts
+        .iter()
+        .map(|(account, reward)| {
+            (
+                account.clone(),
+                reward.amount,
+                HashSet::from([account.clone()]),
+            )
+        })
+        .collect();
+    let mut referrals: BTreeMap<String, Reward> = BTreeMap::new();
     let mut level = 1;
-    let mut ref_accounts: BTreeMap<String, f64> = total_accounts.clone();
-    loop {
-        let mut next_ref_accounts: BTreeMap<String, f64> = BTreeMap::new();
-        let stmt = format!(
-            "SELECT a1.address, a2.address, a2.referral_code
-                FROM whitelisted_addresses AS a1
-                JOIN whitelisted_addresses AS a2
-                ON a2.referral_code = a1.referred_from
-                WHERE a1.address IN({})
-                AND NULLIF(a1.referred_from, '') IS NOT NULL",
-            vec!["?"; ref_accounts.len()].join(",")
-        );
-        let result = conn.exec_iter(stmt, ref_accounts.keys().collect::<Vec<_>>())?;
-        for row_res in result {
-            let row_res = row_res?;
-            let src_account_res: Option<String> = row_res.get(0);
-            let ref_account_res: Option<String> = row_res.get(1);
-            // Finding visited referral codes.
-            let referral_code_res: Option<String> = row_res.get(2);
-            if let Some(referral_code) = referral_code_res {
-                if visited_refs.insert(referral_code) {
-                    // Inserting the found account if no cyclic referral detected.
-                    if let Some(src_account) = src_account_res {
-                        if let Some(ref_account) = ref_account_res {
-                            // Compute rewards amount from the source one.
-                            if let Some(src_amount) = total_accounts.get(&src_account) {
-                                let new_amount = get_referral_amount(src_amount, &level);
-                                // Insert referral rewards or append it for case of multiple referrals
-                                match next_ref_accounts.get(&ref_account) {
-                                    Some(amount) => {
-                                        next_ref_accounts.insert(ref_account, amount + new_amount);
-                                    }
-                                    None => {
-                                        next_ref_accounts.insert(ref_account, new_amount);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+    while schedule.allows_level(level) && !paths.is_empty() {
+        let accounts: BTreeSet<String> = paths.iter().map(|el| el.0.clone()).collect();
+        let accounts: Vec<String> = accounts.into_iter().collect();
+        let referrers: HashMap<String, String> = store
+            .read_referrers(&accounts)?
+            .into_iter()
+            .map(|(src_account, ref_account, _)| (src_account, ref_account))
+            .collect();
+        let mut next_paths = Vec::new();
+        for (account, amount, mut visited) in paths {
+            let Some(referrer) = referrers.get(&account) else {
+                continue;
+            };
+            if !visited.insert(referrer.clone()) {
+                continue;
             }
+            let amount = schedule.referral_amount(&amount, level);
+            if amount.is_zero() {
+                continue;
+            }
+            // The level is the closest one the referrer was reached at.
+            let reward = referrals.entry(referrer.clone()).or_insert(Reward {
+                amount: U256::zero(),
+                level,
+            });
+            reward.amount = reward.amount.saturating_add(amount);
+            next_paths.push((referrer.clone(), amount, visited));
         }
-        if next_ref_accounts.is_empty() {
-            break;
-        }
-        ref_accounts = next_ref_accounts.clone();
-        total_accounts.append(&mut next_ref_accounts);
+        paths = next_paths;
         level += 1;
     }
-    Ok(())
-}
-
-pub fn get_referral_amount(src_amount: &f64, level: &u32) -> f64 {
-    match level {
-        0 => 1.0,
-        1 => 0.1 * src_amount,
-        _ => 0.5 * src_amount,
+    for (account, reward) in referrals {
+        let amount = schedule.cap(reward.amount);
+        // Referrers below the minimum payout aren't paid, but their upline still is.
+        if !schedule.is_paid(&amount) {
+            continue;
+        }
+        match total_accounts.get_mut(&account) {
+            Some(keeper) => keeper.amount = keeper.amount.saturating_add(amount),
+            None => {
+                total_accounts.insert(
+                    account,
+                    Reward {
+                        amount,
+                        level: reward.level,
+                    },
+                );
+            }
+        }
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::referrers_fetch::get_referral_amount;
+    use std::collections::BTreeMap;
+
+    use ethers::types::{Address, U256};
+
+    use crate::{memory_store::MemoryStore, referral_schedule::ReferralSchedule};
+
+    use super::{read_referrers_list, Reward};
+
+    #[tokio::test]
+    async fn test_read_referrers_list() -> Result<(), String> {
+        let account = |idx: u64| Address::from_low_u64_be(idx);
+        let mut store = MemoryStore::default();
+        // 3 is referred by 2 and 2 by 1, 4 and 5 refer each other.
+        store.insert_keeper(&account(1), Some("one"), None);
+        store.insert_keeper(&account(2), Some("two"), Some("one"));
+        store.insert_keeper(&account(3), Some("three"), Some("two"));
+        store.insert_keeper(&account(4), Some("four"), Some("five"));
+        store.insert_keeper(&account(5), Some("five"), Some("four"));
+
+        let schedule = ReferralSchedule::default();
+        let reward = Reward {
+            amount: schedule.time_keeper_reward,
+            level: 0,
+        };
+        let mut total_accounts = BTreeMap::from([
+            (format!("{:#x}", account(3)), reward),
+            (format!("{:#x}", account(4)), reward),
+        ]);
+        read_referrers_list(&mut store, &mut total_accounts, &schedule)
+            .map_err(|err| err.to_string())?;
+
+        let level_1 = schedule.time_keeper_reward / 10;
+        let expected = BTreeMap::from([
+            (
+                format!("{:#x}", account(1)),
+                Reward {
+                    amount: level_1 / 2,
+                    level: 2,
+                },
+            ),
+            (
+                format!("{:#x}", account(2)),
+                Reward {
+                    amount: level_1,
+                    level: 1,
+                },
+            ),
+            (format!("{:#x}", account(3)), reward),
+            (format!("{:#x}", account(4)), reward),
+            (
+                format!("{:#x}", account(5)),
+                Reward {
+                    amount: level_1,
+                    level: 1,
+                },
+            ),
+        ]);
+        assert_eq!(total_accounts, expected);
+        assert_eq!(
+            expected[&format!("{:#x}", account(1))].amount,
+            U256::exp10(16) * 5
+        );
+        Ok(())
+    }
 
     #[tokio::test]
-    async fn test_ref_amount() {
-        assert_eq!(get_referral_amount(&1.0, &1), 0.1);
-        assert_eq!(get_referral_amount(&0.1, &2), 0.05);
-        assert_eq!(get_referral_amount(&0.025, &4), 0.0125);
+    async fn test_read_referrers_list_shared_referrer() -> Result<(), String> {
+        let account = |idx: u64| format!("{:#x}", Address::from_low_u64_be(idx));
+        let mut store = MemoryStore::default();
+        // 1 refers 2 and 3, 2 refers 4. 2, 3 and 4 are the time keepers of the tick.
+        store.insert_keeper(&Address::from_low_u64_be(1), Some("one"), None);
+        store.insert_keeper(&Address::from_low_u64_be(2), Some("two"), Some("one"));
+        store.insert_keeper(&Address::from_low_u64_be(3), Some("three"), Some("one"));
+        store.insert_keeper(&Address::from_low_u64_be(4), Some("four"), Some("two"));
+
+        let ether = |amount: u64| U256::exp10(16) * amount;
+        let mut schedule = ReferralSchedule::default();
+        let reward = |amount: U256, level: u32| Reward { amount, level };
+        let keepers = BTreeMap::from([
+            (account(2), reward(ether(100), 0)),
+            (account(3), reward(ether(100), 0)),
+            (account(4), reward(ether(100), 0)),
+        ]);
+        let mut total_accounts = keepers.clone();
+        read_referrers_list(&mut store, &mut total_accounts, &schedule)
+            .map_err(|err| err.to_string())?;
+        // 1 gets 0.1 from 2 and 3 each and 0.05 from 4, 2 gets 0.1 from 4 on top of its own reward.
+        assert_eq!(
+            total_accounts,
+            BTreeMap::from([
+                (account(1), reward(ether(25), 1)),
+                (account(2), reward(ether(110), 0)),
+                (account(3), reward(ether(100), 0)),
+                (account(4), reward(ether(100), 0)),
+            ])
+        );
+
+        // The cap applies to the sum of the referrer.
+        schedule.cap_per_tick = Some(ether(20));
+        let mut total_accounts = keepers;
+        read_referrers_list(&mut store, &mut total_accounts, &schedule)
+            .map_err(|err| err.to_string())?;
+        assert_eq!(total_accounts[&account(1)], reward(ether(20), 1));
+        assert_eq!(total_accounts[&account(2)], reward(ether(110), 0));
+        Ok(())
     }
 }
@@ -0,0 +1,21 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+// A reason for rejecting a request, answered with its status and a JSON body with the error code.
+pub trait Rejection {
+    fn status(&self) -> StatusCode;
+    fn code(&self) -> &'static str;
+    fn message(&self) -> String;
+
+    fn response(&self) -> Response {
+        let body = Json(json!({
+            "error": self.code(),
+            "message": self.message(),
+        }));
+        (self.status(), body).into_response()
+    }
+}
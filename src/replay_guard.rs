@@ -0,0 +1,269 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use ethers::types::{Bytes, U256};
+use log::error;
+use tokio::{
+    sync::Mutex,
+    time::{interval, MissedTickBehavior},
+};
+
+use crate::{
+    db::{prune_seen_signatures, read_seen_signatures},
+    db_pool::{DbError, DbPool},
+    store::{KeeperStore, StorePool},
+};
+
+// Order of the secp256k1 curve.
+const SECP256K1_N: &str = "0xfffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141";
+
+#[derive(Debug, PartialEq)]
+pub enum Freshness {
+    Fresh,
+    TooOld,
+    InFuture,
+}
+
+// Rejects stale, future and replayed chronicles.
+// Keeps a bounded in-memory set of seen signatures, backed by the `seen_signatures` table.
+pub struct ReplayGuard {
+    max_skew: Duration,
+    capacity: usize,
+    seen: HashSet<Bytes>,
+    order: VecDeque<Bytes>,
+}
+
+impl ReplayGuard {
+    pub fn new(max_skew: Duration, capacity: usize) -> ReplayGuard {
+        ReplayGuard {
+            max_skew,
+            capacity,
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    // Drops the signatures that can't be replayed anymore from the db and loads the rest.
+    pub async fn load(&mut self, db: &DbPool, curr_ts: Duration) -> Result<(), DbError> {
+        let min_epoch = curr_ts.saturating_sub(self.max_skew).as_nanos() as u64;
+        let capacity = self.capacity;
+        let signatures = db
+            .run(move |conn| {
+                prune_seen_signatures(conn, min_epoch)?;
+                read_seen_signatures(conn, capacity)
+            })
+            .await?;
+        // Oldest first, so that they are evicted first.
+        for signature in signatures.into_iter().rev() {
+            self.insert(canonical_signature(&Bytes::from_str(&signature)?));
+        }
+        Ok(())
+    }
+
+    pub fn check_freshness(&self, epoch: &U256, curr_ts: Duration) -> Freshness {
+        let curr_ts: U256 = curr_ts.as_nanos().into();
+        let max_skew: U256 = self.max_skew.as_nanos().into();
+        if epoch.saturating_add(max_skew) < curr_ts {
+            return Freshness::TooOld;
+        }
+        if *epoch > curr_ts.saturating_add(max_skew) {
+            return Freshness::InFuture;
+        }
+        Freshness::Fresh
+    }
+
+    pub fn is_seen(&self, signature: &Bytes) -> bool {
+        self.seen.contains(signature)
+    }
+
+    // Remembers the signature, evicting the oldest one when the cache is full.
+    pub fn insert(&mut self, signature: Bytes) {
+        if !self.seen.insert(signature.clone()) {
+            return;
+        }
+        self.order.push_back(signature);
+        while self.order.len() > self.capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                self.seen.remove(&evicted);
+            }
+        }
+    }
+}
+
+// Signatures recover the same signer with `v` as 0/1 or 27/28 and with `s` or `n - s`,
+// so they are remembered as (r, low s, v in 27/28).
+pub fn canonical_signature(signature: &Bytes) -> Bytes {
+    if signature.len() != 65 {
+        return signature.clone();
+    }
+    let mut s = U256::from_big_endian(&signature[32..64]);
+    let mut v = match signature[64] {
+        0 | 1 => signature[64] + 27,
+        27 | 28 => signature[64],
+        _ => return signature.clone(),
+    };
+    let n = U256::from_str(SECP256K1_N).unwrap_or_default();
+    if s > n / 2 {
+        s = n - s;
+        v = if v == 27 { 28 } else { 27 };
+    }
+    let mut canonical = signature[..32].to_vec();
+    let mut s_bytes = [0u8; 32];
+    s.to_big_endian(&mut s_bytes);
+    canonical.extend_from_slice(&s_bytes);
+    canonical.push(v);
+    Bytes::from(canonical)
+}
+
+// Remembers the signature, returns false if it was seen already.
+// The db insert is the atomic check, the cache only turns away the known replays early.
+pub async fn remember_signature<S: StorePool>(
+    replay_guard: &Mutex<ReplayGuard>,
+    db: &S,
+    signature: &Bytes,
+    epoch: &U256,
+) -> Result<bool, DbError> {
+    let signature = canonical_signature(signature);
+    if replay_guard.lock().await.is_seen(&signature) {
+        return Ok(false);
+    }
+    let (seen, epoch) = (signature.clone(), *epoch);
+    let is_new = db
+        .with_store(move |store| store.store_seen_signature(&seen, &epoch))
+        .await?;
+    if is_new {
+        replay_guard.lock().await.insert(signature);
+    }
+    Ok(is_new)
+}
+
+// Deletes the seen signatures that can't be replayed anymore, they are too old to pass the freshness check.
+pub async fn prune_periodically(db: DbPool, max_skew: Duration) {
+    let mut ticks = interval(max_skew.max(Duration::from_secs(1)));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let curr_ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO);
+        let min_epoch = curr_ts.saturating_sub(max_skew).as_nanos() as u64;
+        if let Err(err) = db
+            .run(move |conn| prune_seen_signatures(conn, min_epoch))
+            .await
+        {
+            error!("Error pruning the seen signatures: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use std::str::FromStr;
+
+    use ethers::{
+        signers::{LocalWallet, Signer},
+        types::{Bytes, U256},
+    };
+
+    use crate::memory_store::MemoryPool;
+
+    use super::{canonical_signature, remember_signature, Freshness, ReplayGuard, SECP256K1_N};
+
+    #[tokio::test]
+    async fn test_check_freshness() -> Result<(), String> {
+        let guard = ReplayGuard::new(Duration::from_secs(5), 10);
+        let curr_ts = Duration::new(1734220767, 0);
+        let epoch = |secs: u64| -> U256 { Duration::new(secs, 0).as_nanos().into() };
+        assert_eq!(
+            guard.check_freshness(&epoch(1734220767), curr_ts),
+            Freshness::Fresh
+        );
+        assert_eq!(
+            guard.check_freshness(&epoch(1734220762), curr_ts),
+            Freshness::Fresh
+        );
+        assert_eq!(
+            guard.check_freshness(&epoch(1734220772), curr_ts),
+            Freshness::Fresh
+        );
+        assert_eq!(
+            guard.check_freshness(&epoch(1734220761), curr_ts),
+            Freshness::TooOld
+        );
+        assert_eq!(
+            guard.check_freshness(&epoch(1734220773), curr_ts),
+            Freshness::InFuture
+        );
+        assert_eq!(
+            guard.check_freshness(&U256::MAX, curr_ts),
+            Freshness::InFuture
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_seen_signatures_bounded() -> Result<(), String> {
+        let mut guard = ReplayGuard::new(Duration::from_secs(5), 2);
+        let sig = |byte: u8| Bytes::from(vec![byte; 65]);
+        guard.insert(sig(1));
+        guard.insert(sig(2));
+        guard.insert(sig(1));
+        assert!(guard.is_seen(&sig(1)));
+        assert!(guard.is_seen(&sig(2)));
+        guard.insert(sig(3));
+        assert!(!guard.is_seen(&sig(1)));
+        assert!(guard.is_seen(&sig(2)));
+        assert!(guard.is_seen(&sig(3)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_malleated_signatures() -> Result<(), String> {
+        let wallet = LocalWallet::from_str(
+            "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )
+        .map_err(|err| err.to_string())?;
+        let signature = wallet
+            .sign_message("1734220767000000000")
+            .await
+            .map_err(|err| err.to_string())?;
+        let original = Bytes::from(signature.to_vec());
+        let mut flipped_v = original.to_vec();
+        flipped_v[64] -= 27;
+        let n = U256::from_str(SECP256K1_N).unwrap();
+        let mut high_s = original.to_vec();
+        (n - signature.s).to_big_endian(&mut high_s[32..64]);
+        high_s[64] = if signature.v == 27 { 28 } else { 27 };
+        assert_eq!(
+            canonical_signature(&Bytes::from(flipped_v.clone())),
+            original
+        );
+        assert_eq!(canonical_signature(&Bytes::from(high_s.clone())), original);
+
+        let guard = tokio::sync::Mutex::new(ReplayGuard::new(Duration::from_secs(5), 10));
+        let db = MemoryPool::default();
+        let epoch = U256::from(1);
+        for (signature, is_new) in [
+            (original, true),
+            (flipped_v.into(), false),
+            (high_s.into(), false),
+        ] {
+            let res = remember_signature(&guard, &db, &signature, &epoch)
+                .await
+                .map_err(|err| err.to_string())?;
+            assert_eq!(res, is_new);
+        }
+        // The db catches the replays the cache doesn't know.
+        let guard = tokio::sync::Mutex::new(ReplayGuard::new(Duration::from_secs(5), 10));
+        let res = remember_signature(&guard, &db, &Bytes::from(signature.to_vec()), &epoch)
+            .await
+            .map_err(|err| err.to_string())?;
+        assert!(!res);
+        Ok(())
+    }
+}
@@ -0,0 +1,426 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use axum::{extract::Query, http::StatusCode, Json};
+use ethers::types::{Address, H256, U256};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{
+        claim_reward_backlog, read_reward_events, read_reward_totals, release_reward_backlog,
+        store_reward_events, update_reward_events,
+    },
+    db_pool::DbPool,
+    referrers_fetch::Reward,
+};
+
+const DEFAULT_HISTORY_LIMIT: u64 = 50;
+const MAX_HISTORY_LIMIT: u64 = 500;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RewardStatus {
+    // The rewards transaction is on its way.
+    Pending,
+    Confirmed,
+    Failed,
+    // Not sent, the tick didn't satisfy the BlockTime params or the chain was backing off.
+    Skipped,
+    // Not sent yet, the BlockTime params hold the tick and its chronicles count in the next one.
+    Held,
+    // Earned in the dry run mode, kept in the backlog.
+    Accrued,
+    // Released from the backlog, settled by the next ticks.
+    Queued,
+}
+
+impl RewardStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RewardStatus::Pending => "pending",
+            RewardStatus::Confirmed => "confirmed",
+            RewardStatus::Failed => "failed",
+            RewardStatus::Skipped => "skipped",
+            RewardStatus::Held => "held",
+            RewardStatus::Accrued => "accrued",
+            RewardStatus::Queued => "queued",
+        }
+    }
+}
+
+impl FromStr for RewardStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(RewardStatus::Pending),
+            "confirmed" => Ok(RewardStatus::Confirmed),
+            "failed" => Ok(RewardStatus::Failed),
+            "skipped" => Ok(RewardStatus::Skipped),
+            "held" => Ok(RewardStatus::Held),
+            "accrued" => Ok(RewardStatus::Accrued),
+            "queued" => Ok(RewardStatus::Queued),
+            other => Err(format!("Unknown reward status \"{}\"", other)),
+        }
+    }
+}
+
+// Reward of one receiver in a tick.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RewardShare {
+    pub receiver: Address,
+    pub amount: U256,
+    pub level: u32,
+}
+
+// A stored reward of one receiver in one tick on one chain.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RewardEvent {
+    pub id: u64,
+    pub chain_id: u64,
+    pub mean_time: String,
+    pub chronicles_md5: String,
+    pub receiver: String,
+    // In wei.
+    pub amount: String,
+    pub referral_level: u32,
+    pub tx_hash: Option<String>,
+    pub status: RewardStatus,
+    pub reason: Option<String>,
+    pub created_at: u64,
+}
+
+// Sums of the rewards of a receiver by status, in wei.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct RewardTotals {
+    pub address: String,
+    pub confirmed: String,
+    pub pending: String,
+    pub failed: String,
+    pub skipped: String,
+    pub held: String,
+    pub accrued: String,
+    pub queued: String,
+}
+
+// Stores the rewards of a tick on a chain, the ledger doesn't stop the tick if the database fails.
+pub async fn record_rewards(
+    db: &DbPool,
+    chain_id: u64,
+    mean_time: &U256,
+    chronicles_md5: &str,
+    shares: &[RewardShare],
+    status: RewardStatus,
+    reason: Option<&str>,
+) {
+    let mean_time = *mean_time;
+    let chronicles_md5 = chronicles_md5.to_string();
+    let shares = shares.to_vec();
+    let reason = reason.map(str::to_string);
+    if let Err(err) = db
+        .run(move |conn| {
+            store_reward_events(
+                conn,
+                chain_id,
+                &mean_time,
+                &chronicles_md5,
+                &shares,
+                status,
+                reason.as_deref(),
+            )
+        })
+        .await
+    {
+        error!(
+            "Error storing the rewards of the chain {}: {}",
+            chain_id, err
+        );
+    }
+}
+
+// Updates the pending rewards of a tick on a chain with the outcome of the transaction.
+// The backlog settled by a failed transaction goes back to the queue.
+pub async fn settle_rewards(
+    db: &DbPool,
+    chain_id: u64,
+    chronicles_md5: &str,
+    status: RewardStatus,
+    tx_hash: Option<H256>,
+    reason: Option<&str>,
+) {
+    if status == RewardStatus::Failed {
+        let chronicles_md5 = chronicles_md5.to_string();
+        if let Err(err) = db
+            .run(move |conn| release_reward_backlog(conn, chain_id, &chronicles_md5))
+            .await
+        {
+            error!(
+                "Error releasing the backlog of the chain {}: {}",
+                chain_id, err
+            );
+        }
+    }
+    let chronicles_md5 = chronicles_md5.to_string();
+    let reason = reason.map(str::to_string);
+    if let Err(err) = db
+        .run(move |conn| {
+            update_reward_events(
+                conn,
+                chain_id,
+                &chronicles_md5,
+                status,
+                tx_hash,
+                reason.as_deref(),
+            )
+        })
+        .await
+    {
+        error!(
+            "Error updating the rewards of the chain {}: {}",
+            chain_id, err
+        );
+    }
+}
+
+// Takes queued backlog rewards of up to `max_receivers` receivers for the tick, summed by receiver.
+pub async fn claim_backlog(
+    db: &DbPool,
+    chain_id: u64,
+    chronicles_md5: &str,
+    max_receivers: usize,
+) -> Vec<(Address, U256)> {
+    if max_receivers == 0 {
+        return Vec::new();
+    }
+    let chronicles_md5 = chronicles_md5.to_string();
+    match db
+        .run(move |conn| claim_reward_backlog(conn, chain_id, &chronicles_md5, max_receivers))
+        .await
+    {
+        Ok(backlog) => backlog,
+        Err(err) => {
+            error!(
+                "Error claiming the backlog of the chain {}: {}",
+                chain_id, err
+            );
+            Vec::new()
+        }
+    }
+}
+
+// Rewards of the accounts with valid addresses, an account is dropped with its amount.
+pub fn reward_shares(accounts: BTreeMap<String, Reward>) -> Vec<RewardShare> {
+    accounts
+        .into_iter()
+        .filter_map(|(account, reward)| match account.parse::<Address>() {
+            Ok(receiver) => Some(RewardShare {
+                receiver,
+                amount: reward.amount,
+                level: reward.level,
+            }),
+            Err(err) => {
+                warn!(
+                    "Skipping the reward of the invalid address {}: {}",
+                    account, err
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+// Receivers and amounts of a tick including the backlog, one entry per receiver.
+pub fn merge_backlog(
+    shares: &[RewardShare],
+    backlog: &[(Address, U256)],
+) -> (Vec<Address>, Vec<U256>) {
+    let mut amounts: BTreeMap<Address, U256> = BTreeMap::new();
+    let shares = shares.iter().map(|el| (el.receiver, el.amount));
+    for (receiver, amount) in shares.chain(backlog.iter().copied()) {
+        let total = amounts.entry(receiver).or_default();
+        *total = total.saturating_add(amount);
+    }
+    amounts.into_iter().unzip()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RewardHistoryParams {
+    address: String,
+    #[serde(default)]
+    offset: u64,
+    limit: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RewardTotalsParams {
+    address: String,
+}
+
+// Newest rewards of a receiver first.
+pub async fn handle_get_reward_history(
+    Query(params): Query<RewardHistoryParams>,
+    db: DbPool,
+) -> Result<Json<Vec<RewardEvent>>, StatusCode> {
+    let address = Address::from_str(&params.address).map_err(|err| {
+        error!("Error extracting address: {}", err);
+        StatusCode::BAD_REQUEST
+    })?;
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .min(MAX_HISTORY_LIMIT);
+    let offset = params.offset;
+    match db
+        .run(move |conn| read_reward_events(conn, &address, offset, limit))
+        .await
+    {
+        Ok(events) => Ok(Json(events)),
+        Err(err) => {
+            error!("Error reading rewards: {}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn handle_get_reward_totals(
+    Query(params): Query<RewardTotalsParams>,
+    db: DbPool,
+) -> Result<Json<RewardTotals>, StatusCode> {
+    let address = Address::from_str(&params.address).map_err(|err| {
+        error!("Error extracting address: {}", err);
+        StatusCode::BAD_REQUEST
+    })?;
+    let sums = db
+        .run(move |conn| read_reward_totals(conn, &address))
+        .await
+        .map_err(|err| {
+            error!("Error reading reward totals: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(reward_totals(&address, sums)))
+}
+
+fn reward_totals(address: &Address, sums: Vec<(RewardStatus, String)>) -> RewardTotals {
+    let mut totals = RewardTotals {
+        address: format!("{:#x}", address),
+        confirmed: "0".to_string(),
+        pending: "0".to_string(),
+        failed: "0".to_string(),
+        skipped: "0".to_string(),
+        held: "0".to_string(),
+        accrued: "0".to_string(),
+        queued: "0".to_string(),
+    };
+    for (status, sum) in sums {
+        match status {
+            RewardStatus::Pending => totals.pending = sum,
+            RewardStatus::Confirmed => totals.confirmed = sum,
+            RewardStatus::Failed => totals.failed = sum,
+            RewardStatus::Skipped => totals.skipped = sum,
+            RewardStatus::Held => totals.held = sum,
+            RewardStatus::Accrued => totals.accrued = sum,
+            RewardStatus::Queued => totals.queued = sum,
+        }
+    }
+    totals
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use std::collections::BTreeMap;
+
+    use ethers::types::{Address, U256};
+    use proptest::prelude::*;
+
+    use crate::referrers_fetch::Reward;
+
+    use super::{merge_backlog, reward_shares, reward_totals, RewardShare, RewardStatus};
+
+    #[tokio::test]
+    async fn test_reward_totals() -> Result<(), String> {
+        for status in [
+            RewardStatus::Pending,
+            RewardStatus::Confirmed,
+            RewardStatus::Failed,
+            RewardStatus::Skipped,
+            RewardStatus::Held,
+            RewardStatus::Accrued,
+            RewardStatus::Queued,
+        ] {
+            assert_eq!(RewardStatus::from_str(status.as_str())?, status);
+        }
+        assert!(RewardStatus::from_str("lost").is_err());
+
+        let address = Address::from_str("0x25EE756F5D93E26F5011B7ED4866AFB192CE483E").unwrap();
+        let totals = reward_totals(
+            &address,
+            vec![
+                (RewardStatus::Confirmed, "1100000000000000000".to_string()),
+                (RewardStatus::Failed, "1000000000000000000".to_string()),
+            ],
+        );
+        assert_eq!(totals.address, "0x25ee756f5d93e26f5011b7ed4866afb192ce483e");
+        assert_eq!(totals.confirmed, "1100000000000000000");
+        assert_eq!(totals.failed, "1000000000000000000");
+        assert_eq!(totals.pending, "0");
+        assert_eq!(totals.skipped, "0");
+        assert_eq!(totals.held, "0");
+        assert_eq!(totals.accrued, "0");
+        assert_eq!(totals.queued, "0");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_merge_backlog() -> Result<(), String> {
+        let keeper = Address::from_str("0x25ee756f5d93e26f5011b7ed4866afb192ce483e").unwrap();
+        let referrer = Address::from_str("0x2c57d1cfc6d5f8e4182a56b4cf75421472ebaea4").unwrap();
+        let shares = vec![RewardShare {
+            receiver: keeper,
+            amount: U256::from(10),
+            level: 0,
+        }];
+        let backlog = vec![(referrer, U256::from(1)), (keeper, U256::from(20))];
+        let (receivers, amounts) = merge_backlog(&shares, &backlog);
+        assert_eq!(receivers, vec![keeper, referrer]);
+        assert_eq!(amounts, vec![U256::from(30), U256::from(1)]);
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn test_receivers_and_amounts_match(
+            accounts in prop::collection::btree_map(
+                prop_oneof![
+                    any::<[u8; 20]>().prop_map(|el| format!("{:#x}", Address::from(el))),
+                    "[0-9a-z]{0,12}",
+                ],
+                (any::<u128>(), 0u32..8),
+                0..32,
+            ),
+            backlog in prop::collection::vec((any::<[u8; 20]>(), any::<u128>()), 0..32),
+        ) {
+            let accounts: BTreeMap<String, Reward> = accounts
+                .into_iter()
+                .map(|(account, (amount, level))| (account, Reward { amount: amount.into(), level }))
+                .collect();
+            let valid_total = accounts
+                .iter()
+                .filter(|(account, _)| account.parse::<Address>().is_ok())
+                .fold(U256::zero(), |acc, (_, reward)| acc + reward.amount);
+            let backlog: Vec<(Address, U256)> = backlog
+                .into_iter()
+                .map(|(receiver, amount)| (Address::from(receiver), U256::from(amount)))
+                .collect();
+            let backlog_total = backlog.iter().fold(U256::zero(), |acc, el| acc + el.1);
+
+            let shares = reward_shares(accounts);
+            let (receivers, amounts) = merge_backlog(&shares, &backlog);
+            prop_assert_eq!(receivers.len(), amounts.len());
+            prop_assert!(receivers.windows(2).all(|el| el[0] < el[1]));
+            let total = amounts.iter().fold(U256::zero(), |acc, el| acc + el);
+            prop_assert_eq!(total, valid_total + backlog_total);
+        }
+    }
+}
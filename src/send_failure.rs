@@ -0,0 +1,155 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use ethers::{
+    contract::{ContractError, ContractRevert},
+    providers::Middleware,
+    types::{Bytes, H256},
+};
+
+use crate::{call_breaker::CallBreakerErrors, time_signature::BlockTimeErrors};
+
+// Custom error a reward transaction reverted with.
+#[derive(Debug, PartialEq)]
+pub enum RevertReason {
+    CallBreaker(CallBreakerErrors),
+    BlockTime(BlockTimeErrors),
+    Unknown(Bytes),
+}
+
+impl RevertReason {
+    // `moveTime` is called through the CallBreaker, so the data may hold errors of both contracts.
+    pub fn decode(data: &Bytes) -> RevertReason {
+        if let Some(err) = CallBreakerErrors::decode_with_selector(data) {
+            return RevertReason::CallBreaker(err);
+        }
+        if let Some(err) = BlockTimeErrors::decode_with_selector(data) {
+            return RevertReason::BlockTime(err);
+        }
+        RevertReason::Unknown(data.clone())
+    }
+}
+
+impl Display for RevertReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            RevertReason::CallBreaker(err) => write!(f, "CallBreaker {:?}", err),
+            RevertReason::BlockTime(err) => write!(f, "BlockTime {:?}", err),
+            RevertReason::Unknown(data) => write!(f, "unknown revert data {}", data),
+        }
+    }
+}
+
+// Reasons the rewards didn't make it on chain.
+#[derive(Debug, PartialEq)]
+pub enum SendFailure {
+    // The call reverts, detected before sending the transaction.
+    Reverted(RevertReason),
+    // The transaction was mined with the status 0.
+    TxReverted(H256),
+    // The transaction was dropped from the mempool.
+    Dropped(H256),
+    // No receipt within the max tracking time, the transaction is resumed by the next send.
+    Stuck(H256),
+    // Not sent, the resumed transaction with the hash is still pending.
+    Blocked(H256),
+    // The transaction couldn't be built.
+    Encoding(String),
+    // The node couldn't estimate, send or track the transaction.
+    Provider(String),
+    // The transaction couldn't be persisted, so it wasn't sent.
+    Storage(String),
+}
+
+impl SendFailure {
+    pub fn tx_hash(&self) -> Option<H256> {
+        match self {
+            SendFailure::TxReverted(tx_hash)
+            | SendFailure::Dropped(tx_hash)
+            | SendFailure::Stuck(tx_hash) => Some(*tx_hash),
+            _ => None,
+        }
+    }
+
+    pub fn from_contract_error<M: Middleware>(err: ContractError<M>) -> SendFailure {
+        match err.as_revert() {
+            Some(data) => SendFailure::Reverted(RevertReason::decode(data)),
+            None => SendFailure::Provider(err.to_string()),
+        }
+    }
+}
+
+impl Display for SendFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            SendFailure::Reverted(reason) => write!(f, "call reverted with {}", reason),
+            SendFailure::TxReverted(tx_hash) => write!(f, "transaction {:#x} reverted", tx_hash),
+            SendFailure::Dropped(tx_hash) => write!(f, "transaction {:#x} was dropped", tx_hash),
+            SendFailure::Stuck(tx_hash) => {
+                write!(f, "transaction {:#x} is still pending", tx_hash)
+            }
+            SendFailure::Blocked(tx_hash) => {
+                write!(f, "not sent behind the pending transaction {:#x}", tx_hash)
+            }
+            SendFailure::Encoding(err) => write!(f, "encoding error: {}", err),
+            SendFailure::Provider(err) => write!(f, "provider error: {}", err),
+            SendFailure::Storage(err) => write!(f, "storage error: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use ethers::{
+        abi::AbiEncode,
+        contract::ContractError,
+        providers::{MockProvider, Provider},
+        types::{Address, Bytes, H256},
+    };
+
+    use crate::{
+        call_breaker::{CallBreakerErrors, UnauthorisedSigner},
+        time_signature::{AccessControlUnauthorizedAccount, BlockTimeErrors},
+    };
+
+    use super::{RevertReason, SendFailure};
+
+    #[tokio::test]
+    async fn test_decode_revert() -> Result<(), String> {
+        // Encoded errors are prefixed with their selector.
+        let keeper = Address::from_str("0x25ee756f5d93e26f5011b7ed4866afb192ce483e").unwrap();
+        let err = UnauthorisedSigner {
+            recovered_address: keeper,
+            signer: Address::zero(),
+        };
+        let data = Bytes::from(err.clone().encode());
+        assert_eq!(
+            RevertReason::decode(&data),
+            RevertReason::CallBreaker(CallBreakerErrors::UnauthorisedSigner(err))
+        );
+
+        let err = AccessControlUnauthorizedAccount {
+            account: keeper,
+            needed_role: H256::zero().into(),
+        };
+        let data = Bytes::from(err.clone().encode());
+        assert_eq!(
+            RevertReason::decode(&data),
+            RevertReason::BlockTime(BlockTimeErrors::AccessControlUnauthorizedAccount(err))
+        );
+
+        let data = Bytes::from_str("0xdeadbeef").unwrap();
+        assert_eq!(
+            RevertReason::decode(&data),
+            RevertReason::Unknown(data.clone())
+        );
+
+        let err = ContractError::<Provider<MockProvider>>::Revert(data.clone());
+        assert_eq!(
+            SendFailure::from_contract_error(err),
+            SendFailure::Reverted(RevertReason::Unknown(data))
+        );
+        Ok(())
+    }
+}
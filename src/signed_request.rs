@@ -0,0 +1,442 @@
+use std::{
+    convert::Infallible,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use ethers::{
+    abi::{encode, Token},
+    types::{
+        transaction::eip712::{EIP712Domain, Eip712},
+        Address, Bytes, Signature, H256, U256,
+    },
+    utils::keccak256,
+};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    rejection::Rejection,
+    replay_guard::{remember_signature, Freshness, ReplayGuard},
+    store::StorePool,
+    time_signature::SignatureScheme,
+    user_data::{AvatarData, ReferralCodeData, ReferredFromData, SessionData, UserData},
+};
+
+// A profile change the time keeper signs, it changes one string field.
+pub trait SignedPayload {
+    // EIP-712 primary type, also the first line of the EIP-191 message.
+    const TYPE_NAME: &'static str;
+    const FIELD_NAME: &'static str;
+
+    fn time_keeper(&self) -> Address;
+    fn field(&self) -> &str;
+
+    fn type_string() -> String {
+        format!(
+            "{}(address timeKeeper,string {},uint256 timestamp)",
+            Self::TYPE_NAME,
+            Self::FIELD_NAME
+        )
+    }
+
+    // The EIP-191 personal message of the change.
+    fn message(&self, timestamp: &U256) -> String {
+        format!(
+            "{}\ntimeKeeper: {:#x}\n{}: {}\ntimestamp: {}",
+            Self::TYPE_NAME,
+            self.time_keeper(),
+            Self::FIELD_NAME,
+            self.field(),
+            timestamp
+        )
+    }
+}
+
+impl SignedPayload for UserData {
+    const TYPE_NAME: &'static str = "Onboard";
+    const FIELD_NAME: &'static str = "avatar";
+
+    fn time_keeper(&self) -> Address {
+        self.time_keeper
+    }
+
+    fn field(&self) -> &str {
+        &self.avatar
+    }
+}
+
+impl SignedPayload for AvatarData {
+    const TYPE_NAME: &'static str = "ClaimAvatar";
+    const FIELD_NAME: &'static str = "avatar";
+
+    fn time_keeper(&self) -> Address {
+        self.time_keeper
+    }
+
+    fn field(&self) -> &str {
+        &self.avatar
+    }
+}
+
+impl SignedPayload for ReferralCodeData {
+    const TYPE_NAME: &'static str = "UpdateReferralCode";
+    const FIELD_NAME: &'static str = "referralCode";
+
+    fn time_keeper(&self) -> Address {
+        self.time_keeper
+    }
+
+    fn field(&self) -> &str {
+        &self.referral_code
+    }
+}
+
+impl SignedPayload for ReferredFromData {
+    const TYPE_NAME: &'static str = "UpdateReferredFrom";
+    const FIELD_NAME: &'static str = "referredFrom";
+
+    fn time_keeper(&self) -> Address {
+        self.time_keeper
+    }
+
+    fn field(&self) -> &str {
+        &self.referred_from
+    }
+}
+
+impl SignedPayload for SessionData {
+    const TYPE_NAME: &'static str = "OpenKeeperSession";
+    const FIELD_NAME: &'static str = "session";
+
+    fn time_keeper(&self) -> Address {
+        self.time_keeper
+    }
+
+    fn field(&self) -> &str {
+        &self.session
+    }
+}
+
+// Request body with the payload fields and the signature of the time keeper over them.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SignedRequest<T> {
+    #[serde(flatten)]
+    pub data: T,
+    // Unix time in nanoseconds, must be within the max epoch skew like the chronicle epochs.
+    pub timestamp: String,
+    pub signature: String,
+    #[serde(default)]
+    pub scheme: SignatureScheme,
+}
+
+// The typed data a time keeper signs with the EIP-712 scheme.
+struct TypedRequest<'a, T> {
+    domain: &'a EIP712Domain,
+    data: &'a T,
+    timestamp: U256,
+}
+
+impl<T: SignedPayload> Eip712 for TypedRequest<'_, T> {
+    type Error = Infallible;
+
+    fn domain(&self) -> Result<EIP712Domain, Self::Error> {
+        Ok(self.domain.clone())
+    }
+
+    fn type_hash() -> Result<[u8; 32], Self::Error> {
+        Ok(keccak256(T::type_string()))
+    }
+
+    fn struct_hash(&self) -> Result<[u8; 32], Self::Error> {
+        Ok(keccak256(encode(&[
+            Token::FixedBytes(Self::type_hash()?.to_vec()),
+            Token::Address(self.data.time_keeper()),
+            Token::FixedBytes(keccak256(self.data.field()).to_vec()),
+            Token::Uint(self.timestamp),
+        ])))
+    }
+}
+
+// Reasons for rejecting a signed request, each one has its own status and error code.
+#[derive(Debug, PartialEq)]
+pub enum RequestRejection {
+    MalformedInput(String),
+    InvalidSignature,
+    TimestampTooOld,
+    TimestampInFuture,
+    Replayed,
+    Internal,
+}
+
+impl Rejection for RequestRejection {
+    fn status(&self) -> StatusCode {
+        match self {
+            RequestRejection::MalformedInput(_) => StatusCode::BAD_REQUEST,
+            RequestRejection::InvalidSignature => StatusCode::UNAUTHORIZED,
+            RequestRejection::TimestampTooOld => StatusCode::UNPROCESSABLE_ENTITY,
+            RequestRejection::TimestampInFuture => StatusCode::TOO_EARLY,
+            RequestRejection::Replayed => StatusCode::CONFLICT,
+            RequestRejection::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            RequestRejection::MalformedInput(_) => "malformed_input",
+            RequestRejection::InvalidSignature => "invalid_signature",
+            RequestRejection::TimestampTooOld => "timestamp_too_old",
+            RequestRejection::TimestampInFuture => "timestamp_in_future",
+            RequestRejection::Replayed => "signature_replayed",
+            RequestRejection::Internal => "internal_error",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            RequestRejection::MalformedInput(err) => format!("Malformed input: {}", err),
+            RequestRejection::InvalidSignature => {
+                "The signature doesn't match the time keeper".to_string()
+            }
+            RequestRejection::TimestampTooOld => "The timestamp is too far in the past".to_string(),
+            RequestRejection::TimestampInFuture => {
+                "The timestamp is too far in the future".to_string()
+            }
+            RequestRejection::Replayed => "The signature was already submitted".to_string(),
+            RequestRejection::Internal => "Internal server error".to_string(),
+        }
+    }
+}
+
+impl IntoResponse for RequestRejection {
+    fn into_response(self) -> Response {
+        self.response()
+    }
+}
+
+impl<T: SignedPayload> SignedRequest<T> {
+    // Checks that the time keeper signed the request recently and only once.
+    pub async fn verify<S: StorePool>(
+        &self,
+        db: &S,
+        replay_guard: &Arc<Mutex<ReplayGuard>>,
+        signing_domain: &EIP712Domain,
+    ) -> Result<(), RequestRejection> {
+        let timestamp = U256::from_dec_str(&self.timestamp).map_err(|err| {
+            error!("Error extracting timestamp: {}", err);
+            RequestRejection::MalformedInput(format!("timestamp: {}", err))
+        })?;
+        let signature = Bytes::from_str(&self.signature).map_err(|err| {
+            error!("Error extracting signature: {}", err);
+            RequestRejection::MalformedInput(format!("signature: {}", err))
+        })?;
+        let time_keeper = self.data.time_keeper();
+        let curr_ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO);
+        match replay_guard
+            .lock()
+            .await
+            .check_freshness(&timestamp, curr_ts)
+        {
+            Freshness::Fresh => {}
+            Freshness::TooOld => return Err(RequestRejection::TimestampTooOld),
+            Freshness::InFuture => return Err(RequestRejection::TimestampInFuture),
+        }
+        if !self.is_signed_by_time_keeper(&signature, &timestamp, signing_domain) {
+            warn!("Invalid {} signature from {:#x}", T::TYPE_NAME, time_keeper);
+            return Err(RequestRejection::InvalidSignature);
+        }
+        match remember_signature(replay_guard, db, &signature, &timestamp).await {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                warn!(
+                    "Replayed {} signature from {:#x}",
+                    T::TYPE_NAME,
+                    time_keeper
+                );
+                Err(RequestRejection::Replayed)
+            }
+            Err(err) => {
+                error!("Error storing seen signature: {}", err);
+                Err(RequestRejection::Internal)
+            }
+        }
+    }
+
+    fn is_signed_by_time_keeper(
+        &self,
+        signature: &Bytes,
+        timestamp: &U256,
+        signing_domain: &EIP712Domain,
+    ) -> bool {
+        let signature = match Signature::try_from(signature.to_vec().as_slice()) {
+            Ok(signature) => signature,
+            Err(err) => {
+                error!("Error parsing signature: {}", err);
+                return false;
+            }
+        };
+        let time_keeper = self.data.time_keeper();
+        let res = match self.scheme {
+            SignatureScheme::Eip191 => signature.verify(self.data.message(timestamp), time_keeper),
+            SignatureScheme::Eip712 => {
+                let typed = TypedRequest {
+                    domain: signing_domain,
+                    data: &self.data,
+                    timestamp: *timestamp,
+                };
+                let hash = typed.encode_eip712().unwrap_or_else(|never| match never {});
+                signature.verify(H256::from(hash), time_keeper)
+            }
+        };
+        res.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        str::FromStr,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use ethers::{
+        signers::{LocalWallet, Signer},
+        types::{Address, Bytes, U256},
+    };
+    use tokio::sync::Mutex;
+
+    use crate::{
+        memory_store::MemoryPool,
+        replay_guard::ReplayGuard,
+        time_signature::{chronicle_domain, SignatureScheme},
+        user_data::ReferralCodeData,
+    };
+
+    use super::{RequestRejection, SignedPayload, SignedRequest, TypedRequest};
+
+    #[tokio::test]
+    async fn test_verify_signed_request() -> Result<(), String> {
+        let wallet = LocalWallet::from_str(
+            "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )
+        .map_err(|err| err.to_string())?;
+        let domain = chronicle_domain(
+            21363,
+            Address::from_str("0xdD1B4D9337D0a8Ef2F133a39cC93EF85261b4A80").unwrap(),
+            &Bytes::from_str("0x01").unwrap(),
+        );
+        let data = ReferralCodeData {
+            time_keeper: wallet.address(),
+            referral_code: "code".to_string(),
+        };
+        let timestamp = U256::from_dec_str("1734554316445000000").unwrap();
+        assert_eq!(
+            ReferralCodeData::type_string(),
+            "UpdateReferralCode(address timeKeeper,string referralCode,uint256 timestamp)"
+        );
+
+        let signature = wallet
+            .sign_message(data.message(&timestamp))
+            .await
+            .map_err(|err| err.to_string())?;
+        let mut request = SignedRequest {
+            data,
+            timestamp: timestamp.to_string(),
+            signature: signature.to_string(),
+            scheme: SignatureScheme::Eip191,
+        };
+        let signature = Bytes::from(signature.to_vec());
+        assert!(request.is_signed_by_time_keeper(&signature, &timestamp, &domain));
+        // Signed for another time.
+        assert!(!request.is_signed_by_time_keeper(&signature, &(timestamp + 1), &domain));
+
+        let signature = wallet
+            .sign_typed_data(&TypedRequest {
+                domain: &domain,
+                data: &request.data,
+                timestamp,
+            })
+            .await
+            .map_err(|err| err.to_string())?;
+        let signature = Bytes::from(signature.to_vec());
+        request.scheme = SignatureScheme::Eip712;
+        assert!(request.is_signed_by_time_keeper(&signature, &timestamp, &domain));
+        // Signed for another referral code.
+        request.data.referral_code = "other".to_string();
+        assert!(!request.is_signed_by_time_keeper(&signature, &timestamp, &domain));
+        // Signed by another key.
+        request.data.referral_code = "code".to_string();
+        request.data.time_keeper = Address::zero();
+        assert!(!request.is_signed_by_time_keeper(&signature, &timestamp, &domain));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replayed_signed_request() -> Result<(), String> {
+        let wallet = LocalWallet::from_str(
+            "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )
+        .map_err(|err| err.to_string())?;
+        let domain = chronicle_domain(
+            21363,
+            Address::from_str("0xdD1B4D9337D0a8Ef2F133a39cC93EF85261b4A80").unwrap(),
+            &Bytes::from_str("0x01").unwrap(),
+        );
+        let db = MemoryPool::default();
+        let replay_guard = Arc::new(Mutex::new(ReplayGuard::new(Duration::from_secs(60), 16)));
+        let data = ReferralCodeData {
+            time_keeper: wallet.address(),
+            referral_code: "code".to_string(),
+        };
+        let curr_ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO);
+        let timestamp = U256::from(curr_ts.as_nanos());
+        let signature = wallet
+            .sign_message(data.message(&timestamp))
+            .await
+            .map_err(|err| err.to_string())?;
+        let mut request = SignedRequest {
+            data,
+            timestamp: timestamp.to_string(),
+            signature: signature.to_string(),
+            scheme: SignatureScheme::Eip191,
+        };
+        assert!(request.verify(&db, &replay_guard, &domain).await.is_ok());
+
+        // The same signature with v as 0/1 still recovers the time keeper.
+        let mut malleated = signature.to_vec();
+        malleated[64] -= 27;
+        let malleated = Bytes::from(malleated);
+        assert!(request.is_signed_by_time_keeper(&malleated, &timestamp, &domain));
+        request.signature = malleated.to_string();
+        let res = request.verify(&db, &replay_guard, &domain).await;
+        assert!(matches!(res, Err(RequestRejection::Replayed)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_signed_request_body() -> Result<(), String> {
+        let request: SignedRequest<ReferralCodeData> = serde_json::from_str(
+            r#"{
+                "time_keeper": "0x25ee756f5d93e26f5011b7ed4866afb192ce483e",
+                "referral_code": "code",
+                "timestamp": "1734554316445000000",
+                "signature": "0x00"
+            }"#,
+        )
+        .map_err(|err| err.to_string())?;
+        assert_eq!(request.data.referral_code, "code");
+        assert_eq!(request.scheme, SignatureScheme::Eip191);
+        Ok(())
+    }
+}
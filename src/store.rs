@@ -0,0 +1,312 @@
+use std::{collections::HashMap, error::Error, future::Future, pin::Pin, time::Duration};
+
+use ethers::types::{Address, Bytes, U256};
+use mysql::Conn;
+
+use crate::{
+    db::{self, ReferrerLink},
+    db_pool::{DbError, DbPool},
+    referral::ReferralData,
+    referral_tree::{Keeper, Link},
+};
+
+// Time keeper profiles: the whitelist, avatars, referral codes, referrers and submitted signatures.
+pub trait KeeperStore {
+    fn store_user_data(&mut self, addr: &Address, avatar: &str) -> Result<(), Box<dyn Error>>;
+    fn is_address_whitelisted(&mut self, addr: &Address) -> Result<bool, Box<dyn Error>>;
+    fn get_time_keepers_count(&mut self) -> Result<u64, Box<dyn Error>>;
+    fn is_avatar_available(&mut self, addr: &Address, avatar: &str)
+        -> Result<bool, Box<dyn Error>>;
+    // Returns false if another time keeper holds the avatar.
+    fn update_avatar(&mut self, addr: &Address, avatar: &str) -> Result<bool, Box<dyn Error>>;
+    // Referees of the old code follow the time keeper to the new one.
+    // Returns false if another time keeper holds the code.
+    fn update_referral_code(
+        &mut self,
+        addr: &Address,
+        referral_code: &str,
+    ) -> Result<bool, Box<dyn Error>>;
+    fn update_referred_from(
+        &mut self,
+        addr: &Address,
+        referred_from: &str,
+    ) -> Result<(), Box<dyn Error>>;
+    // Unix time when the referrer was first set.
+    fn read_referred_at(&mut self, addr: &Address) -> Result<Option<u64>, Box<dyn Error>>;
+    fn read_keeper(&mut self, addr: &Address) -> Result<Option<Keeper>, Box<dyn Error>>;
+    fn read_keeper_by_referral_code(
+        &mut self,
+        referral_code: &str,
+    ) -> Result<Option<Keeper>, Box<dyn Error>>;
+    // Returns false if the signature was already stored.
+    fn store_seen_signature(
+        &mut self,
+        signature: &Bytes,
+        epoch: &U256,
+    ) -> Result<bool, Box<dyn Error>>;
+    // Returns false unless the nonce is greater than the last one of the time keeper.
+    fn advance_chronicle_nonce(
+        &mut self,
+        addr: &Address,
+        nonce: u64,
+    ) -> Result<bool, Box<dyn Error>>;
+    // Round trip to the store, for the readiness check.
+    fn ping(&mut self) -> Result<(), Box<dyn Error>>;
+}
+
+// Referral links: the device referrals of the referral web app, the referrer chains and the
+// referral trees.
+pub trait ReferralStore {
+    // Empty if the device wasn't referred.
+    fn read_referral(&mut self, ref_key: &str) -> Result<String, Box<dyn Error>>;
+    // Keeps the first referral of a device.
+    fn write_referral(&mut self, ref_data: &ReferralData) -> Result<(), Box<dyn Error>>;
+    // Direct referrers of the accounts.
+    fn read_referrers(&mut self, addresses: &[String])
+        -> Result<Vec<ReferrerLink>, Box<dyn Error>>;
+    // The referees within the depth ordered by level and address, with their total count.
+    fn read_downline(
+        &mut self,
+        keeper: &Keeper,
+        depth: u32,
+        offset: u64,
+        limit: u64,
+    ) -> Result<(u64, Vec<Link>), Box<dyn Error>>;
+    // Direct referees of the time keepers and how many of them were rewarded within the period.
+    fn read_referral_stats(
+        &mut self,
+        addresses: &[&str],
+        active_period: Duration,
+    ) -> Result<HashMap<String, (u64, u64)>, Box<dyn Error>>;
+    // Sums of the confirmed referral rewards, in wei.
+    fn read_referral_earnings(
+        &mut self,
+        addresses: &[&str],
+    ) -> Result<HashMap<String, String>, Box<dyn Error>>;
+}
+
+// User objective nonces of the solvers: the next unused one and the released ones of each chain.
+pub trait NonceStore {
+    fn read_user_objective_nonce(
+        &mut self,
+        chain_id: u64,
+        solver: &Address,
+    ) -> Result<Option<u64>, Box<dyn Error>>;
+    fn store_user_objective_nonce(
+        &mut self,
+        chain_id: u64,
+        solver: &Address,
+        next_nonce: u64,
+    ) -> Result<(), Box<dyn Error>>;
+    fn read_released_nonces(
+        &mut self,
+        chain_id: u64,
+        solver: &Address,
+    ) -> Result<Vec<u64>, Box<dyn Error>>;
+    fn store_released_nonce(
+        &mut self,
+        chain_id: u64,
+        solver: &Address,
+        nonce: u64,
+    ) -> Result<(), Box<dyn Error>>;
+    fn delete_released_nonce(
+        &mut self,
+        chain_id: u64,
+        solver: &Address,
+        nonce: u64,
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+pub trait Store: KeeperStore + ReferralStore + NonceStore {}
+
+impl<S: KeeperStore + ReferralStore + NonceStore> Store for S {}
+
+pub type StoreFuture<T> = Pin<Box<dyn Future<Output = Result<T, DbError>> + Send>>;
+
+// Hands out stores to the handlers, the MySQL pool in production.
+// The future is boxed, so that generic handlers stay Send.
+pub trait StorePool: Clone + Send + Sync + 'static {
+    type Store: Store;
+
+    fn with_store<T, F>(&self, queries: F) -> StoreFuture<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Self::Store) -> Result<T, Box<dyn Error>> + Send + 'static;
+}
+
+impl StorePool for DbPool {
+    type Store = Conn;
+
+    fn with_store<T, F>(&self, queries: F) -> StoreFuture<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Conn) -> Result<T, Box<dyn Error>> + Send + 'static,
+    {
+        let pool = self.clone();
+        Box::pin(async move { pool.run(queries).await })
+    }
+}
+
+impl KeeperStore for Conn {
+    fn store_user_data(&mut self, addr: &Address, avatar: &str) -> Result<(), Box<dyn Error>> {
+        db::store_user_data(self, addr, avatar)
+    }
+
+    fn is_address_whitelisted(&mut self, addr: &Address) -> Result<bool, Box<dyn Error>> {
+        db::is_address_whitelisted(self, addr)
+    }
+
+    fn get_time_keepers_count(&mut self) -> Result<u64, Box<dyn Error>> {
+        db::get_time_keepers_count(self)
+    }
+
+    fn is_avatar_available(
+        &mut self,
+        addr: &Address,
+        avatar: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        db::is_avatar_available(self, addr, avatar)
+    }
+
+    fn update_avatar(&mut self, addr: &Address, avatar: &str) -> Result<bool, Box<dyn Error>> {
+        db::update_avatar(self, addr, avatar)
+    }
+
+    fn update_referral_code(
+        &mut self,
+        addr: &Address,
+        referral_code: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        db::update_referral_code(self, addr, referral_code)
+    }
+
+    fn update_referred_from(
+        &mut self,
+        addr: &Address,
+        referred_from: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        db::update_referred_from(self, addr, referred_from)
+    }
+
+    fn read_referred_at(&mut self, addr: &Address) -> Result<Option<u64>, Box<dyn Error>> {
+        db::read_referred_at(self, addr)
+    }
+
+    fn read_keeper(&mut self, addr: &Address) -> Result<Option<Keeper>, Box<dyn Error>> {
+        db::read_keeper(self, addr)
+    }
+
+    fn read_keeper_by_referral_code(
+        &mut self,
+        referral_code: &str,
+    ) -> Result<Option<Keeper>, Box<dyn Error>> {
+        db::read_keeper_by_referral_code(self, referral_code)
+    }
+
+    fn store_seen_signature(
+        &mut self,
+        signature: &Bytes,
+        epoch: &U256,
+    ) -> Result<bool, Box<dyn Error>> {
+        db::store_seen_signature(self, signature, epoch)
+    }
+
+    fn advance_chronicle_nonce(
+        &mut self,
+        addr: &Address,
+        nonce: u64,
+    ) -> Result<bool, Box<dyn Error>> {
+        db::advance_chronicle_nonce(self, addr, nonce)
+    }
+
+    fn ping(&mut self) -> Result<(), Box<dyn Error>> {
+        db::ping(self)
+    }
+}
+
+impl ReferralStore for Conn {
+    fn read_referral(&mut self, ref_key: &str) -> Result<String, Box<dyn Error>> {
+        db::read_referral(self, ref_key)
+    }
+
+    fn write_referral(&mut self, ref_data: &ReferralData) -> Result<(), Box<dyn Error>> {
+        db::write_referral(self, ref_data)
+    }
+
+    fn read_referrers(
+        &mut self,
+        addresses: &[String],
+    ) -> Result<Vec<ReferrerLink>, Box<dyn Error>> {
+        db::read_referrers(self, addresses)
+    }
+
+    fn read_downline(
+        &mut self,
+        keeper: &Keeper,
+        depth: u32,
+        offset: u64,
+        limit: u64,
+    ) -> Result<(u64, Vec<Link>), Box<dyn Error>> {
+        db::read_downline(self, keeper, depth, offset, limit)
+    }
+
+    fn read_referral_stats(
+        &mut self,
+        addresses: &[&str],
+        active_period: Duration,
+    ) -> Result<HashMap<String, (u64, u64)>, Box<dyn Error>> {
+        db::read_referral_stats(self, addresses, active_period)
+    }
+
+    fn read_referral_earnings(
+        &mut self,
+        addresses: &[&str],
+    ) -> Result<HashMap<String, String>, Box<dyn Error>> {
+        db::read_referral_earnings(self, addresses)
+    }
+}
+
+impl NonceStore for Conn {
+    fn read_user_objective_nonce(
+        &mut self,
+        chain_id: u64,
+        solver: &Address,
+    ) -> Result<Option<u64>, Box<dyn Error>> {
+        db::read_user_objective_nonce(self, chain_id, solver)
+    }
+
+    fn store_user_objective_nonce(
+        &mut self,
+        chain_id: u64,
+        solver: &Address,
+        next_nonce: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        db::store_user_objective_nonce(self, chain_id, solver, next_nonce)
+    }
+
+    fn read_released_nonces(
+        &mut self,
+        chain_id: u64,
+        solver: &Address,
+    ) -> Result<Vec<u64>, Box<dyn Error>> {
+        db::read_released_nonces(self, chain_id, solver)
+    }
+
+    fn store_released_nonce(
+        &mut self,
+        chain_id: u64,
+        solver: &Address,
+        nonce: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        db::store_released_nonce(self, chain_id, solver, nonce)
+    }
+
+    fn delete_released_nonce(
+        &mut self,
+        chain_id: u64,
+        solver: &Address,
+        nonce: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        db::delete_released_nonce(self, chain_id, solver, nonce)
+    }
+}
@@ -0,0 +1,187 @@
+use std::{convert::Infallible, sync::Arc, time::Duration};
+
+use axum::response::sse::{Event, KeepAlive, Sse};
+use ethers::types::{Address, H256, U256};
+use futures_util::{stream, Stream};
+use log::error;
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::{consensus::Consensus, time_signature::Chronicle};
+
+// The latest tick, subscribers that fall behind skip to it.
+pub type TickFeed = watch::Sender<Option<Arc<TickEvent>>>;
+
+// What happened to the tick on a chain.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChainTickStatus {
+    // The tick is being submitted.
+    Pending,
+    // The chronicles didn't change since the last submission.
+    Unchanged,
+    BackingOff,
+    // The BlockTime params hold the tick, its chronicles count in the next one.
+    Held,
+    Skipped,
+    // Rewards accrued to the backlog in the dry run mode.
+    Accrued,
+    Confirmed,
+    Failed,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChainTick {
+    pub chain_id: u64,
+    pub status: ChainTickStatus,
+    pub tx_hash: Option<H256>,
+    pub gas_used: Option<String>,
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Contributor {
+    pub time_keeper: Address,
+    // Unix time in nanoseconds.
+    pub epoch: String,
+}
+
+impl From<&Chronicle> for Contributor {
+    fn from(chronicle: &Chronicle) -> Self {
+        Contributor {
+            time_keeper: chronicle.time_keeper,
+            epoch: chronicle.epoch.to_string(),
+        }
+    }
+}
+
+// A computed tick with its contributors and the submission on every chain, times in nanoseconds.
+// Published when computed with pending chains, and again whenever a chain is settled.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TickEvent {
+    pub mean_time: String,
+    pub computed_at: String,
+    pub keepers: Vec<Contributor>,
+    pub rejected: Vec<Contributor>,
+    pub chains: Vec<ChainTick>,
+}
+
+impl TickEvent {
+    pub fn new(consensus: &Consensus, computed_at: Duration, chain_ids: &[u64]) -> TickEvent {
+        TickEvent {
+            mean_time: consensus.mean_time.to_string(),
+            computed_at: computed_at.as_nanos().to_string(),
+            keepers: consensus.accepted.iter().map(Contributor::from).collect(),
+            rejected: consensus.rejected.iter().map(Contributor::from).collect(),
+            chains: chain_ids
+                .iter()
+                .map(|chain_id| ChainTick {
+                    chain_id: *chain_id,
+                    status: ChainTickStatus::Pending,
+                    tx_hash: None,
+                    gas_used: None,
+                    reason: None,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn set_status(
+        &mut self,
+        chain_id: u64,
+        status: ChainTickStatus,
+        tx_hash: Option<H256>,
+        reason: Option<String>,
+    ) {
+        if let Some(chain) = self.chains.iter_mut().find(|el| el.chain_id == chain_id) {
+            chain.status = status;
+            chain.tx_hash = tx_hash;
+            chain.reason = reason;
+        }
+    }
+
+    pub fn set_confirmed(&mut self, chain_id: u64, tx_hash: H256, gas_used: Option<U256>) {
+        self.set_status(chain_id, ChainTickStatus::Confirmed, Some(tx_hash), None);
+        if let Some(chain) = self.chains.iter_mut().find(|el| el.chain_id == chain_id) {
+            chain.gas_used = gas_used.map(|el| el.to_string());
+        }
+    }
+}
+
+// Server-sent events with the latest tick on subscription and every tick after it.
+pub async fn handle_tick_feed(
+    feed: Arc<TickFeed>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let mut ticks = feed.subscribe();
+    ticks.mark_changed();
+    let events = stream::unfold(ticks, |mut ticks| async move {
+        loop {
+            // Ends the stream when the ticker is gone.
+            ticks.changed().await.ok()?;
+            let Some(tick) = ticks.borrow_and_update().clone() else {
+                continue;
+            };
+            match Event::default().event("tick").json_data(&*tick) {
+                Ok(event) => return Some((Ok(event), ticks)),
+                Err(err) => error!("Error encoding the tick: {}", err),
+            }
+        }
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, sync::Arc, time::Duration};
+
+    use ethers::types::{Address, Bytes, H256, U256};
+    use serde_json::json;
+    use tokio::sync::watch;
+
+    use crate::{consensus::Consensus, time_signature::Chronicle};
+
+    use super::{ChainTickStatus, TickEvent};
+
+    #[tokio::test]
+    async fn test_tick_event() -> Result<(), String> {
+        let chronicle = |epoch: u64, time_keeper: u64| {
+            Chronicle::new(
+                U256::from(epoch),
+                Address::from_low_u64_be(time_keeper),
+                Bytes::from_str("0x01").unwrap(),
+            )
+        };
+        let consensus = Consensus {
+            mean_time: U256::from(1_000),
+            accepted: vec![chronicle(999, 1), chronicle(1_001, 2)],
+            rejected: vec![chronicle(5_000, 3)],
+        };
+        let mut tick = TickEvent::new(&consensus, Duration::from_nanos(1_010), &[1, 2]);
+        assert!(tick
+            .chains
+            .iter()
+            .all(|el| el.status == ChainTickStatus::Pending));
+        tick.set_confirmed(1, H256::zero(), Some(U256::from(21_000)));
+        tick.set_status(
+            2,
+            ChainTickStatus::Skipped,
+            None,
+            Some("too few keepers".to_string()),
+        );
+
+        let (feed, mut ticks) = watch::channel(None);
+        feed.send_replace(Some(Arc::new(tick)));
+        let tick = ticks.borrow_and_update().clone().unwrap();
+        let value = serde_json::to_value(&*tick).map_err(|err| err.to_string())?;
+        assert_eq!(value["mean_time"], "1000");
+        assert_eq!(
+            value["rejected"],
+            json!([{"time_keeper": format!("{:#x}", Address::from_low_u64_be(3)), "epoch": "5000"}])
+        );
+        assert_eq!(value["chains"][0]["status"], "confirmed");
+        assert_eq!(value["chains"][0]["gas_used"], "21000");
+        assert_eq!(value["chains"][1]["status"], "skipped");
+        assert_eq!(value["chains"][1]["reason"], "too few keepers");
+        Ok(())
+    }
+}
@@ -1,15 +1,25 @@
-use std::{str::FromStr, sync::Arc};
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
-use axum::{http::StatusCode, Json};
-use ethers::types::{Address, Bytes, U256};
-use log::error;
-use mysql::PooledConn;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::types::{transaction::eip712::EIP712Domain, Address, Bytes, U256};
+use log::{error, warn};
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 
 use crate::{
-    db::{fix_address, is_address_whitelisted},
-    time_signature::Chronicle,
+    metrics::METRICS,
+    rejection::Rejection,
+    replay_guard::{remember_signature, Freshness, ReplayGuard},
+    store::{KeeperStore, StorePool},
+    time_signature::{Chronicle, SignatureScheme},
 };
 
 pub type TimeSigPool = Vec<Chronicle>;
@@ -19,60 +29,296 @@ pub struct TimeSigInput {
     epoch: String,
     time_keeper: String,
     signature: String,
+    #[serde(default)]
+    scheme: SignatureScheme,
+    // Required by the EIP-712 scheme.
+    #[serde(default)]
+    nonce: Option<String>,
+}
+
+// Reasons for rejecting a time signature, each one has its own status and error code.
+#[derive(Debug, PartialEq)]
+pub enum TimeSigRejection {
+    MalformedInput(String),
+    NotWhitelisted,
+    InvalidSignature,
+    EpochTooOld,
+    EpochInFuture,
+    Replayed,
+    StaleNonce,
+    Internal,
+}
+
+impl Rejection for TimeSigRejection {
+    fn status(&self) -> StatusCode {
+        match self {
+            TimeSigRejection::MalformedInput(_) => StatusCode::BAD_REQUEST,
+            TimeSigRejection::NotWhitelisted => StatusCode::FORBIDDEN,
+            TimeSigRejection::InvalidSignature => StatusCode::UNAUTHORIZED,
+            TimeSigRejection::EpochTooOld => StatusCode::UNPROCESSABLE_ENTITY,
+            TimeSigRejection::EpochInFuture => StatusCode::TOO_EARLY,
+            TimeSigRejection::Replayed => StatusCode::CONFLICT,
+            TimeSigRejection::StaleNonce => StatusCode::CONFLICT,
+            TimeSigRejection::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            TimeSigRejection::MalformedInput(_) => "malformed_input",
+            TimeSigRejection::NotWhitelisted => "not_whitelisted",
+            TimeSigRejection::InvalidSignature => "invalid_signature",
+            TimeSigRejection::EpochTooOld => "epoch_too_old",
+            TimeSigRejection::EpochInFuture => "epoch_in_future",
+            TimeSigRejection::Replayed => "signature_replayed",
+            TimeSigRejection::StaleNonce => "stale_nonce",
+            TimeSigRejection::Internal => "internal_error",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            TimeSigRejection::MalformedInput(err) => format!("Malformed input: {}", err),
+            TimeSigRejection::NotWhitelisted => "The time keeper isn't whitelisted".to_string(),
+            TimeSigRejection::InvalidSignature => "The signature doesn't match".to_string(),
+            TimeSigRejection::EpochTooOld => "The epoch is too far in the past".to_string(),
+            TimeSigRejection::EpochInFuture => "The epoch is too far in the future".to_string(),
+            TimeSigRejection::Replayed => "The signature was already submitted".to_string(),
+            TimeSigRejection::StaleNonce => {
+                "The nonce isn't greater than the last accepted one".to_string()
+            }
+            TimeSigRejection::Internal => "Internal server error".to_string(),
+        }
+    }
 }
 
-pub async fn handle_add_time_sig(
+impl IntoResponse for TimeSigRejection {
+    fn into_response(self) -> Response {
+        self.response()
+    }
+}
+
+impl TimeSigInput {
+    pub fn is_from(&self, time_keeper: &Address) -> bool {
+        Address::from_str(&self.time_keeper).ok().as_ref() == Some(time_keeper)
+    }
+}
+
+pub async fn handle_add_time_sig<S: StorePool>(
     Json(input): Json<TimeSigInput>,
     pool: Arc<Mutex<TimeSigPool>>,
-    db_conn: Arc<Mutex<PooledConn>>,
-) -> Result<(), StatusCode> {
-    let epoch = U256::from_str_radix(&input.epoch, 10);
-    let time_keeper = Address::from_str(&input.time_keeper);
-    let signature = Bytes::from_str(&input.signature);
-    if let Err(err) = epoch {
-        error!("Error extracting epoch: {}", err);
-        return Err(StatusCode::BAD_REQUEST);
+    db: S,
+    replay_guard: Arc<Mutex<ReplayGuard>>,
+    signing_domain: Arc<EIP712Domain>,
+) -> Result<(), TimeSigRejection> {
+    add_time_sig(input, &pool, &db, &replay_guard, &signing_domain).await
+}
+
+// Validates the chronicle and adds it to the pool, shared by the HTTP and WebSocket intakes.
+pub async fn add_time_sig<S: StorePool>(
+    input: TimeSigInput,
+    pool: &Mutex<TimeSigPool>,
+    db: &S,
+    replay_guard: &Mutex<ReplayGuard>,
+    signing_domain: &EIP712Domain,
+) -> Result<(), TimeSigRejection> {
+    let res = push_time_sig(input, pool, db, replay_guard, signing_domain).await;
+    match &res {
+        Ok(()) => METRICS.record_time_sig("accepted"),
+        Err(err) => METRICS.record_time_sig(err.code()),
     }
-    if let Err(err) = time_keeper {
+    res
+}
+
+async fn push_time_sig<S: StorePool>(
+    input: TimeSigInput,
+    pool: &Mutex<TimeSigPool>,
+    db: &S,
+    replay_guard: &Mutex<ReplayGuard>,
+    signing_domain: &EIP712Domain,
+) -> Result<(), TimeSigRejection> {
+    let epoch = U256::from_str_radix(&input.epoch, 10).map_err(|err| {
+        error!("Error extracting epoch: {}", err);
+        TimeSigRejection::MalformedInput(format!("epoch: {}", err))
+    })?;
+    let time_keeper = Address::from_str(&input.time_keeper).map_err(|err| {
         error!("Error extracting time keeper: {}", err);
-        return Err(StatusCode::BAD_REQUEST);
-    }
-    if let Err(err) = signature {
+        TimeSigRejection::MalformedInput(format!("time_keeper: {}", err))
+    })?;
+    let signature = Bytes::from_str(&input.signature).map_err(|err| {
         error!("Error extracting signature: {}", err);
-        return Err(StatusCode::BAD_REQUEST);
+        TimeSigRejection::MalformedInput(format!("signature: {}", err))
+    })?;
+    let nonce = match input.scheme {
+        SignatureScheme::Eip191 => None,
+        SignatureScheme::Eip712 => {
+            let nonce = input.nonce.as_deref().unwrap_or_default();
+            Some(nonce.parse::<u64>().map_err(|err| {
+                error!("Error extracting nonce: {}", err);
+                TimeSigRejection::MalformedInput(format!("nonce: {}", err))
+            })?)
+        }
+    };
+    let curr_ts = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO);
+    match replay_guard.lock().await.check_freshness(&epoch, curr_ts) {
+        Freshness::Fresh => {}
+        Freshness::TooOld => {
+            warn!("The epoch {} from {:#x} is too old", epoch, time_keeper);
+            return Err(TimeSigRejection::EpochTooOld);
+        }
+        Freshness::InFuture => {
+            warn!(
+                "The epoch {} from {:#x} is in the future",
+                epoch, time_keeper
+            );
+            return Err(TimeSigRejection::EpochInFuture);
+        }
     }
+    match db
+        .with_store(move |store| store.is_address_whitelisted(&time_keeper))
+        .await
     {
-        let mut db_conn = db_conn.lock().await;
-        match is_address_whitelisted(db_conn.as_mut(), &time_keeper.unwrap()).await {
-            Ok(res) => {
-                if !res {
-                    error!("The address {} isn't whitelisted", time_keeper.unwrap());
-                    return Err(StatusCode::UNAUTHORIZED);
-                }
-            }
-            Err(err) => {
-                error!("Error checking time keepers whitelist: {}", err);
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        Ok(res) => {
+            if !res {
+                error!("The address {} isn't whitelisted", time_keeper);
+                return Err(TimeSigRejection::NotWhitelisted);
             }
         }
+        Err(err) => {
+            error!("Error checking time keepers whitelist: {}", err);
+            return Err(TimeSigRejection::Internal);
+        }
+    }
+    let time_signature = Chronicle::new(epoch, time_keeper, signature);
+    let is_valid = match nonce {
+        None => time_signature.verify(),
+        Some(nonce) => time_signature.verify_typed(signing_domain, nonce.into()),
+    };
+    if !is_valid {
+        return Err(TimeSigRejection::InvalidSignature);
     }
-    let time_signature = Chronicle::new(epoch.unwrap(), time_keeper.unwrap(), signature.unwrap());
-    if time_signature.verify() {
-        // Update the address in the database, fix the display address error.
-        let mut db_conn = db_conn.lock().await;
-        if let Err(err) = fix_address(db_conn.as_mut(), &time_signature.time_keeper).await {
-            error!("Error fixing address: {}", err);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    match remember_signature(
+        replay_guard,
+        db,
+        &time_signature.signature,
+        &time_signature.epoch,
+    )
+    .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            warn!("Replayed signature from {:#x}", time_keeper);
+            return Err(TimeSigRejection::Replayed);
+        }
+        Err(err) => {
+            error!("Error storing seen signature: {}", err);
+            return Err(TimeSigRejection::Internal);
         }
-        let mut time_sig_pool = pool.lock().await;
-        time_sig_pool.push(time_signature);
-        return Ok(());
-    } else {
-        return Err(StatusCode::UNAUTHORIZED);
     }
+    // Typed chronicles of a time keeper come with increasing nonces.
+    if let Some(nonce) = nonce {
+        match db
+            .with_store(move |store| store.advance_chronicle_nonce(&time_keeper, nonce))
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!("Stale nonce {} from {:#x}", nonce, time_keeper);
+                return Err(TimeSigRejection::StaleNonce);
+            }
+            Err(err) => {
+                error!("Error advancing chronicle nonce: {}", err);
+                return Err(TimeSigRejection::Internal);
+            }
+        }
+    }
+    let mut time_sig_pool = pool.lock().await;
+    time_sig_pool.push(time_signature);
+    Ok(())
 }
 
 pub async fn handle_list_time_sigs(pool: Arc<Mutex<TimeSigPool>>) -> Json<TimeSigPool> {
     let pool = pool.lock().await;
     Json(pool.to_vec())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        str::FromStr,
+        time::{Duration, SystemTime},
+    };
+
+    use ethers::{
+        signers::{LocalWallet, Signer},
+        types::{Address, Bytes, U256},
+    };
+    use tokio::sync::Mutex;
+
+    use crate::{
+        memory_store::MemoryPool,
+        replay_guard::ReplayGuard,
+        time_signature::{chronicle_domain, SignatureScheme, TypedChronicle},
+    };
+
+    use super::{add_time_sig, TimeSigInput, TimeSigRejection};
+
+    #[tokio::test]
+    async fn test_chronicle_nonces() -> Result<(), String> {
+        let wallet = LocalWallet::from_str(
+            "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )
+        .map_err(|err| err.to_string())?;
+        let domain = chronicle_domain(
+            21363,
+            Address::from_str("0xdD1B4D9337D0a8Ef2F133a39cC93EF85261b4A80").unwrap(),
+            &Bytes::from_str("0x01").unwrap(),
+        );
+        let db = MemoryPool::default();
+        db.0.lock()
+            .unwrap()
+            .insert_keeper(&wallet.address(), None, None);
+        let replay_guard = Mutex::new(ReplayGuard::new(Duration::from_secs(60), 16));
+        let pool = Mutex::new(Vec::new());
+        let curr_ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO);
+
+        let mut results = Vec::new();
+        for (idx, nonce) in [5u64, 5, 4, 6].into_iter().enumerate() {
+            // Every chronicle has its own epoch, so that the signatures differ.
+            let epoch = U256::from(curr_ts.as_nanos()) + idx;
+            let typed = TypedChronicle {
+                domain: domain.clone(),
+                epoch,
+                time_keeper: wallet.address(),
+                nonce: nonce.into(),
+            };
+            let signature = wallet
+                .sign_typed_data(&typed)
+                .await
+                .map_err(|err| err.to_string())?;
+            let input = TimeSigInput {
+                epoch: epoch.to_string(),
+                time_keeper: format!("{:#x}", wallet.address()),
+                signature: signature.to_string(),
+                scheme: SignatureScheme::Eip712,
+                nonce: Some(nonce.to_string()),
+            };
+            results.push(add_time_sig(input, &pool, &db, &replay_guard, &domain).await);
+        }
+        assert_eq!(
+            results,
+            [
+                Ok(()),
+                Err(TimeSigRejection::StaleNonce),
+                Err(TimeSigRejection::StaleNonce),
+                Ok(()),
+            ]
+        );
+        assert_eq!(pool.lock().await.len(), 2);
+        Ok(())
+    }
+}
@@ -1,9 +1,16 @@
+use std::convert::Infallible;
+
 use ethers::{
-    abi::Token,
+    abi::{encode, Token},
     prelude::abigen,
-    types::{Address, Bytes, Signature, U256},
+    types::{
+        transaction::eip712::{EIP712Domain, Eip712},
+        Address, Bytes, Signature, H256, U256,
+    },
+    utils::keccak256,
 };
 use log::error;
+use serde::{Deserialize, Serialize};
 
 abigen!(
   BlockTime,
@@ -11,6 +18,66 @@ abigen!(
   derives(serde::Deserialize, serde::Serialize);
 );
 
+pub const CHRONICLE_DOMAIN_NAME: &str = "BlockClock";
+pub const CHRONICLE_DOMAIN_VERSION: &str = "1";
+const CHRONICLE_TYPE: &str = "Chronicle(uint256 epoch,address timeKeeper,uint256 nonce)";
+
+// The way the time keeper signed the chronicle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureScheme {
+    // EIP-191 personal message of the decimal epoch, to be retired after the migration.
+    #[default]
+    Eip191,
+    // EIP-712 typed `Chronicle` struct.
+    Eip712,
+}
+
+// Builds the EIP-712 domain chronicles are signed in, the app id goes into the salt.
+pub fn chronicle_domain(
+    chain_id: u64,
+    block_time_address: Address,
+    app_id: &Bytes,
+) -> EIP712Domain {
+    EIP712Domain {
+        name: Some(CHRONICLE_DOMAIN_NAME.to_string()),
+        version: Some(CHRONICLE_DOMAIN_VERSION.to_string()),
+        chain_id: Some(chain_id.into()),
+        verifying_contract: Some(block_time_address),
+        salt: Some(keccak256(app_id)),
+    }
+}
+
+// The typed data a time keeper signs with the EIP-712 scheme.
+#[derive(Clone, Debug)]
+pub struct TypedChronicle {
+    pub domain: EIP712Domain,
+    pub epoch: U256,
+    pub time_keeper: Address,
+    pub nonce: U256,
+}
+
+impl Eip712 for TypedChronicle {
+    type Error = Infallible;
+
+    fn domain(&self) -> Result<EIP712Domain, Self::Error> {
+        Ok(self.domain.clone())
+    }
+
+    fn type_hash() -> Result<[u8; 32], Self::Error> {
+        Ok(keccak256(CHRONICLE_TYPE))
+    }
+
+    fn struct_hash(&self) -> Result<[u8; 32], Self::Error> {
+        Ok(keccak256(encode(&[
+            Token::FixedBytes(Self::type_hash()?.to_vec()),
+            Token::Uint(self.epoch),
+            Token::Address(self.time_keeper),
+            Token::Uint(self.nonce),
+        ])))
+    }
+}
+
 impl Chronicle {
     pub fn new(epoch: U256, time_keeper: Address, signature: Bytes) -> Chronicle {
         Chronicle {
@@ -20,19 +87,43 @@ impl Chronicle {
         }
     }
 
+    // Verifies the legacy EIP-191 signature over the decimal epoch.
     pub fn verify(&self) -> bool {
-        // TODO: Make sure what message is signed.
         match Signature::try_from(self.signature.to_vec().as_slice()) {
             Ok(signature) => {
                 if let Err(err) = signature.verify(self.epoch.to_string(), self.time_keeper) {
                     error!("Error signature verification: {}", err);
                     return false;
                 }
-                return true;
+                true
+            }
+            Err(err) => {
+                error!("Error parsing signature: {}", err);
+                false
+            }
+        }
+    }
+
+    // Verifies the EIP-712 signature over the typed chronicle in the given domain.
+    pub fn verify_typed(&self, domain: &EIP712Domain, nonce: U256) -> bool {
+        let typed = TypedChronicle {
+            domain: domain.clone(),
+            epoch: self.epoch,
+            time_keeper: self.time_keeper,
+            nonce,
+        };
+        let hash = typed.encode_eip712().unwrap_or_else(|never| match never {});
+        match Signature::try_from(self.signature.to_vec().as_slice()) {
+            Ok(signature) => {
+                if let Err(err) = signature.verify(H256::from(hash), self.time_keeper) {
+                    error!("Error typed signature verification: {}", err);
+                    return false;
+                }
+                true
             }
             Err(err) => {
                 error!("Error parsing signature: {}", err);
-                return false;
+                false
             }
         }
     }
@@ -48,7 +139,7 @@ impl Chronicle {
 
 #[cfg(test)]
 mod tests {
-    use super::Chronicle;
+    use super::{chronicle_domain, Chronicle};
     use ethers::types::{Address, Bytes, U256};
     use std::str::FromStr;
 
@@ -63,4 +154,34 @@ mod tests {
         assert!(time_sig.verify());
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_verify_typed() -> Result<(), String> {
+        let time_keeper = Address::from_str("0xfcad0b19bb29d4674531d6f115237e16afce377c").unwrap();
+        let domain = chronicle_domain(
+            21363,
+            Address::from_str("0xdD1B4D9337D0a8Ef2F133a39cC93EF85261b4A80").unwrap(),
+            &Bytes::from_str("0x0000000000000000000000000000000000000000000000000000000000000001")
+                .unwrap(),
+        );
+        let time_sig = Chronicle::new(
+            U256::from_dec_str("1734554316445000000").unwrap(),
+            time_keeper,
+            Bytes::from_str("0xde690f094d3f66a34f1765f55e915bd883f9651d6c97bf4d8a7e99d846c61fc06bc52c251e02a0ac06e38e2a924798762f8f9728d2513e7ddf6fb959e02472161b").unwrap()
+        );
+        assert!(time_sig.verify_typed(&domain, U256::from(7)));
+        // Wrong nonce.
+        assert!(!time_sig.verify_typed(&domain, U256::from(8)));
+        // Wrong chain.
+        let other_domain = chronicle_domain(
+            84532,
+            Address::from_str("0xdD1B4D9337D0a8Ef2F133a39cC93EF85261b4A80").unwrap(),
+            &Bytes::from_str("0x0000000000000000000000000000000000000000000000000000000000000001")
+                .unwrap(),
+        );
+        assert!(!time_sig.verify_typed(&other_domain, U256::from(7)));
+        // The typed signature isn't a valid legacy one.
+        assert!(!time_sig.verify());
+        Ok(())
+    }
 }
@@ -4,27 +4,29 @@ use std::{
 };
 
 use ethers::providers::Middleware;
-use mysql::PooledConn;
 use tokio::{spawn, sync::Mutex, time::interval};
 
-use crate::meantime::MeanTime;
+use crate::{db_pool::DbPool, health::TickerHeartbeat, meantime::MeanTime};
 
 pub struct TimeTick<M: Middleware> {
     period: Duration,
     mean_time: Arc<Mutex<MeanTime<M>>>,
-    conn: Arc<Mutex<PooledConn>>,
+    db: DbPool,
+    heartbeat: TickerHeartbeat,
 }
 
 impl<M: Middleware + 'static> TimeTick<M> {
     pub fn new(
         period: Duration,
         mean_time: Arc<Mutex<MeanTime<M>>>,
-        conn: Arc<Mutex<PooledConn>>,
+        db: DbPool,
+        heartbeat: TickerHeartbeat,
     ) -> TimeTick<M> {
         TimeTick {
             period,
             mean_time,
-            conn,
+            db,
+            heartbeat,
         }
     }
 
@@ -33,10 +35,13 @@ impl<M: Middleware + 'static> TimeTick<M> {
         loop {
             delay.tick().await;
             let mean_time = self.mean_time.clone();
-            let conn = self.conn.clone();
+            let db = self.db.clone();
+            let heartbeat = self.heartbeat.clone();
             spawn(async move {
                 if let Ok(mut mean_time) = mean_time.try_lock() {
-                    mean_time.handle_time_tick(SystemTime::now(), conn).await;
+                    mean_time.handle_time_tick(SystemTime::now(), db).await;
+                    // Only completed ticks count, a hung one stops the beats.
+                    heartbeat.beat();
                 }
             });
         }
@@ -0,0 +1,411 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ethers::{
+    contract::ContractError,
+    providers::Middleware,
+    types::{Address, BlockNumber, Eip1559TransactionRequest, TransactionReceipt, H256, U256, U64},
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Mutex, time::sleep};
+
+use crate::{
+    db::{delete_pending_tx, read_pending_tx, store_pending_tx},
+    db_pool::{DbError, DbPool},
+    metrics::METRICS,
+    send_failure::SendFailure,
+};
+
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// EIP-1559 fee strategy of the solver transactions.
+#[derive(Clone, Copy, Debug)]
+pub struct TxConfig {
+    // Time without a receipt after which the transaction is replaced with bumped fees.
+    pub stuck_timeout: Duration,
+    // Time without a receipt after which the send gives up, the transaction stays persisted.
+    pub max_tracking_time: Duration,
+    pub fee_bump_percent: u64,
+    pub max_fee_per_gas: U256,
+}
+
+impl TxConfig {
+    // Caps the estimated fees, the priority fee never exceeds the max fee.
+    fn apply_fees(&self, tx: &mut Eip1559TransactionRequest, max_fee: U256, priority_fee: U256) {
+        let max_fee = max_fee.min(self.max_fee_per_gas);
+        tx.max_fee_per_gas = Some(max_fee);
+        tx.max_priority_fee_per_gas = Some(priority_fee.min(max_fee));
+    }
+
+    // Bumps both fees by the bump percent, returns false if the max fee is already at the cap.
+    fn bump(&self, tx: &mut Eip1559TransactionRequest) -> bool {
+        let max_fee = tx.max_fee_per_gas.unwrap_or_default();
+        if max_fee >= self.max_fee_per_gas {
+            return false;
+        }
+        let bumped = |fee: U256| fee * (100 + self.fee_bump_percent) / 100 + 1;
+        let priority_fee = tx.max_priority_fee_per_gas.unwrap_or_default();
+        self.apply_fees(tx, bumped(max_fee), bumped(priority_fee));
+        true
+    }
+}
+
+// The tick a transaction settles: the rewards of its chronicles and its user objective nonce.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TxTick {
+    pub chain_id: u64,
+    pub chronicles_md5: String,
+    pub nonce: u64,
+}
+
+// A transaction with the hashes of all its fee replacements, persisted until one of them is mined.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct PendingTx {
+    tx: Eip1559TransactionRequest,
+    tx_hashes: Vec<H256>,
+    tick: TxTick,
+}
+
+// Outcome of a transaction an earlier send left without a receipt, its tick is settled by the caller.
+#[derive(Debug)]
+pub struct Resumed {
+    pub tick: TxTick,
+    pub res: Result<TransactionReceipt, SendFailure>,
+}
+
+// Sends the solver transactions of one chain, one at a time.
+pub struct TxManager<M: Middleware> {
+    chain_id: u64,
+    sender: Address,
+    client: Arc<M>,
+    config: TxConfig,
+    // Transaction left pending by the previous run, the lock also serializes the sends.
+    resumed: Mutex<Option<PendingTx>>,
+}
+
+impl<M: Middleware> TxManager<M> {
+    pub fn new(chain_id: u64, sender: Address, client: Arc<M>, config: TxConfig) -> TxManager<M> {
+        TxManager {
+            chain_id,
+            sender,
+            client,
+            config,
+            resumed: Mutex::new(None),
+        }
+    }
+
+    // Picks up the transaction the previous run didn't see mined.
+    pub async fn load(
+        db: &DbPool,
+        chain_id: u64,
+        sender: Address,
+        client: Arc<M>,
+        config: TxConfig,
+    ) -> Result<TxManager<M>, DbError> {
+        let tx_manager = TxManager::new(chain_id, sender, client, config);
+        if let Some(tx) = db.run(move |conn| read_pending_tx(conn, chain_id)).await? {
+            *tx_manager.resumed.lock().await = Some(serde_json::from_str(&tx)?);
+        }
+        Ok(tx_manager)
+    }
+
+    // Sends the transaction and waits for its receipt, replacing it while it's stuck.
+    // A transaction left pending by an earlier send is finished first and returned with its outcome.
+    pub async fn send(
+        &self,
+        tx: Eip1559TransactionRequest,
+        tick: TxTick,
+        db: DbPool,
+    ) -> (Option<Resumed>, Result<TransactionReceipt, SendFailure>) {
+        let mut resumed = self.resumed.lock().await;
+        // Its nonce must not be sent twice.
+        let Some(pending_tx) = resumed.take() else {
+            return (None, self.send_new(tx, tick, &db, &mut resumed).await);
+        };
+        info!(
+            "Resuming the transaction {:?} on the chain {}",
+            pending_tx.tx_hashes, self.chain_id
+        );
+        let resumed_tick = pending_tx.tick.clone();
+        let resumed_res = self.track(pending_tx, &db, &mut resumed).await;
+        let res = match &resumed_res {
+            // A transaction behind it would be stuck too, so the new one isn't sent.
+            Err(SendFailure::Stuck(tx_hash)) => Err(SendFailure::Blocked(*tx_hash)),
+            _ => self.send_new(tx, tick, &db, &mut resumed).await,
+        };
+        let resumed = Resumed {
+            tick: resumed_tick,
+            res: resumed_res,
+        };
+        (Some(resumed), res)
+    }
+
+    async fn send_new(
+        &self,
+        mut tx: Eip1559TransactionRequest,
+        tick: TxTick,
+        db: &DbPool,
+        resumed: &mut Option<PendingTx>,
+    ) -> Result<TransactionReceipt, SendFailure> {
+        let nonce = self
+            .client
+            .get_transaction_count(self.sender, Some(BlockNumber::Pending.into()))
+            .await
+            .map_err(|err| SendFailure::Provider(err.to_string()))?;
+        let (max_fee, priority_fee) = self
+            .client
+            .estimate_eip1559_fees(None)
+            .await
+            .map_err(|err| SendFailure::Provider(err.to_string()))?;
+        tx.from = Some(self.sender);
+        tx.nonce = Some(nonce);
+        self.config.apply_fees(&mut tx, max_fee, priority_fee);
+        let pending_tx = PendingTx {
+            tx,
+            tx_hashes: Vec::new(),
+            tick,
+        };
+        // Persisted before sending, so that a restart resumes it instead of sending another one.
+        self.persist(db, &pending_tx)
+            .await
+            .map_err(|err| SendFailure::Storage(err.to_string()))?;
+        self.track(pending_tx, db, resumed).await
+    }
+
+    // A transaction without a receipt within the max tracking time goes back to `resumed`.
+    async fn track(
+        &self,
+        mut pending_tx: PendingTx,
+        db: &DbPool,
+        resumed: &mut Option<PendingTx>,
+    ) -> Result<TransactionReceipt, SendFailure> {
+        if pending_tx.tx_hashes.is_empty() {
+            if let Err(failure) = self.broadcast(&mut pending_tx, db).await {
+                self.forget(db).await;
+                return Err(failure);
+            }
+            METRICS.record_tx(self.chain_id, "sent");
+        }
+        let nonce = pending_tx.tx.nonce.unwrap_or_default();
+        let mut sent_at = Instant::now();
+        let tracked_at = Instant::now();
+        loop {
+            if tracked_at.elapsed() >= self.config.max_tracking_time {
+                warn!(
+                    "Transaction with the nonce {} on the chain {} has no receipt after {:?}",
+                    nonce, self.chain_id, self.config.max_tracking_time
+                );
+                let tx_hash = pending_tx.tx_hashes.last().copied().unwrap_or_default();
+                *resumed = Some(pending_tx);
+                return Err(SendFailure::Stuck(tx_hash));
+            }
+            sleep(RECEIPT_POLL_INTERVAL).await;
+            // Read the nonce before the receipts, a replacement mined in between is found next time.
+            let mined_nonce = self
+                .client
+                .get_transaction_count(self.sender, Some(BlockNumber::Latest.into()))
+                .await;
+            for tx_hash in pending_tx.tx_hashes.iter() {
+                match self.client.get_transaction_receipt(*tx_hash).await {
+                    Ok(Some(receipt)) => {
+                        self.forget(db).await;
+                        self.record_receipt(&receipt).await;
+                        if receipt.status == Some(U64::from(1)) {
+                            return Ok(receipt);
+                        }
+                        return Err(SendFailure::TxReverted(*tx_hash));
+                    }
+                    Ok(None) => {}
+                    Err(err) => warn!("Error getting the receipt of {:#x}: {}", tx_hash, err),
+                }
+            }
+            match mined_nonce {
+                Ok(mined_nonce) if mined_nonce > nonce => {
+                    // The nonce was used by a transaction this manager doesn't know.
+                    self.forget(db).await;
+                    return Err(SendFailure::Dropped(
+                        pending_tx.tx_hashes.last().copied().unwrap_or_default(),
+                    ));
+                }
+                Ok(_) => {}
+                Err(err) => warn!(
+                    "Error getting the nonce on the chain {}: {}",
+                    self.chain_id, err
+                ),
+            }
+            if sent_at.elapsed() < self.config.stuck_timeout {
+                continue;
+            }
+            sent_at = Instant::now();
+            let mut replacement = pending_tx.clone();
+            if !self.config.bump(&mut replacement.tx) {
+                warn!(
+                    "Transaction with the nonce {} on the chain {} is stuck at the max fee",
+                    nonce, self.chain_id
+                );
+                continue;
+            }
+            info!(
+                "Replacing the stuck transaction with the nonce {} on the chain {}",
+                nonce, self.chain_id
+            );
+            match self.broadcast(&mut replacement, db).await {
+                Ok(()) => pending_tx = replacement,
+                // The previous transaction is still pending.
+                Err(failure) => warn!(
+                    "Error replacing the transaction on the chain {}: {}",
+                    self.chain_id, failure
+                ),
+            }
+        }
+    }
+
+    // Sends the transaction, bumping the fees while the node finds them too low.
+    async fn broadcast(&self, pending_tx: &mut PendingTx, db: &DbPool) -> Result<(), SendFailure> {
+        loop {
+            match self
+                .client
+                .send_transaction(pending_tx.tx.clone(), None)
+                .await
+            {
+                Ok(sent) => {
+                    let tx_hash = sent.tx_hash();
+                    info!(
+                        "Transaction is sent on the chain {}, txhash: {:#x}",
+                        self.chain_id, tx_hash
+                    );
+                    pending_tx.tx_hashes.push(tx_hash);
+                    if let Err(err) = self.persist(db, pending_tx).await {
+                        error!("Error storing the pending transaction: {}", err);
+                    }
+                    return Ok(());
+                }
+                Err(err) if is_underpriced(&err.to_string()) => {
+                    if !self.config.bump(&mut pending_tx.tx) {
+                        return Err(SendFailure::Provider(err.to_string()));
+                    }
+                    warn!(
+                        "Transaction on the chain {} is underpriced, bumping the fees",
+                        self.chain_id
+                    );
+                }
+                Err(err) => {
+                    return Err(SendFailure::from_contract_error(
+                        ContractError::<M>::from_middleware_error(err),
+                    ))
+                }
+            }
+        }
+    }
+
+    // Reverted transactions burn gas too, the balance only moves with the mined transactions.
+    async fn record_receipt(&self, receipt: &TransactionReceipt) {
+        let status = if receipt.status == Some(U64::from(1)) {
+            "confirmed"
+        } else {
+            "reverted"
+        };
+        METRICS.record_tx(self.chain_id, status);
+        if let Some(gas_used) = receipt.gas_used {
+            METRICS.record_gas_used(self.chain_id, gas_used);
+        }
+        self.refresh_balance().await;
+    }
+
+    pub async fn refresh_balance(&self) {
+        match self.client.get_balance(self.sender, None).await {
+            Ok(balance) => METRICS.set_solver_balance(self.chain_id, balance),
+            Err(err) => warn!(
+                "Error getting the solver balance on the chain {}: {}",
+                self.chain_id, err
+            ),
+        }
+    }
+
+    async fn persist(&self, db: &DbPool, pending_tx: &PendingTx) -> Result<(), DbError> {
+        let tx = serde_json::to_string(pending_tx)?;
+        let chain_id = self.chain_id;
+        db.run(move |conn| store_pending_tx(conn, chain_id, &tx))
+            .await
+    }
+
+    async fn forget(&self, db: &DbPool) {
+        let chain_id = self.chain_id;
+        if let Err(err) = db.run(move |conn| delete_pending_tx(conn, chain_id)).await {
+            error!("Error deleting the pending transaction: {}", err);
+        }
+    }
+}
+
+fn is_underpriced(err: &str) -> bool {
+    let err = err.to_lowercase();
+    err.contains("underpriced")
+        || err.contains("fee too low")
+        || err.contains("less than block base fee")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ethers::{
+        types::{Address, Eip1559TransactionRequest, H256, U256},
+        utils::parse_units,
+    };
+
+    use super::{is_underpriced, PendingTx, TxConfig, TxTick};
+
+    #[tokio::test]
+    async fn test_bump_fees() -> Result<(), String> {
+        let gwei = |value: u64| -> U256 { parse_units(value, "gwei").unwrap().into() };
+        let config = TxConfig {
+            stuck_timeout: Duration::from_secs(60),
+            max_tracking_time: Duration::from_secs(600),
+            fee_bump_percent: 20,
+            max_fee_per_gas: gwei(14),
+        };
+        let mut tx = Eip1559TransactionRequest::new();
+        config.apply_fees(&mut tx, gwei(10), gwei(20));
+        assert_eq!(tx.max_fee_per_gas, Some(gwei(10)));
+        assert_eq!(tx.max_priority_fee_per_gas, Some(gwei(10)));
+
+        config.apply_fees(&mut tx, gwei(10), gwei(1));
+        assert!(config.bump(&mut tx));
+        assert_eq!(tx.max_fee_per_gas, Some(gwei(12) + 1));
+        assert_eq!(tx.max_priority_fee_per_gas, Some(gwei(1) * 6 / 5 + 1));
+        assert!(config.bump(&mut tx));
+        assert_eq!(tx.max_fee_per_gas, Some(gwei(14)));
+        assert!(!config.bump(&mut tx));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pending_tx_round_trip() -> Result<(), String> {
+        let pending_tx = PendingTx {
+            tx: Eip1559TransactionRequest::new()
+                .from(Address::zero())
+                .nonce(7)
+                .gas(100000)
+                .max_fee_per_gas(10)
+                .max_priority_fee_per_gas(1),
+            tx_hashes: vec![H256::zero(), H256::repeat_byte(1)],
+            tick: TxTick {
+                chain_id: 21363,
+                chronicles_md5: "d41d8cd98f00b204e9800998ecf8427e".to_string(),
+                nonce: 3,
+            },
+        };
+        let json = serde_json::to_string(&pending_tx).map_err(|err| err.to_string())?;
+        assert_eq!(
+            serde_json::from_str::<PendingTx>(&json).map_err(|err| err.to_string())?,
+            pending_tx
+        );
+        assert!(is_underpriced("replacement transaction underpriced"));
+        assert!(is_underpriced("max fee per gas less than block base fee"));
+        assert!(!is_underpriced("nonce too low"));
+        Ok(())
+    }
+}
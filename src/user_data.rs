@@ -24,3 +24,10 @@ pub struct ReferredFromData {
     pub time_keeper: Address,
     pub referred_from: String,
 }
+
+// Opens a WebSocket session of the time keeper, the session is any string chosen by the client.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SessionData {
+    pub time_keeper: Address,
+    pub session: String,
+}